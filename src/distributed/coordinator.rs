@@ -0,0 +1,210 @@
+use std::collections::VecDeque;
+use std::io::{self, BufReader, BufWriter};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use indicatif::ProgressBar;
+
+use crate::raytracer::film::{Film, PixelStats};
+use crate::raytracer::tiles::Tile;
+use crate::raytracer::Scene;
+
+use super::protocol::{Message, invalid_data};
+
+// how long a worker with nothing to do waits before checking whether a failed worker gave back its tile
+const IDLE_WAIT: Duration = Duration::from_millis(10);
+
+// tiles waiting for a worker, and the number of tiles not rendered yet
+struct TileQueue {
+    pending: Mutex<VecDeque<Tile>>,
+    remaining: AtomicUsize,
+}
+
+// renders the film on the workers, one tile at a time per worker; the tile of a worker that fails
+// or does not answer within tile_timeout goes back to the queue for the others, and rendering fails
+// only once every worker failed
+pub fn render_distributed(scene: &Scene, film: &mut Film, seed: u64, workers: &[String], tile_timeout: Duration) -> Result<(), String> {
+    let scene_yaml = serde_yaml::to_string(scene).map_err(|err| format!("cannot serialize the scene: {}", err))?;
+    let job = Message::Job { seed, frame_size: film.frame_size, scene: scene_yaml };
+
+    // workers get tiles in frame pixels, the film may be a region of the frame
+    let tiles: VecDeque<Tile> = scene.tiles.tiles(film.width, film.height)
+        .into_iter()
+        .map(|tile| Tile { x: film.origin.0 + tile.x, y: film.origin.1 + tile.y, ..tile })
+        .collect();
+    let tile_count = tiles.len();
+    let queue = Arc::new(TileQueue { pending: Mutex::new(tiles), remaining: AtomicUsize::new(tile_count) });
+
+    let (sender, receiver) = mpsc::channel();
+    let handles: Vec<_> = workers.iter()
+        .map(|address| {
+            let (address, job, queue, sender) = (address.clone(), job.clone(), queue.clone(), sender.clone());
+            thread::spawn(move || {
+                run_worker(&address, &job, &queue, &sender, tile_timeout).map_err(|err| format!("worker {}: {}", address, err))
+            })
+        })
+        .collect();
+    // the channel closes once every worker thread is gone
+    drop(sender);
+
+    let progress = ProgressBar::new(tile_count as u64);
+    for _ in 0..tile_count {
+        let (tile, pixels): (Tile, Vec<PixelStats>) = match receiver.recv() {
+            Ok(result) => result,
+            Err(_) => {
+                let errors: Vec<String> = handles.into_iter().filter_map(|handle| handle.join().ok()?.err()).collect();
+                return Err(format!("every worker failed with tiles left to render: {}", errors.join(", ")));
+            }
+        };
+        for (index, pixel) in pixels.into_iter().enumerate() {
+            let x = tile.x - film.origin.0 + index as u32 % tile.width;
+            let y = tile.y - film.origin.1 + index as u32 / tile.width;
+            film.pixels[(x + y * film.width) as usize] = pixel;
+        }
+        progress.inc(1);
+    }
+    progress.finish();
+
+    for handle in handles {
+        if let Ok(Err(err)) = handle.join() {
+            eprintln!("{}", err);
+        }
+    }
+    Ok(())
+}
+
+fn run_worker(address: &str, job: &Message, queue: &TileQueue, sender: &mpsc::Sender<(Tile, Vec<PixelStats>)>, tile_timeout: Duration)
+    -> io::Result<()> {
+    let stream = TcpStream::connect(address)?;
+    // a hung worker is given up like a disconnected one instead of blocking its thread forever
+    stream.set_read_timeout(Some(tile_timeout))?;
+    stream.set_write_timeout(Some(tile_timeout))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    job.write_to(&mut writer)?;
+
+    while queue.remaining.load(Ordering::SeqCst) > 0 {
+        let tile = match queue.pending.lock().unwrap().pop_front() {
+            Some(tile) => tile,
+            None => {
+                thread::sleep(IDLE_WAIT);
+                continue;
+            }
+        };
+
+        Message::Tile(tile).write_to(&mut writer)
+            .and_then(|_| Message::read_from(&mut reader))
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => io::Error::new(io::ErrorKind::TimedOut, "no answer in time"),
+                _ => err,
+            })
+            .and_then(|message| match message {
+                Some(Message::TileResult { tile: result_tile, pixels }) if result_tile == tile => Ok(pixels),
+                Some(Message::Error(err)) => Err(io::Error::other(err)),
+                Some(message) => Err(invalid_data(format!("expected the result of {:?}, got {:?}", tile, message))),
+                None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "disconnected")),
+            })
+            .map(|pixels| {
+                queue.remaining.fetch_sub(1, Ordering::SeqCst);
+                // the receiver only goes away once every tile is received
+                sender.send((tile, pixels)).ok();
+            })
+            .inspect_err(|_| queue.pending.lock().unwrap().push_back(tile))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use crate::distributed::worker;
+
+    const SCENE: &str = "
+camera:
+  cam_orient: [0.0, 0.0, 1.0]
+  cam_pos: [0.0, 0.0, 0.0]
+  up_vec: [0.0, 1.0, 0.0]
+shapes:
+  - type: Sphere
+    centre: [0.0, 0.0, 5.0]
+    r: 1.0
+    color: [0.0, 0.0, 0.0]
+    emissive_color: [0.5, 0.25, 0.5]
+    albedo: 0.0
+    reflectivity: 0.0
+distant_lights: []
+tiles:
+  size: 2
+  order: Scanline
+";
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn start_worker() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || worker::serve(listener));
+        address
+    }
+
+    // accepts one coordinator and never answers it
+    fn start_hung_worker() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_secs(60));
+            drop(stream);
+        });
+        address
+    }
+
+    // accepts one coordinator and hangs up after the start of its job
+    fn start_failing_worker() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut first_bytes = [0_u8; 8];
+            stream.read_exact(&mut first_bytes).ok();
+        });
+        address
+    }
+
+    #[test]
+    fn test_workers_render_like_a_single_process() {
+        let scene: Scene = serde_yaml::from_str(SCENE).unwrap();
+        let mut film = Film::new(5, 3);
+        render_distributed(&scene, &mut film, 3, &[start_worker(), start_worker()], TIMEOUT).unwrap();
+        assert_eq!(film.pixels, scene.render_scene(5, 3, 3).pixels);
+    }
+
+    #[test]
+    fn test_tiles_of_failed_workers_are_reassigned() {
+        let scene: Scene = serde_yaml::from_str(SCENE).unwrap();
+        let mut film = Film::region(5, 3, 1, 1, 3, 2).unwrap();
+        render_distributed(&scene, &mut film, 3, &[start_failing_worker(), start_worker()], TIMEOUT).unwrap();
+        let full = scene.render_scene(5, 3, 3);
+        assert_eq!(film.pixels[0], full.pixels[1 + 5]);
+        assert_eq!(film.pixels[5], full.pixels[3 + 2 * 5]);
+    }
+
+    #[test]
+    fn test_tiles_of_hung_workers_are_reassigned() {
+        let scene: Scene = serde_yaml::from_str(SCENE).unwrap();
+        let mut film = Film::new(5, 3);
+        render_distributed(&scene, &mut film, 3, &[start_hung_worker(), start_worker()], Duration::from_millis(500)).unwrap();
+        assert_eq!(film.pixels, scene.render_scene(5, 3, 3).pixels);
+    }
+
+    #[test]
+    fn test_rendering_fails_without_workers() {
+        let scene: Scene = serde_yaml::from_str(SCENE).unwrap();
+        let mut film = Film::new(5, 3);
+        assert!(render_distributed(&scene, &mut film, 3, &[start_failing_worker()], TIMEOUT).is_err());
+    }
+}
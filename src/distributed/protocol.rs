@@ -0,0 +1,155 @@
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+
+use crate::raytracer::film::PixelStats;
+use crate::raytracer::tiles::Tile;
+
+// every message is a kind byte and a little endian u32 payload length, followed by the payload
+const JOB: u8 = 1;
+const TILE: u8 = 2;
+const TILE_RESULT: u8 = 3;
+const ERROR: u8 = 4;
+// scenes are small, anything bigger is a corrupted stream
+const MAX_MESSAGE_SIZE: usize = 1 << 30;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    // render settings sent once per connection: the seed, the frame size and the scene with its overrides as yaml
+    Job { seed: u64, frame_size: (u32, u32), scene: String },
+    // tile of the frame to render, in frame pixels
+    Tile(Tile),
+    // rendered tile with the sample statistics of its pixels in row order
+    TileResult { tile: Tile, pixels: Vec<PixelStats> },
+    // the worker cannot render the job
+    Error(String),
+}
+
+impl Message {
+    pub fn write_to(&self, stream: &mut impl Write) -> io::Result<()> {
+        let mut payload = Vec::new();
+        let kind = match self {
+            Message::Job { seed, frame_size, scene } => {
+                payload.extend_from_slice(&seed.to_le_bytes());
+                write_u32s(&mut payload, &[frame_size.0, frame_size.1]);
+                payload.extend_from_slice(scene.as_bytes());
+                JOB
+            }
+            Message::Tile(tile) => {
+                write_tile(&mut payload, tile);
+                TILE
+            }
+            Message::TileResult { tile, pixels } => {
+                write_tile(&mut payload, tile);
+                pixels.iter().for_each(|pixel| pixel.write_bytes(&mut payload));
+                TILE_RESULT
+            }
+            Message::Error(message) => {
+                payload.extend_from_slice(message.as_bytes());
+                ERROR
+            }
+        };
+        stream.write_all(&[kind])?;
+        stream.write_all(&(payload.len() as u32).to_le_bytes())?;
+        stream.write_all(&payload)?;
+        stream.flush()
+    }
+
+    // None when the stream was closed between two messages
+    pub fn read_from(stream: &mut impl Read) -> io::Result<Option<Message>> {
+        let mut header = [0_u8; 5];
+        match stream.read_exact(&mut header) {
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        }
+        let length = u32::from_le_bytes(header[1..5].try_into().unwrap()) as usize;
+        if length > MAX_MESSAGE_SIZE {
+            return Err(invalid_data(format!("message of {} bytes is too big", length)));
+        }
+        let mut payload = vec![0_u8; length];
+        stream.read_exact(&mut payload)?;
+        Message::parse(header[0], &payload).map(Some)
+    }
+
+    fn parse(kind: u8, payload: &[u8]) -> io::Result<Message> {
+        let u32_at = |offset: usize| u32::from_le_bytes(payload[offset..offset + 4].try_into().unwrap());
+        let tile_at = |offset: usize| Tile { x: u32_at(offset), y: u32_at(offset + 4), width: u32_at(offset + 8), height: u32_at(offset + 12) };
+        let text = |bytes: &[u8]| String::from_utf8(bytes.to_vec()).map_err(|_| invalid_data(String::from("text is not utf-8")));
+
+        match kind {
+            JOB if payload.len() >= 16 => Ok(Message::Job {
+                seed: u64::from_le_bytes(payload[0..8].try_into().unwrap()),
+                frame_size: (u32_at(8), u32_at(12)),
+                scene: text(&payload[16..])?,
+            }),
+            TILE if payload.len() == 16 => Ok(Message::Tile(tile_at(0))),
+            TILE_RESULT if payload.len() >= 16 => {
+                let tile = tile_at(0);
+                let pixel_count = tile.width as usize * tile.height as usize;
+                if payload.len() != 16 + pixel_count * PixelStats::ENCODED_SIZE {
+                    return Err(invalid_data(format!("tile result of {}x{} pixels has {} bytes", tile.width, tile.height, payload.len())));
+                }
+                let pixels = payload[16..].chunks_exact(PixelStats::ENCODED_SIZE).map(PixelStats::read_bytes).collect();
+                Ok(Message::TileResult { tile, pixels })
+            }
+            ERROR => Ok(Message::Error(text(payload)?)),
+            _ => Err(invalid_data(format!("invalid message of kind {} and {} bytes", kind, payload.len()))),
+        }
+    }
+}
+
+fn write_u32s(payload: &mut Vec<u8>, values: &[u32]) {
+    values.iter().for_each(|value| payload.extend_from_slice(&value.to_le_bytes()));
+}
+
+fn write_tile(payload: &mut Vec<u8>, tile: &Tile) {
+    write_u32s(payload, &[tile.x, tile.y, tile.width, tile.height]);
+}
+
+pub fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use na::Vector3;
+
+    fn round_trip(message: &Message) -> Message {
+        let mut bytes = Vec::new();
+        message.write_to(&mut bytes).unwrap();
+        Message::read_from(&mut bytes.as_slice()).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_messages_round_trip() {
+        let tile = Tile { x: 16, y: 32, width: 2, height: 1 };
+        let mut pixel = PixelStats::default();
+        pixel.add_sample(&Vector3::new(0.5, 0.25, 1.0));
+
+        let messages = [
+            Message::Job { seed: 7, frame_size: (640, 480), scene: String::from("shapes: []\n") },
+            Message::Tile(tile),
+            Message::TileResult { tile, pixels: vec![pixel, PixelStats::default()] },
+            Message::Error(String::from("invalid scene")),
+        ];
+        for message in &messages {
+            assert_eq!(&round_trip(message), message);
+        }
+    }
+
+    #[test]
+    fn test_closed_stream_has_no_message() {
+        assert!(Message::read_from(&mut [].as_slice()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_invalid_messages_are_errors() {
+        // a tile result announcing more pixels than it holds
+        let mut bytes = vec![TILE_RESULT, 16, 0, 0, 0];
+        write_tile(&mut bytes, &Tile { x: 0, y: 0, width: 4, height: 4 });
+        assert!(Message::read_from(&mut bytes.as_slice()).is_err());
+        assert!(Message::read_from(&mut [42_u8, 0, 0, 0, 0].as_slice()).is_err());
+        // truncated payload
+        assert!(Message::read_from(&mut [TILE, 16, 0, 0, 0, 1].as_slice()).is_err());
+    }
+}
@@ -0,0 +1,53 @@
+use std::io::{self, BufReader, BufWriter};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::AtomicBool;
+use std::thread;
+
+use crate::raytracer::film::Film;
+use crate::raytracer::Scene;
+
+use super::protocol::{Message, invalid_data};
+
+// accepts coordinators forever, each connection is served by its own thread
+pub fn serve(listener: TcpListener) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let stream = stream?;
+        thread::spawn(move || {
+            let peer = stream.peer_addr().map(|address| address.to_string()).unwrap_or_default();
+            if let Err(err) = handle_connection(stream) {
+                eprintln!("connection with {} failed: {}", peer, err);
+            }
+        });
+    }
+    Ok(())
+}
+
+// reads the job, then renders the tiles the coordinator sends until it closes the connection
+pub fn handle_connection(stream: TcpStream) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    let (seed, frame_size, scene) = match Message::read_from(&mut reader)? {
+        Some(Message::Job { seed, frame_size, scene }) => (seed, frame_size, scene),
+        Some(message) => return Err(invalid_data(format!("expected a job, got {:?}", message))),
+        None => return Ok(()),
+    };
+    let scene: Scene = match serde_yaml::from_str(&scene) {
+        Ok(scene) => scene,
+        Err(err) => return Message::Error(format!("invalid scene: {}", err)).write_to(&mut writer),
+    };
+
+    while let Some(message) = Message::read_from(&mut reader)? {
+        let tile = match message {
+            Message::Tile(tile) => tile,
+            message => return Err(invalid_data(format!("expected a tile, got {:?}", message))),
+        };
+        let mut film = match Film::region(frame_size.0, frame_size.1, tile.x, tile.y, tile.width, tile.height) {
+            Ok(film) => film,
+            Err(err) => return Message::Error(err).write_to(&mut writer),
+        };
+        scene.render_progressive(&mut film, seed, &AtomicBool::new(false), |_, _| true);
+        Message::TileResult { tile, pixels: film.pixels }.write_to(&mut writer)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,375 @@
+use std::collections::{HashMap, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use crate::raytracer::film::Film;
+use crate::raytracer::samplers::SamplerKind;
+use crate::raytracer::Scene;
+
+// largest image a request may ask for, a 4096x4096 film; larger ones could abort the whole server on allocation
+const MAX_PIXELS: u64 = 4096 * 4096;
+
+// finished jobs kept with their image, the ones that finished first are forgotten beyond this
+const MAX_FINISHED_JOBS: usize = 100;
+
+// render settings given with a scene, the sample count and the sampler override the scene file
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderOptions {
+    pub width: u32,
+    pub height: u32,
+    pub seed: u64,
+    pub samples_per_pixel: Option<u32>,
+    pub sampler: Option<SamplerKind>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions { width: 640, height: 480, seed: 0, samples_per_pixel: None, sampler: None }
+    }
+}
+
+impl RenderOptions {
+    // url query such as "width=320&height=240&seed=3&spp=16&sampler=sobol", missing options keep their default
+    pub fn from_query(query: &str) -> Result<RenderOptions, String> {
+        let mut options = RenderOptions::default();
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let invalid = |_| format!("invalid value '{}' for {}", value, name);
+            match name {
+                "width" => options.width = value.parse().map_err(invalid)?,
+                "height" => options.height = value.parse().map_err(invalid)?,
+                "seed" => options.seed = value.parse().map_err(invalid)?,
+                "spp" => options.samples_per_pixel = Some(value.parse().map_err(invalid)?),
+                "sampler" => options.sampler = Some(value.parse()?),
+                _ => return Err(format!("unknown option '{}'", name)),
+            }
+        }
+        if options.width < 2 || options.height < 2 {
+            return Err(String::from("width and height must be at least 2"));
+        }
+        if options.width as u64 * options.height as u64 > MAX_PIXELS {
+            return Err(format!("the image must have at most {} pixels", MAX_PIXELS));
+        }
+        Ok(options)
+    }
+
+    fn apply(&self, scene: &mut Scene) {
+        if let Some(samples_per_pixel) = self.samples_per_pixel {
+            scene.sampling.samples_per_pixel = samples_per_pixel;
+        }
+        if let Some(sampler) = self.sampler {
+            scene.sampling.sampler = sampler;
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed(String),
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Done => "done",
+            JobStatus::Failed(_) => "failed",
+            JobStatus::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self, JobStatus::Done | JobStatus::Failed(_) | JobStatus::Cancelled)
+    }
+}
+
+// 16 bit image of a job after a pass, the samples of the film are only kept by the render thread
+pub struct JobImage {
+    pub width: u32,
+    pub height: u32,
+    pub rgb16: Vec<u16>,
+    pub samples_per_pixel: f64,
+}
+
+impl JobImage {
+    fn new(film: &Film) -> JobImage {
+        JobImage { width: film.width, height: film.height, rgb16: film.to_rgb16(), samples_per_pixel: film.samples_per_pixel() }
+    }
+}
+
+pub struct Job {
+    pub status: JobStatus,
+    // fraction of the samples taken, adaptive sampling can finish before reaching 1
+    pub progress: f64,
+    pub elapsed_seconds: f64,
+    // image so far, updated after every pass
+    pub image: Option<Arc<JobImage>>,
+    // scenes are kept as yaml and parsed by the thread rendering them
+    scene: String,
+    options: RenderOptions,
+    cancelled: Arc<AtomicBool>,
+}
+
+struct QueueState {
+    jobs: HashMap<u64, Job>,
+    queued: VecDeque<u64>,
+    // in the order the jobs finished
+    finished: VecDeque<u64>,
+    next_id: u64,
+}
+
+impl QueueState {
+    fn finish(&mut self, id: u64, status: JobStatus) {
+        self.jobs.get_mut(&id).unwrap().status = status;
+        self.finished.push_back(id);
+        while self.finished.len() > MAX_FINISHED_JOBS {
+            let oldest = self.finished.pop_front().unwrap();
+            self.jobs.remove(&oldest);
+        }
+    }
+}
+
+// jobs waiting for one of a fixed number of render threads; finished jobs are kept until they are deleted or
+// MAX_FINISHED_JOBS later jobs have finished
+pub struct JobQueue {
+    state: Mutex<QueueState>,
+    job_queued: Condvar,
+}
+
+impl JobQueue {
+    // starts max_running_jobs render threads
+    pub fn new(max_running_jobs: usize) -> Arc<JobQueue> {
+        let queue = Arc::new(JobQueue {
+            state: Mutex::new(QueueState { jobs: HashMap::new(), queued: VecDeque::new(), finished: VecDeque::new(), next_id: 1 }),
+            job_queued: Condvar::new(),
+        });
+        for _ in 0..max_running_jobs.max(1) {
+            let queue = queue.clone();
+            thread::spawn(move || queue.run_jobs());
+        }
+        queue
+    }
+
+    // checks that the scene can be read and queues it, returning the job id
+    pub fn submit(&self, scene: String, options: RenderOptions) -> Result<u64, String> {
+        serde_yaml::from_str::<Scene>(&scene).map_err(|err| format!("invalid scene: {}", err))?;
+
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        let job = Job {
+            status: JobStatus::Queued,
+            progress: 0.0,
+            elapsed_seconds: 0.0,
+            image: None,
+            scene,
+            options,
+            cancelled: Arc::new(AtomicBool::new(false)),
+        };
+        state.jobs.insert(id, job);
+        state.queued.push_back(id);
+        self.job_queued.notify_one();
+        Ok(id)
+    }
+
+    // calls inspect with the job, None if there is no such job
+    pub fn with_job<T>(&self, id: u64, inspect: impl FnOnce(&Job) -> T) -> Option<T> {
+        self.state.lock().unwrap().jobs.get(&id).map(inspect)
+    }
+
+    // a queued job is cancelled at once, a running one stops in the middle of its current pass; returns the new status
+    pub fn cancel(&self, id: u64) -> Option<JobStatus> {
+        let mut state = self.state.lock().unwrap();
+        let job = state.jobs.get_mut(&id)?;
+        match job.status {
+            JobStatus::Queued => {
+                state.queued.retain(|queued_id| *queued_id != id);
+                state.finish(id, JobStatus::Cancelled);
+            }
+            JobStatus::Running => job.cancelled.store(true, Ordering::SeqCst),
+            _ => {}
+        }
+        state.jobs.get(&id).map(|job| job.status.clone())
+    }
+
+    // forgets a finished job and its image, returning its status; jobs that are not finished are left alone
+    pub fn remove(&self, id: u64) -> Option<JobStatus> {
+        let mut state = self.state.lock().unwrap();
+        if !state.jobs.get(&id)?.status.is_finished() {
+            return None;
+        }
+        state.finished.retain(|finished_id| *finished_id != id);
+        state.jobs.remove(&id).map(|job| job.status)
+    }
+
+    fn run_jobs(&self) {
+        loop {
+            let (id, scene, options, cancelled) = {
+                let mut state = self.state.lock().unwrap();
+                let id = loop {
+                    match state.queued.pop_front() {
+                        Some(id) => break id,
+                        None => state = self.job_queued.wait(state).unwrap(),
+                    }
+                };
+                let job = state.jobs.get_mut(&id).unwrap();
+                job.status = JobStatus::Running;
+                (id, job.scene.clone(), job.options.clone(), job.cancelled.clone())
+            };
+
+            // a panicking render fails its job instead of taking down the render thread
+            let result = panic::catch_unwind(AssertUnwindSafe(|| self.render_job(id, &scene, &options, &cancelled)))
+                .unwrap_or_else(|_| Err(String::from("the render crashed")));
+
+            let status = match result {
+                Ok(()) if cancelled.load(Ordering::SeqCst) => JobStatus::Cancelled,
+                Ok(()) => JobStatus::Done,
+                Err(err) => JobStatus::Failed(err),
+            };
+            self.state.lock().unwrap().finish(id, status);
+        }
+    }
+
+    fn render_job(&self, id: u64, scene: &str, options: &RenderOptions, cancelled: &AtomicBool) -> Result<(), String> {
+        let mut scene: Scene = serde_yaml::from_str(scene).map_err(|err| format!("invalid scene: {}", err))?;
+        options.apply(&mut scene);
+
+        let mut film = Film::new(options.width, options.height);
+        let start = Instant::now();
+        scene.render_progressive(&mut film, options.seed, cancelled, |film, _| {
+            // converted before taking the lock, which status requests wait for
+            let image = JobImage::new(film);
+            let elapsed_seconds = start.elapsed().as_secs_f64();
+            let progress = scene.sampling.progress(film, elapsed_seconds);
+
+            let mut state = self.state.lock().unwrap();
+            let job = state.jobs.get_mut(&id).unwrap();
+            job.elapsed_seconds = elapsed_seconds;
+            job.progress = progress;
+            job.image = Some(Arc::new(image));
+            !cancelled.load(Ordering::SeqCst)
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const SCENE: &str = "
+camera:
+  cam_orient: [0.0, 0.0, 1.0]
+  cam_pos: [0.0, 0.0, 0.0]
+  up_vec: [0.0, 1.0, 0.0]
+shapes:
+  - type: Sphere
+    centre: [0.0, 0.0, 5.0]
+    r: 1.0
+    color: [0.0, 0.0, 0.0]
+    emissive_color: [0.5, 0.25, 0.5]
+    albedo: 0.0
+    reflectivity: 0.0
+distant_lights: []
+";
+
+    fn wait_until_finished(queue: &JobQueue, id: u64) -> JobStatus {
+        for _ in 0..1000 {
+            let status = queue.with_job(id, |job| job.status.clone()).unwrap();
+            if status.is_finished() {
+                return status;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("job {} did not finish", id);
+    }
+
+    #[test]
+    fn test_options_from_query() {
+        let options = RenderOptions::from_query("width=32&height=16&spp=4&sampler=sobol").unwrap();
+        assert_eq!((options.width, options.height, options.seed), (32, 16, 0));
+        assert_eq!((options.samples_per_pixel, options.sampler), (Some(4), Some(SamplerKind::Sobol)));
+        assert_eq!(RenderOptions::from_query("").unwrap(), RenderOptions::default());
+        assert!(RenderOptions::from_query("width=big").is_err());
+        assert!(RenderOptions::from_query("depth=3").is_err());
+        assert!(RenderOptions::from_query("width=1").is_err());
+        assert!(RenderOptions::from_query("width=50000&height=50000").is_err());
+        assert!(RenderOptions::from_query("width=4096&height=4096").is_ok());
+    }
+
+    #[test]
+    fn test_job_is_rendered() {
+        let queue = JobQueue::new(1);
+        let options = RenderOptions { width: 4, height: 3, samples_per_pixel: Some(2), ..RenderOptions::default() };
+        let id = queue.submit(String::from(SCENE), options).unwrap();
+        assert_eq!(wait_until_finished(&queue, id), JobStatus::Done);
+
+        let (progress, image) = queue.with_job(id, |job| {
+            let image = job.image.as_ref().unwrap();
+            (job.progress, (image.width, image.height, image.rgb16.clone(), image.samples_per_pixel))
+        }).unwrap();
+        assert_eq!(progress, 1.0);
+        let mut scene: Scene = serde_yaml::from_str(SCENE).unwrap();
+        scene.sampling.samples_per_pixel = 2;
+        assert_eq!(image, (4, 3, scene.render_scene(4, 3, 0).to_rgb16(), 2.0));
+    }
+
+    #[test]
+    fn test_invalid_scene_is_refused() {
+        let queue = JobQueue::new(1);
+        assert!(queue.submit(String::from("camera: 3"), RenderOptions::default()).is_err());
+        assert!(queue.with_job(1, |_| ()).is_none());
+    }
+
+    #[test]
+    fn test_cancelled_jobs_stop() {
+        // the first job never finishes on its own, so the second one waits in the queue
+        let queue = JobQueue::new(1);
+        let endless = RenderOptions { width: 2, height: 2, samples_per_pixel: Some(u32::MAX), ..RenderOptions::default() };
+        let running = queue.submit(String::from(SCENE), endless.clone()).unwrap();
+        let queued = queue.submit(String::from(SCENE), endless).unwrap();
+
+        assert_eq!(queue.cancel(queued), Some(JobStatus::Cancelled));
+        while queue.with_job(running, |job| job.status.clone()) != Some(JobStatus::Running) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        queue.cancel(running);
+        assert_eq!(wait_until_finished(&queue, running), JobStatus::Cancelled);
+        assert_eq!(queue.cancel(42), None);
+    }
+
+    #[test]
+    fn test_finished_jobs_are_removed() {
+        let queue = JobQueue::new(1);
+        let endless = RenderOptions { width: 2, height: 2, samples_per_pixel: Some(u32::MAX), ..RenderOptions::default() };
+        let running = queue.submit(String::from(SCENE), endless.clone()).unwrap();
+        while queue.with_job(running, |job| job.status.clone()) != Some(JobStatus::Running) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(queue.remove(running), None);
+
+        // jobs cancelled while queued finish at once, the oldest ones are dropped past the limit
+        let cancelled: Vec<u64> = (0..MAX_FINISHED_JOBS + 2).map(|_| {
+            let id = queue.submit(String::from(SCENE), endless.clone()).unwrap();
+            queue.cancel(id);
+            id
+        }).collect();
+        assert!(queue.with_job(cancelled[0], |_| ()).is_none());
+        assert!(queue.with_job(cancelled[1], |_| ()).is_none());
+        assert!(queue.with_job(cancelled[2], |_| ()).is_some());
+
+        assert_eq!(queue.remove(cancelled[2]), Some(JobStatus::Cancelled));
+        assert!(queue.with_job(cancelled[2], |_| ()).is_none());
+        assert_eq!(queue.remove(cancelled[2]), None);
+        queue.cancel(running);
+    }
+}
@@ -0,0 +1,272 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+
+use image::{DynamicImage, ImageBuffer, ImageOutputFormat, Rgb};
+use serde_json::json;
+
+use super::jobs::{JobQueue, JobStatus, RenderOptions};
+
+// scenes bigger than this are refused
+const MAX_BODY_SIZE: usize = 16 << 20;
+
+#[derive(Debug, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn json(status: u16, value: serde_json::Value) -> Response {
+        Response { status, content_type: "application/json", body: value.to_string().into_bytes() }
+    }
+
+    fn error(status: u16, message: &str) -> Response {
+        Response::json(status, json!({ "error": message }))
+    }
+
+    fn write_to(&self, stream: &mut impl Write) -> io::Result<()> {
+        let reason = match self.status {
+            200 => "OK",
+            202 => "Accepted",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            409 => "Conflict",
+            413 => "Payload Too Large",
+            _ => "Internal Server Error",
+        };
+        write!(stream, "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+               self.status, reason, self.content_type, self.body.len())?;
+        stream.write_all(&self.body)?;
+        stream.flush()
+    }
+}
+
+// one thread per connection, each connection carries a single request
+pub fn serve(listener: TcpListener, queue: Arc<JobQueue>) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let queue = queue.clone();
+        thread::spawn(move || {
+            if let Err(err) = handle_connection(stream, &queue) {
+                eprintln!("http connection failed: {}", err);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle_connection(stream: TcpStream, queue: &JobQueue) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let response = match read_request(&mut reader) {
+        Ok(request) => route(&request, queue),
+        Err(response) => response,
+    };
+    response.write_to(&mut &stream)
+}
+
+// request line, headers and a body of Content-Length bytes; chunked bodies are not supported
+pub fn read_request(reader: &mut impl BufRead) -> Result<Request, Response> {
+    let bad_request = |message: &str| Response::error(400, message);
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(|_| bad_request("cannot read the request"))?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+        _ => return Err(bad_request("invalid request line")),
+    };
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+
+    let mut content_length = 0;
+    loop {
+        line.clear();
+        reader.read_line(&mut line).map_err(|_| bad_request("cannot read the headers"))?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().map_err(|_| bad_request("invalid Content-Length"))?;
+            }
+        }
+    }
+    if content_length > MAX_BODY_SIZE {
+        return Err(Response::error(413, "the body is too big"));
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).map_err(|_| bad_request("truncated body"))?;
+    Ok(Request { method, path: path.to_string(), query: query.to_string(), body })
+}
+
+pub fn route(request: &Request, queue: &JobQueue) -> Response {
+    let segments: Vec<&str> = request.path.split('/').filter(|segment| !segment.is_empty()).collect();
+    match (request.method.as_str(), segments.as_slice()) {
+        ("POST", ["render"]) => submit(request, queue),
+        (method, ["jobs", id, rest @ ..]) => {
+            let id = match id.parse::<u64>() {
+                Ok(id) => id,
+                Err(_) => return Response::error(404, "no such job"),
+            };
+            match (method, rest) {
+                ("GET", []) => job_status(id, queue),
+                ("DELETE", []) => delete_job(id, queue),
+                ("POST", ["cancel"]) => match queue.cancel(id) {
+                    Some(status) => Response::json(200, json!({ "id": id, "status": status.name() })),
+                    None => Response::error(404, "no such job"),
+                },
+                ("GET", ["image"]) => job_image(id, queue),
+                _ => Response::error(405, "unsupported method"),
+            }
+        }
+        _ => Response::error(404, "unknown path"),
+    }
+}
+
+// the body is the scene yaml, the options are in the query
+fn submit(request: &Request, queue: &JobQueue) -> Response {
+    let options = match RenderOptions::from_query(&request.query) {
+        Ok(options) => options,
+        Err(err) => return Response::error(400, &err),
+    };
+    let scene = match String::from_utf8(request.body.clone()) {
+        Ok(scene) => scene,
+        Err(_) => return Response::error(400, "the scene is not utf-8"),
+    };
+    match queue.submit(scene, options) {
+        Ok(id) => Response::json(202, json!({ "id": id, "status": "queued" })),
+        Err(err) => Response::error(400, &err),
+    }
+}
+
+fn job_status(id: u64, queue: &JobQueue) -> Response {
+    let status = queue.with_job(id, |job| {
+        let samples_per_pixel = job.image.as_ref().map_or(0.0, |image| image.samples_per_pixel);
+        let error = match &job.status {
+            JobStatus::Failed(err) => Some(err.clone()),
+            _ => None,
+        };
+        json!({
+            "id": id,
+            "status": job.status.name(),
+            "finished": job.status.is_finished(),
+            "progress": job.progress,
+            "samples_per_pixel": samples_per_pixel,
+            "elapsed_seconds": job.elapsed_seconds,
+            "error": error,
+        })
+    });
+    match status {
+        Some(status) => Response::json(200, status),
+        None => Response::error(404, "no such job"),
+    }
+}
+
+// a finished job is removed, any other one is cancelled and can be deleted again once it has stopped
+fn delete_job(id: u64, queue: &JobQueue) -> Response {
+    if let Some(status) = queue.remove(id) {
+        return Response::json(200, json!({ "id": id, "status": status.name(), "removed": true }));
+    }
+    match queue.cancel(id) {
+        Some(status) => Response::json(200, json!({ "id": id, "status": status.name(), "removed": false })),
+        None => Response::error(404, "no such job"),
+    }
+}
+
+// 16 bit png of the image so far, available after the first pass
+fn job_image(id: u64, queue: &JobQueue) -> Response {
+    let image = match queue.with_job(id, |job| job.image.clone()) {
+        Some(Some(image)) => image,
+        Some(None) => return Response::error(409, "the job has no image yet"),
+        None => return Response::error(404, "no such job"),
+    };
+    let buffer = ImageBuffer::<Rgb<u16>, Vec<u16>>::from_vec(image.width, image.height, image.rgb16.clone()).unwrap();
+    let mut png = Vec::new();
+    match DynamicImage::ImageRgb16(buffer).write_to(&mut png, ImageOutputFormat::Png) {
+        Ok(()) => Response { status: 200, content_type: "image/png", body: png },
+        Err(err) => Response::error(500, &err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const SCENE: &str = "
+camera:
+  cam_orient: [0.0, 0.0, 1.0]
+  cam_pos: [0.0, 0.0, 0.0]
+  up_vec: [0.0, 1.0, 0.0]
+shapes: []
+distant_lights: []
+";
+
+    fn request(method: &str, target: &str, body: &str) -> Request {
+        let raw = format!("{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}", method, target, body.len(), body);
+        read_request(&mut raw.as_bytes()).unwrap()
+    }
+
+    fn json_body(response: &Response) -> serde_json::Value {
+        serde_json::from_slice(&response.body).unwrap()
+    }
+
+    #[test]
+    fn test_read_request() {
+        let request = request("POST", "/render?width=8&height=4", "shapes: []");
+        assert_eq!(request.method, "POST");
+        assert_eq!((request.path.as_str(), request.query.as_str()), ("/render", "width=8&height=4"));
+        assert_eq!(request.body, b"shapes: []");
+
+        assert!(read_request(&mut "garbage\r\n\r\n".as_bytes()).is_err());
+        let too_big = format!("POST /render HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY_SIZE + 1);
+        assert_eq!(read_request(&mut too_big.as_bytes()).unwrap_err().status, 413);
+    }
+
+    #[test]
+    fn test_render_job_over_http() {
+        let queue = JobQueue::new(1);
+        let submitted = route(&request("POST", "/render?width=4&height=3&spp=2", SCENE), &queue);
+        assert_eq!(submitted.status, 202);
+        let id = json_body(&submitted)["id"].as_u64().unwrap();
+
+        let mut status = json_body(&route(&request("GET", &format!("/jobs/{}", id), ""), &queue));
+        while status["finished"] == false {
+            thread::sleep(Duration::from_millis(10));
+            status = json_body(&route(&request("GET", &format!("/jobs/{}", id), ""), &queue));
+        }
+        assert_eq!(status["status"], "done");
+        assert_eq!(status["samples_per_pixel"], 2.0);
+
+        let image = route(&request("GET", &format!("/jobs/{}/image", id), ""), &queue);
+        assert_eq!((image.status, image.content_type), (200, "image/png"));
+        assert!(image.body.starts_with(b"\x89PNG"));
+
+        let deleted = route(&request("DELETE", &format!("/jobs/{}", id), ""), &queue);
+        assert_eq!(json_body(&deleted)["removed"], true);
+        assert_eq!(route(&request("GET", &format!("/jobs/{}", id), ""), &queue).status, 404);
+    }
+
+    #[test]
+    fn test_http_errors() {
+        let queue = JobQueue::new(1);
+        assert_eq!(route(&request("POST", "/render", "camera: 3"), &queue).status, 400);
+        assert_eq!(route(&request("POST", "/render?width=x", SCENE), &queue).status, 400);
+        assert_eq!(route(&request("GET", "/jobs/7", ""), &queue).status, 404);
+        assert_eq!(route(&request("DELETE", "/jobs/7", ""), &queue).status, 404);
+        assert_eq!(route(&request("PUT", "/render", ""), &queue).status, 404);
+        assert_eq!(route(&request("PUT", "/jobs/7", ""), &queue).status, 405);
+    }
+}
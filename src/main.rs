@@ -3,12 +3,30 @@ use image::{ImageBuffer, Rgb};
 extern crate nalgebra as na;
 extern crate num_complex;
 extern crate clap;
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 
 mod raytracer;
+mod distributed;
+mod http;
+mod watch;
+mod terminal;
 use crate::raytracer::*;
+use crate::raytracer::film::Film;
+use crate::raytracer::checkpoint::{Checkpoint, scene_hash};
+use crate::raytracer::animation::Animation;
+use crate::terminal::TerminalGraphics;
 
+use std::convert::TryInto;
 use std::fs;
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+
+const DEFAULT_CHECKPOINT_INTERVAL: Duration = Duration::from_secs(300);
+const DEFAULT_WORKER_ADDRESS: &str = "127.0.0.1:7878";
+const DEFAULT_WORKER_TIMEOUT: Duration = Duration::from_secs(600);
+const DEFAULT_HTTP_ADDRESS: &str = "127.0.0.1:8080";
 
 
 fn main() -> std::io::Result<()> {
@@ -16,6 +34,38 @@ fn main() -> std::io::Result<()> {
         .version("0.1")
         .author("Barthélémy Gouby")
         .about("Simple raytracer written in rust")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("renders tiles for coordinators started with --workers")
+                .arg(
+                    Arg::with_name("listen")
+                        .long("listen")
+                        .value_name("ADDRESS")
+                        .help("address to accept coordinators on, 127.0.0.1:7878 by default; coordinators are not authenticated \
+                               and can make the worker read any texture or grid file it has access to, only listen on the other \
+                               interfaces, like 0.0.0.0:7878, on a trusted network")
+                        .takes_value(true)
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("serve-http")
+                .about("renders scenes posted to an http api")
+                .arg(
+                    Arg::with_name("listen")
+                        .long("listen")
+                        .value_name("ADDRESS")
+                        .help("address to accept requests on, 127.0.0.1:8080 by default")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("max-jobs")
+                        .long("max-jobs")
+                        .value_name("JOBS")
+                        .help("number of jobs rendered at the same time, 1 by default")
+                        .takes_value(true)
+                )
+        )
         .arg(
             Arg::with_name("SCENE")
                 .help("The scene configuration file")
@@ -36,23 +86,452 @@ fn main() -> std::io::Result<()> {
                 .help("the dimension of the output image")
                 .takes_value(true)
         )
+        .arg(
+            Arg::with_name("seed")
+                .short("s")
+                .long("seed")
+                .value_name("SEED")
+                .help("the seed of the random sampling, renders with the same seed are identical")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("sampler")
+                .long("sampler")
+                .value_name("SAMPLER")
+                .help("independent, stratified, halton, sobol or blue-noise, overrides the scene file")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("spp")
+                .long("spp")
+                .value_name("SAMPLES")
+                .help("camera rays per pixel, overrides the scene file")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("heatmap")
+                .long("heatmap")
+                .value_name("HEATMAP")
+                .help("also write an image of the number of samples taken in each pixel")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("preview")
+                .long("preview")
+                .value_name("PREVIEW")
+                .help("where snapshots of the render in progress are written, the output path by default")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("snapshot-seconds")
+                .long("snapshot-seconds")
+                .value_name("DURATION")
+                .help("write a snapshot of the render in progress at most every DURATION, in seconds or with a unit like 90s, 10m or 2h")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("snapshot-passes")
+                .long("snapshot-passes")
+                .value_name("PASSES")
+                .help("write a snapshot of the render in progress every PASSES passes")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("checkpoint")
+                .long("checkpoint")
+                .value_name("CHECKPOINT")
+                .help("periodically save the render in progress to this file, so that it can be resumed")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("checkpoint-seconds")
+                .long("checkpoint-seconds")
+                .value_name("DURATION")
+                .help("save the checkpoint at most every DURATION, 5m by default")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("resume")
+                .long("resume")
+                .help("go on with the render saved in the checkpoint, which must come from the same scene")
+                .requires("checkpoint")
+        )
+        .arg(
+            Arg::with_name("time-limit")
+                .long("time-limit")
+                .value_name("DURATION")
+                .help("keep adding samples until DURATION (e.g. 90s, 10m, 2h) is spent, without a sample limit unless --spp is given")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("tile-size")
+                .long("tile-size")
+                .value_name("PIXELS")
+                .help("side of the tiles the image is rendered in, overrides the scene file")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("tile-order")
+                .long("tile-order")
+                .value_name("ORDER")
+                .help("scanline, spiral or hilbert, overrides the scene file")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("region")
+                .long("region")
+                .value_name("X,Y,W,H")
+                .help("only render the WxH crop at X,Y of the frame, with the same pixels as the full render")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("workers")
+                .long("workers")
+                .value_name("ADDRESSES")
+                .help("render the tiles on these comma separated workers, started with the serve subcommand")
+                .takes_value(true)
+                .conflicts_with_all(&["snapshot-seconds", "snapshot-passes", "checkpoint", "time-limit"])
+        )
+        .arg(
+            Arg::with_name("worker-timeout")
+                .long("worker-timeout")
+                .value_name("DURATION")
+                .help("give a tile to another worker when its worker does not answer within DURATION, 10m by default")
+                .takes_value(true)
+                .requires("workers")
+        )
+        .arg(
+            Arg::with_name("watch")
+                .long("watch")
+                .help("render again whenever the scene file or a file it refers to changes")
+                .conflicts_with_all(&["snapshot-seconds", "snapshot-passes", "checkpoint", "time-limit", "workers"])
+        )
+        .arg(
+            Arg::with_name("frames")
+                .long("frames")
+                .value_name("FIRST-LAST")
+                .help("render the frames of the scene animation, numbering the output path like out_%04d.png")
+                .takes_value(true)
+                .conflicts_with_all(&["snapshot-seconds", "snapshot-passes", "checkpoint", "time-limit", "watch"])
+        )
+        .arg(
+            Arg::with_name("preview-terminal")
+                .long("preview-terminal")
+                .help("render at a resolution that fits the terminal and draw the render in the terminal after every pass")
+                .conflicts_with("workers")
+        )
+        .arg(
+            Arg::with_name("preview-full-size")
+                .long("preview-full-size")
+                .help("render the terminal preview at --dimensions and scale it down when drawing it")
+                .requires("preview-terminal")
+        )
         .get_matches();
 
+    if let Some(serve_matches) = matches.subcommand_matches("serve") {
+        let listener = TcpListener::bind(serve_matches.value_of("listen").unwrap_or(DEFAULT_WORKER_ADDRESS))?;
+        println!("waiting for coordinators on {}", listener.local_addr()?);
+        return distributed::worker::serve(listener);
+    }
+    if let Some(serve_matches) = matches.subcommand_matches("serve-http") {
+        let max_jobs = serve_matches.value_of("max-jobs")
+            .map_or(Ok(1), |jobs| match jobs.parse::<usize>() {
+                Ok(jobs) if jobs > 0 => Ok(jobs),
+                _ => Err(format!("invalid job count '{}', at least one job must be rendered at a time", jobs)),
+            })
+            .unwrap_or_else(exit_with_error);
+        let listener = TcpListener::bind(serve_matches.value_of("listen").unwrap_or(DEFAULT_HTTP_ADDRESS))?;
+        println!("accepting render requests on http://{}", listener.local_addr()?);
+        return http::server::serve(listener, http::jobs::JobQueue::new(max_jobs));
+    }
+
 
     let scene_path = matches.value_of("SCENE").unwrap();
     let output_path = matches.value_of("OUTPUT").unwrap();
     let dimensions_str = matches.value_of("dimensions").unwrap_or("640x480");
+    let seed = matches.value_of("seed").map_or(Ok(0), |seed| seed.parse::<u64>().map_err(|_| format!("invalid seed '{}'", seed)))
+        .unwrap_or_else(exit_with_error);
 
 
     let conf = fs::read_to_string(scene_path)?;
-    let scene: Scene = serde_yaml::from_str(&conf).unwrap();
+    let mut scene = load_scene(&conf, &matches).unwrap_or_else(exit_with_error);
+    let time_limit = matches.value_of("time-limit").map(|limit| parse_duration(limit).unwrap_or_else(exit_with_error));
+    if let Some(limit) = time_limit {
+        scene.sampling.time_limit = Some(limit.as_secs_f64());
+        // without a sample count, the time limit is the only thing that stops the render
+        scene.sampling.unlimited = !matches.is_present("spp") && scene.sampling.adaptive.is_none();
+    }
+
+    let mut dimensions = dimensions_str.split('x').map(|s| s.parse::<u32>().unwrap()).collect::<Vec<u32>>();
+    let terminal_graphics = matches.is_present("preview-terminal").then(TerminalGraphics::detect);
+    if let Some(graphics) = terminal_graphics {
+        // the preview is rendered at the size it is drawn at so that passes stay quick,
+        // unless the full size image is wanted and only the drawing is scaled down
+        if !matches.is_present("preview-full-size") {
+            if matches.is_present("region") {
+                exit_with_error::<()>(String::from("--region needs --preview-full-size when drawing in the terminal"));
+            }
+            let (width, height) = graphics.preview_size(dimensions[0], dimensions[1]);
+            dimensions = vec![width, height];
+        }
+        terminal::clear_screen();
+    }
+
+    let preview_path = matches.value_of("preview").unwrap_or(output_path);
+    let snapshot_interval = matches.value_of("snapshot-seconds")
+        .map(|interval| parse_positive_duration(interval, "snapshot interval").unwrap_or_else(exit_with_error));
+    let snapshot_passes = matches.value_of("snapshot-passes")
+        .map(|passes| match passes.parse::<u32>() {
+            Ok(passes) if passes > 0 => passes,
+            _ => exit_with_error(format!("invalid snapshot pass count '{}', expected at least 1", passes)),
+        });
+
+    let checkpoint_path = matches.value_of("checkpoint").map(Path::new);
+    let checkpoint_interval = matches.value_of("checkpoint-seconds")
+        .map_or(Ok(DEFAULT_CHECKPOINT_INTERVAL), |interval| parse_positive_duration(interval, "checkpoint interval"))
+        .unwrap_or_else(exit_with_error);
+
+    let region = matches.value_of("region").map(|region| parse_region(region).unwrap_or_else(exit_with_error));
+    let new_film = || match region {
+        Some([x, y, width, height]) => Film::region(dimensions[0], dimensions[1], x, y, width, height).unwrap_or_else(exit_with_error),
+        None => Film::new(dimensions[0], dimensions[1]),
+    };
+    if matches.is_present("watch") {
+        watch::watch(
+            Path::new(scene_path),
+            |conf| load_scene(conf, &matches),
+            new_film,
+            seed,
+            |film| match terminal_graphics {
+                Some(graphics) => { graphics.draw(film).ok(); }
+                None => save_image(film.width, film.height, film.to_rgb16(), output_path),
+            },
+        );
+    }
+
+    let workers = matches.value_of("workers").map(|workers| workers.split(',').map(String::from).collect::<Vec<String>>());
+    let worker_timeout = matches.value_of("worker-timeout")
+        .map_or(Ok(DEFAULT_WORKER_TIMEOUT), |timeout| parse_positive_duration(timeout, "worker timeout"))
+        .unwrap_or_else(exit_with_error);
 
-    let dimensions = dimensions_str.split('x').map(|s| s.parse::<u32>().unwrap()).collect::<Vec<u32>>();
+    if let Some(frames) = matches.value_of("frames") {
+        let (first, last) = parse_frames(frames).unwrap_or_else(exit_with_error);
+        let animation = Animation::new(&conf).unwrap_or_else(exit_with_error);
+        for frame in first..=last {
+            // the previous scene is only dropped once this one is built, so its textures and grids are not loaded again
+            scene = animation.scene_at(frame as f64).and_then(|scene| apply_overrides(scene, &matches)).unwrap_or_else(exit_with_error);
+            let mut film = new_film();
+            match &workers {
+                Some(workers) => distributed::coordinator::render_distributed(&scene, &mut film, seed, workers, worker_timeout).unwrap_or_else(exit_with_error),
+                None => scene.render_progressive(&mut film, seed, &AtomicBool::new(false), |film, _| {
+                    if let Some(graphics) = terminal_graphics {
+                        graphics.draw(film).ok();
+                    }
+                    true
+                }),
+            }
+            save_image(film.width, film.height, film.to_rgb16(), &frame_path(output_path, frame));
+            if let Some(heatmap_path) = matches.value_of("heatmap") {
+                save_image(film.width, film.height, film.sample_count_heatmap(), &frame_path(heatmap_path, frame));
+            }
+        }
+        return Ok(());
+    }
 
-    let raw_pixels = scene.render_scene(dimensions[0], dimensions[1]);
-    let imgbuf = ImageBuffer::<Rgb<u16>, Vec<u16>>::from_vec(dimensions[0], dimensions[1], raw_pixels).unwrap();
+    let render_start = Instant::now();
+    let progressive = snapshot_interval.is_some() || snapshot_passes.is_some() || checkpoint_path.is_some() || time_limit.is_some()
+        || terminal_graphics.is_some();
+    let film = if let Some(workers) = workers {
+        let mut film = new_film();
+        distributed::coordinator::render_distributed(&scene, &mut film, seed, &workers, worker_timeout).unwrap_or_else(exit_with_error);
+        film
+    } else if !progressive && region.is_none() {
+        scene.render_scene(dimensions[0], dimensions[1], seed)
+    } else {
+        let scene_hash = scene_hash(&scene).unwrap_or_else(exit_with_error);
+        let mut film = new_film();
+        if let Some(path) = checkpoint_path.filter(|_| matches.is_present("resume")) {
+            let checkpoint = Checkpoint::load(path).unwrap_or_else(exit_with_error);
+            checkpoint.check_resumable(scene_hash, seed, &film).unwrap_or_else(exit_with_error);
+            film = checkpoint.film;
+        }
+        let mut last_snapshot = Instant::now();
+        let mut last_checkpoint = Instant::now();
+        scene.render_progressive(&mut film, seed, &AtomicBool::new(false), |film, pass| {
+            if let Some(graphics) = terminal_graphics {
+                // a terminal that went away should not stop the render
+                graphics.draw(film).ok();
+            }
+            let out_of_time = time_limit.is_some_and(|limit| render_start.elapsed() >= limit);
+            let interval_elapsed = snapshot_interval.is_some_and(|interval| last_snapshot.elapsed() >= interval);
+            let passes_elapsed = snapshot_passes.is_some_and(|passes| pass.is_multiple_of(passes));
+            if interval_elapsed || passes_elapsed {
+                save_image(film.width, film.height, film.to_rgb16(), preview_path);
+                last_snapshot = Instant::now();
+            }
 
-    // the format is deduced from the file extension in the output_path
-    imgbuf.save(output_path).unwrap();
+            if let Some(path) = checkpoint_path {
+                // also saved when the time is out, so that the render can be resumed with more time
+                if last_checkpoint.elapsed() >= checkpoint_interval || out_of_time {
+                    // a failed checkpoint should not stop the render
+                    if let Err(err) = (Checkpoint { scene_hash, seed, film: film.clone() }).save(path) {
+                        eprintln!("{}", err);
+                    }
+                    last_checkpoint = Instant::now();
+                }
+            }
+            !out_of_time
+        });
+        film
+    };
+    println!("rendered {:.1} samples per pixel in {:.1}s", film.samples_per_pixel(), render_start.elapsed().as_secs_f64());
+
+    save_image(film.width, film.height, film.to_rgb16(), output_path);
+
+    if let Some(heatmap_path) = matches.value_of("heatmap") {
+        save_image(film.width, film.height, film.sample_count_heatmap(), heatmap_path);
+    }
     Ok(())
 }
+
+// scene with the command line overrides applied
+fn load_scene(conf: &str, matches: &ArgMatches) -> Result<Scene, String> {
+    let scene: Scene = serde_yaml::from_str(conf).map_err(|err| format!("invalid scene: {}", err))?;
+    apply_overrides(scene, matches)
+}
+
+fn apply_overrides(mut scene: Scene, matches: &ArgMatches) -> Result<Scene, String> {
+    if let Some(sampler) = matches.value_of("sampler") {
+        scene.sampling.sampler = sampler.parse()?;
+    }
+    if let Some(spp) = matches.value_of("spp") {
+        scene.sampling.samples_per_pixel = spp.parse().map_err(|_| format!("invalid sample count '{}'", spp))?;
+    }
+    if let Some(tile_size) = matches.value_of("tile-size") {
+        scene.tiles.size = tile_size.parse().map_err(|_| format!("invalid tile size '{}'", tile_size))?;
+    }
+    if let Some(tile_order) = matches.value_of("tile-order") {
+        scene.tiles.order = tile_order.parse()?;
+    }
+    Ok(scene)
+}
+
+// a number of seconds, optionally followed by the unit s, m or h
+fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let (value, unit_seconds) = match duration.chars().last() {
+        Some('s') => (&duration[..duration.len() - 1], 1.0),
+        Some('m') => (&duration[..duration.len() - 1], 60.0),
+        Some('h') => (&duration[..duration.len() - 1], 3600.0),
+        _ => (duration, 1.0),
+    };
+    match value.trim().parse::<f64>() {
+        Ok(value) if value >= 0.0 && value.is_finite() => Ok(Duration::from_secs_f64(value * unit_seconds)),
+        _ => Err(format!("invalid duration '{}', expected e.g. 90s, 10m or 2h", duration)),
+    }
+}
+
+// a duration that has to be longer than zero, for intervals and timeouts
+fn parse_positive_duration(duration: &str, name: &str) -> Result<Duration, String> {
+    match parse_duration(duration)? {
+        duration if duration.is_zero() => Err(format!("the {} must be positive", name)),
+        duration => Ok(duration),
+    }
+}
+
+// the x, y, width and height of a crop, separated by commas
+fn parse_region(region: &str) -> Result<[u32; 4], String> {
+    let values = region.split(',')
+        .map(|value| value.trim().parse::<u32>())
+        .collect::<Result<Vec<u32>, _>>()
+        .map_err(|_| format!("invalid region '{}', expected X,Y,W,H", region))?;
+    values.try_into().map_err(|_| format!("invalid region '{}', expected X,Y,W,H", region))
+}
+
+// a single frame, or the first and last frames separated by a dash
+fn parse_frames(frames: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("invalid frames '{}', expected e.g. 1-240", frames);
+    let (first, last) = frames.split_once('-').unwrap_or((frames, frames));
+    let first = first.trim().parse::<u32>().map_err(|_| invalid())?;
+    let last = last.trim().parse::<u32>().map_err(|_| invalid())?;
+    if first > last {
+        return Err(invalid());
+    }
+    Ok((first, last))
+}
+
+// the path with its %d or %0Nd replaced by the frame number, or the frame number on 4 digits added before the extension
+fn frame_path(path: &str, frame: u32) -> String {
+    if let Some((prefix, rest)) = path.split_once('%') {
+        if let Some((width, suffix)) = rest.split_once('d') {
+            if width.is_empty() || width.parse::<usize>().is_ok() {
+                return format!("{}{:0width$}{}", prefix, frame, suffix, width = width.parse().unwrap_or(0));
+            }
+        }
+    }
+    let path = Path::new(path);
+    let stem = path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+    let name = match path.extension() {
+        Some(extension) => format!("{}_{:04}.{}", stem, frame, extension.to_string_lossy()),
+        None => format!("{}_{:04}", stem, frame),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+fn exit_with_error<T>(err: String) -> T {
+    eprintln!("{}", err);
+    std::process::exit(1)
+}
+
+fn save_image(width: u32, height: u32, rgb16: Vec<u16>, path: &str) {
+    let imgbuf = ImageBuffer::<Rgb<u16>, Vec<u16>>::from_vec(width, height, rgb16).unwrap();
+
+    // the format is deduced from the file extension in the path
+    imgbuf.save(path).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("10m"), Ok(Duration::from_secs(600)));
+        assert_eq!(parse_duration("1.5h"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("42"), Ok(Duration::from_secs(42)));
+        assert!(parse_duration("10 minutes").is_err());
+        assert!(parse_duration("-1m").is_err());
+    }
+
+    #[test]
+    fn test_parse_positive_duration() {
+        assert_eq!(parse_positive_duration("0.5", "interval"), Ok(Duration::from_millis(500)));
+        assert!(parse_positive_duration("0s", "interval").is_err());
+        assert!(parse_positive_duration("-1", "interval").is_err());
+        assert!(parse_positive_duration("inf", "interval").is_err());
+        assert!(parse_positive_duration("NaN", "interval").is_err());
+    }
+
+    #[test]
+    fn test_parse_region() {
+        assert_eq!(parse_region("10,20,64,32"), Ok([10, 20, 64, 32]));
+        assert!(parse_region("10,20,64").is_err());
+        assert!(parse_region("10,20,-64,32").is_err());
+    }
+
+    #[test]
+    fn test_parse_frames() {
+        assert_eq!(parse_frames("1-240"), Ok((1, 240)));
+        assert_eq!(parse_frames("12"), Ok((12, 12)));
+        assert!(parse_frames("10-1").is_err());
+        assert!(parse_frames("1-x").is_err());
+    }
+
+    #[test]
+    fn test_frame_path() {
+        assert_eq!(frame_path("out_%04d.png", 7), "out_0007.png");
+        assert_eq!(frame_path("renders/%d.png", 12), "renders/12.png");
+        assert_eq!(frame_path("renders/out.png", 3), "renders/out_0003.png");
+        assert_eq!(frame_path("out", 3), "out_0003");
+    }
+}
@@ -0,0 +1,251 @@
+use serde::{Serialize, Deserialize};
+use serde_yaml::Value;
+
+use super::Scene;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Default)]
+pub enum Interpolation {
+    #[default]
+    Linear,
+    // smooth curve through the keys: cubic bezier segments with handles along the neighbouring keys
+    Bezier,
+}
+
+// a number such as an intensity, or a vector such as a position or a color
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum KeyValue {
+    Scalar(f64),
+    Vector(Vec<f64>),
+}
+
+impl KeyValue {
+    fn components(&self) -> Vec<f64> {
+        match self {
+            KeyValue::Scalar(value) => vec![*value],
+            KeyValue::Vector(values) => values.clone(),
+        }
+    }
+
+    // same kind of value as self, holding the given components
+    fn with_components(&self, components: Vec<f64>) -> KeyValue {
+        match self {
+            KeyValue::Scalar(_) => KeyValue::Scalar(components[0]),
+            KeyValue::Vector(_) => KeyValue::Vector(components),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Keyframe {
+    pub frame: f64,
+    pub value: KeyValue,
+}
+
+// keyframes of one value of the scene file, named by its path, e.g. camera.cam_pos, shapes.0.centre,
+// shapes.2.albedo or distant_lights.1.intensity; sequence items are numbered from 0
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Track {
+    pub target: String,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    pub keys: Vec<Keyframe>,
+}
+
+impl Track {
+    // value at a frame, the first and last keys hold before and after the animated range
+    pub fn value_at(&self, frame: f64) -> KeyValue {
+        let keys = &self.keys;
+        let next = keys.iter().position(|key| key.frame > frame).unwrap_or(keys.len());
+        if next == 0 {
+            return keys[0].value.clone();
+        }
+        if next == keys.len() {
+            return keys[next - 1].value.clone();
+        }
+
+        let (start, end) = (&keys[next - 1], &keys[next]);
+        let span = end.frame - start.frame;
+        let t = (frame - start.frame) / span;
+        let (p0, p1) = (start.value.components(), end.value.components());
+        let components = match self.interpolation {
+            Interpolation::Linear => p0.iter().zip(&p1).map(|(a, b)| a + (b - a) * t).collect(),
+            Interpolation::Bezier => {
+                // slopes per frame at both ends of the segment, from the keys around them
+                let slope = |index: usize| {
+                    let (before, after) = (&keys[index.saturating_sub(1)], &keys[(index + 1).min(keys.len() - 1)]);
+                    let (a, b) = (before.value.components(), after.value.components());
+                    a.iter().zip(&b).map(|(a, b)| (b - a) / (after.frame - before.frame)).collect::<Vec<f64>>()
+                };
+                let (m0, m1) = (slope(next - 1), slope(next));
+                (0..p0.len())
+                    .map(|i| {
+                        let (c0, c1) = (p0[i] + m0[i] * span / 3.0, p1[i] - m1[i] * span / 3.0);
+                        let u = 1.0 - t;
+                        u * u * u * p0[i] + 3.0 * u * u * t * c0 + 3.0 * u * t * t * c1 + t * t * t * p1[i]
+                    })
+                    .collect()
+            }
+        };
+        start.value.with_components(components)
+    }
+
+    fn check(&self) -> Result<(), String> {
+        let first = self.keys.first().ok_or_else(|| format!("the animation of {} has no keys", self.target))?;
+        let size = first.value.components().len();
+        if self.keys.iter().any(|key| key.value.components().len() != size || !key.frame.is_finite()) {
+            return Err(format!("the keys of {} must be of the same size and at finite frames", self.target));
+        }
+        if self.keys.windows(2).any(|keys| keys[0].frame >= keys[1].frame) {
+            return Err(format!("the keys of {} must be in increasing frame order", self.target));
+        }
+        Ok(())
+    }
+}
+
+// scene file whose animation tracks are applied to the yaml before building the scene of a frame,
+// so any number in the file can be animated without the shapes knowing about it
+pub struct Animation {
+    scene_yaml: Value,
+    tracks: Vec<Track>,
+}
+
+impl Animation {
+    pub fn new(scene_yaml: &str) -> Result<Animation, String> {
+        let scene_yaml: Value = serde_yaml::from_str(scene_yaml).map_err(|err| format!("invalid scene: {}", err))?;
+        let tracks: Vec<Track> = match scene_yaml.get("animation") {
+            Some(tracks) => serde_yaml::from_value(tracks.clone()).map_err(|err| format!("invalid animation: {}", err))?,
+            None => Vec::new(),
+        };
+
+        let mut checked_yaml = scene_yaml.clone();
+        for track in &tracks {
+            track.check()?;
+            set_value(&mut checked_yaml, &track.target, &track.keys[0].value)?;
+        }
+        Ok(Animation { scene_yaml, tracks })
+    }
+
+    pub fn scene_at(&self, frame: f64) -> Result<Scene, String> {
+        let mut scene_yaml = self.scene_yaml.clone();
+        for track in &self.tracks {
+            set_value(&mut scene_yaml, &track.target, &track.value_at(frame))?;
+        }
+        serde_yaml::from_value(scene_yaml).map_err(|err| format!("invalid scene at frame {}: {}", frame, err))
+    }
+}
+
+// replaces the value at a dotted path; the last key of a mapping may be missing, for parameters left to their default
+fn set_value(yaml: &mut Value, target: &str, value: &KeyValue) -> Result<(), String> {
+    let not_found = || format!("the animated value {} is not in the scene", target);
+    let mut node = yaml;
+    let mut segments = target.split('.').peekable();
+    while let Some(segment) = segments.next() {
+        let last = segments.peek().is_none();
+        node = match node {
+            Value::Sequence(items) => {
+                let index = segment.parse::<usize>().map_err(|_| not_found())?;
+                items.get_mut(index).ok_or_else(not_found)?
+            }
+            Value::Mapping(mapping) => {
+                let key = Value::String(segment.to_string());
+                if last && !mapping.contains_key(&key) {
+                    mapping.insert(key.clone(), Value::Null);
+                }
+                mapping.get_mut(&key).ok_or_else(not_found)?
+            }
+            _ => return Err(not_found()),
+        };
+    }
+    *node = serde_yaml::to_value(value).map_err(|err| err.to_string())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENE: &str = "
+camera:
+  cam_orient: [0.0, 0.0, 1.0]
+  cam_pos: [0.0, 0.0, 0.0]
+  up_vec: [0.0, 1.0, 0.0]
+shapes:
+  - type: Sphere
+    centre: [0.0, 0.0, 5.0]
+    r: 1.0
+    color: [1.0, 1.0, 1.0]
+    emissive_color: [0.0, 0.0, 0.0]
+    albedo: 0.5
+    reflectivity: 0.0
+distant_lights:
+  - direction: [0.0, -1.0, 0.0]
+    intensity: 1.0
+animation:
+  - target: camera.cam_pos
+    keys:
+      - frame: 1
+        value: [0.0, 0.0, 0.0]
+      - frame: 11
+        value: [1.0, 0.0, -2.0]
+  - target: distant_lights.0.intensity
+    interpolation: Bezier
+    keys:
+      - frame: 1
+        value: 1.0
+      - frame: 5
+        value: 3.0
+      - frame: 9
+        value: 1.0
+  - target: shapes.0.bump_scale
+    keys:
+      - frame: 1
+        value: 2.0
+";
+
+    fn track(interpolation: Interpolation, keys: &[(f64, f64)]) -> Track {
+        let keys = keys.iter().map(|(frame, value)| Keyframe { frame: *frame, value: KeyValue::Scalar(*value) }).collect();
+        Track { target: String::from("a"), interpolation, keys }
+    }
+
+    #[test]
+    fn test_linear_interpolation() {
+        let track = track(Interpolation::Linear, &[(0.0, 0.0), (10.0, 1.0), (20.0, -1.0)]);
+        assert_eq!(track.value_at(-5.0), KeyValue::Scalar(0.0));
+        assert_eq!(track.value_at(5.0), KeyValue::Scalar(0.5));
+        assert_eq!(track.value_at(15.0), KeyValue::Scalar(0.0));
+        assert_eq!(track.value_at(30.0), KeyValue::Scalar(-1.0));
+    }
+
+    #[test]
+    fn test_bezier_interpolation_goes_through_keys() {
+        let track = track(Interpolation::Bezier, &[(0.0, 0.0), (10.0, 1.0), (20.0, 0.0), (30.0, 3.0)]);
+        assert_eq!(track.value_at(10.0), KeyValue::Scalar(1.0));
+        assert_eq!(track.value_at(20.0), KeyValue::Scalar(0.0));
+        // the curve flattens at the peak instead of turning sharply at the key
+        let KeyValue::Scalar(value) = track.value_at(9.0) else { panic!() };
+        assert!(value > 0.9 && value < 1.0);
+        let KeyValue::Scalar(value) = track.value_at(11.0) else { panic!() };
+        assert!(value > 0.9);
+    }
+
+    #[test]
+    fn test_scene_at_frame() {
+        let animation = Animation::new(SCENE).unwrap();
+        let scene = animation.scene_at(6.0).unwrap();
+        assert_eq!(scene.camera.placement().cam_pos, na::Vector3::new(0.5, 0.0, -1.0));
+        assert_eq!(scene.shapes[0].get_material().bump_scale, 2.0);
+        assert_eq!(animation.scene_at(5.0).unwrap().distant_lights[0].intensity, 3.0);
+        assert_eq!(animation.scene_at(20.0).unwrap().camera.placement().cam_pos, na::Vector3::new(1.0, 0.0, -2.0));
+    }
+
+    #[test]
+    fn test_invalid_animations() {
+        let missing_target = SCENE.replace("distant_lights.0.intensity", "distant_lights.3.intensity");
+        assert!(Animation::new(&missing_target).is_err());
+        let unordered = SCENE.replace("frame: 5", "frame: 12");
+        assert!(Animation::new(&unordered).is_err());
+        let mixed_sizes = SCENE.replace("value: [1.0, 0.0, -2.0]", "value: 2.0");
+        assert!(Animation::new(&mixed_sizes).is_err());
+    }
+}
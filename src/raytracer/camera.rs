@@ -1,10 +1,570 @@
-use serde::{Serialize, Deserialize};
-use na::Vector3;
-// use crate::coordinates::*;
+use serde::{Serialize, Deserialize, Deserializer};
+use serde::de::Error;
+use na::{Rotation3, Unit, Vector3};
 
-#[derive(Serialize, Deserialize)]
-pub struct Camera {
-    pub cam_orient: Vector3<f64>,
+use super::ray::Ray;
+use super::exposure::{Exposure, Lens};
+use super::samplers::Sampler;
+
+use std::convert::TryFrom;
+use std::f64::consts::PI;
+
+fn default_perspective_fov() -> f64 {
+    45.0
+}
+
+// width of a full frame sensor in millimeters, focal lengths are given for it unless the scene says otherwise
+fn default_sensor_width() -> f64 {
+    36.0
+}
+
+fn default_fisheye_fov() -> f64 {
+    180.0
+}
+
+// average human interpupillary distance, in meters
+fn default_interpupillary_distance() -> f64 {
+    0.064
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Default)]
+pub enum StereoLayout {
+    // left eye in the left half of the frame
+    #[default]
+    SideBySide,
+    // left eye in the top half of the frame
+    TopBottom,
+}
+
+// renders both eyes in one frame, each eye getting half of it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Stereo {
+    // distance between the eyes in scene units, the camera position is half way between them
+    #[serde(default = "default_interpupillary_distance")]
+    pub interpupillary_distance: f64,
+    #[serde(default)]
+    pub layout: StereoLayout,
+}
+
+impl Stereo {
+    // point of the eye's own frame, the size of that frame and the eye offset to the right of the camera position;
+    // with an odd width (or height), the right eye gets the extra column (or row)
+    fn split(&self, x: f64, y: f64, (dimx, dimy): (u32, u32)) -> (f64, f64, (u32, u32), f64) {
+        let half_distance = self.interpupillary_distance / 2.0;
+        match self.layout {
+            StereoLayout::SideBySide => {
+                let left_width = dimx / 2;
+                // the pixel decides the eye, the jitter may go a bit past the pixel's centre
+                if x.round() < left_width as f64 {
+                    (x, y, (left_width, dimy), -half_distance)
+                } else {
+                    (x - left_width as f64, y, (dimx - left_width, dimy), half_distance)
+                }
+            }
+            StereoLayout::TopBottom => {
+                let top_height = dimy / 2;
+                if y.round() < top_height as f64 {
+                    (x, y, (dimx, top_height), -half_distance)
+                } else {
+                    (x, y - top_height as f64, (dimx, dimy - top_height), half_distance)
+                }
+            }
+        }
+    }
+}
+
+fn default_up_vec() -> Vector3<f64> {
+    Vector3::new(0.0, 1.0, 0.0)
+}
+
+// position, orientation, shutter and lens shared by every projection, flattened into the camera in scene files
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "CameraPlacementConfig")]
+pub struct CameraPlacement {
     pub cam_pos: Vector3<f64>,
+    // the camera looks either along cam_orient or towards look_at, whose lengths do not matter
+    pub cam_orient: Option<Vector3<f64>>,
+    pub look_at: Option<Vector3<f64>>,
+    // only needs to be roughly up, it is straightened to be perpendicular to the view direction
     pub up_vec: Vector3<f64>,
-}
\ No newline at end of file
+    // counterclockwise rotation of the camera around its view direction in degrees, the image turns clockwise
+    pub roll: f64,
+    // times the shutter opens and closes, camera rays are spread over them for motion blur
+    pub shutter: [f64; 2],
+    pub stereo: Option<Stereo>,
+    pub exposure: Exposure,
+    pub lens: Lens,
+    // unit right, up and forward directions
+    #[serde(skip)]
+    basis: [Vector3<f64>; 3],
+}
+
+#[derive(Deserialize)]
+struct CameraPlacementConfig {
+    cam_pos: Vector3<f64>,
+    #[serde(default)]
+    cam_orient: Option<Vector3<f64>>,
+    #[serde(default)]
+    look_at: Option<Vector3<f64>>,
+    #[serde(default = "default_up_vec")]
+    up_vec: Vector3<f64>,
+    #[serde(default)]
+    roll: f64,
+    #[serde(default)]
+    shutter: [f64; 2],
+    #[serde(default)]
+    stereo: Option<Stereo>,
+    #[serde(default)]
+    exposure: Exposure,
+    #[serde(default)]
+    lens: Lens,
+}
+
+impl TryFrom<CameraPlacementConfig> for CameraPlacement {
+    type Error = String;
+
+    fn try_from(config: CameraPlacementConfig) -> Result<Self, Self::Error> {
+        let mut placement = CameraPlacement::new(config.cam_pos, config.cam_orient, config.look_at, config.up_vec, config.roll)?;
+        placement.shutter = config.shutter;
+        placement.stereo = config.stereo;
+        placement.exposure = config.exposure;
+        placement.lens = config.lens;
+        Ok(placement)
+    }
+}
+
+impl CameraPlacement {
+    pub fn new(
+        cam_pos: Vector3<f64>,
+        cam_orient: Option<Vector3<f64>>,
+        look_at: Option<Vector3<f64>>,
+        up_vec: Vector3<f64>,
+        roll: f64
+    ) -> Result<CameraPlacement, String> {
+        let forward = match (cam_orient, look_at) {
+            (Some(_), Some(_)) => return Err(String::from("the camera needs either cam_orient or look_at, not both")),
+            (Some(cam_orient), None) => cam_orient,
+            (None, Some(look_at)) => look_at - cam_pos,
+            (None, None) => return Err(String::from("the camera needs cam_orient or look_at")),
+        };
+        let finite = |vector: &Vector3<f64>| vector.iter().all(|value| value.is_finite());
+        if !finite(&cam_pos) || !finite(&forward) || !finite(&up_vec) || !roll.is_finite() {
+            return Err(String::from("the camera placement must be finite"));
+        }
+        if forward.norm() < 1e-9 {
+            return Err(String::from(if look_at.is_some() { "look_at is at the camera position" } else { "cam_orient is zero" }));
+        }
+        let forward = forward.normalize();
+        // same handedness as before the basis was normalized: right is up_vec x cam_orient
+        let right = up_vec.cross(&forward);
+        if right.norm() < 1e-9 * up_vec.norm() || up_vec.norm() < 1e-9 {
+            return Err(String::from("up_vec is zero or parallel to the view direction"));
+        }
+        let right = right.normalize();
+        let up = forward.cross(&right);
+
+        let rotation = Rotation3::from_axis_angle(&Unit::new_unchecked(forward), roll.to_radians());
+        let basis = [rotation * right, rotation * up, forward];
+        Ok(CameraPlacement { cam_pos, cam_orient, look_at, up_vec, roll, shutter: [0.0, 0.0], stereo: None, exposure: Exposure::default(), lens: Lens::default(), basis })
+    }
+
+    // unit right, up and forward directions of the camera
+    pub fn basis(&self) -> [Vector3<f64>; 3] {
+        self.basis
+    }
+
+    // point or direction given along the right, up and forward directions of the camera
+    fn to_scene(&self, camera_space: &Vector3<f64>) -> Vector3<f64> {
+        let [right, up, forward] = self.basis;
+        right * camera_space.x + up * camera_space.y + forward * camera_space.z
+    }
+}
+
+// projection of the scene onto the frame
+#[typetag::serde(tag = "type")]
+pub trait Camera: Sync + std::fmt::Debug {
+    fn placement(&self) -> &CameraPlacement;
+
+    // ray through a point of the frame given in pixels from the centre of the top left pixel, x going right
+    // and y going down, seen from an eye that distance to the right of the camera position (left if negative);
+    // None where the projection does not cover the frame, like outside the fisheye circle
+    fn generate_ray(&self, x: f64, y: f64, frame_size: (u32, u32), eye: f64) -> Option<Ray>;
+
+    // ray through a point of the frame, which holds both eyes with stereo, and the factor of the light it brings
+    // back for the exposure and vignetting
+    fn frame_ray(&self, x: f64, y: f64, frame_size: (u32, u32)) -> Option<(Ray, Vector3<f64>)> {
+        let placement = self.placement();
+        let (x, y, eye_frame_size, eye) = match &placement.stereo {
+            Some(stereo) => stereo.split(x, y, frame_size),
+            None => (x, y, frame_size, 0.0),
+        };
+        let (x, y, transmitted) = placement.lens.apply(x, y, eye_frame_size)?;
+        let ray = self.generate_ray(x, y, eye_frame_size, eye)?;
+        Some((ray, placement.exposure.scale() * transmitted))
+    }
+
+    // time of a camera ray, uniform in the shutter interval; an instant shutter takes no random value,
+    // so scenes without motion blur get the same samples as before
+    fn sample_time(&self, sampler: &mut dyn Sampler) -> f64 {
+        let [open, close] = self.placement().shutter;
+        if close > open {
+            open + (close - open) * sampler.next_1d()
+        } else {
+            open
+        }
+    }
+}
+
+// scene files from before the other projections have no camera type, their camera is a perspective one
+pub fn deserialize_camera<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Box<dyn Camera>, D::Error> {
+    let mut camera = serde_yaml::Value::deserialize(deserializer)?;
+    if let serde_yaml::Value::Mapping(mapping) = &mut camera {
+        let type_key = serde_yaml::Value::String(String::from("type"));
+        if !mapping.contains_key(&type_key) {
+            mapping.insert(type_key, serde_yaml::Value::String(String::from("Perspective")));
+        }
+    }
+    serde_yaml::from_value(camera).map_err(D::Error::custom)
+}
+
+// pinhole camera, fov is the horizontal field of view in degrees
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "PerspectiveConfig")]
+pub struct Perspective {
+    #[serde(flatten)]
+    pub placement: CameraPlacement,
+    pub fov: f64,
+}
+
+// the field of view is given either directly or as the focal length of a lens in front of a sensor, in millimeters
+#[derive(Deserialize)]
+struct PerspectiveConfig {
+    #[serde(flatten)]
+    placement: CameraPlacement,
+    #[serde(default)]
+    fov: Option<f64>,
+    #[serde(default)]
+    focal_length: Option<f64>,
+    #[serde(default)]
+    sensor_width: Option<f64>,
+}
+
+impl TryFrom<PerspectiveConfig> for Perspective {
+    type Error = String;
+
+    fn try_from(config: PerspectiveConfig) -> Result<Self, Self::Error> {
+        let fov = match (config.fov, config.focal_length, config.sensor_width) {
+            (Some(_), Some(_), _) => return Err(String::from("the camera needs either fov or focal_length, not both")),
+            (_, None, Some(_)) => return Err(String::from("sensor_width needs a focal_length")),
+            (fov, None, None) => fov.unwrap_or_else(default_perspective_fov),
+            (None, Some(focal_length), sensor_width) => {
+                let sensor_width = sensor_width.unwrap_or_else(default_sensor_width);
+                let positive = |length: f64| length > 0.0 && length.is_finite();
+                if !positive(focal_length) || !positive(sensor_width) {
+                    return Err(String::from("focal_length and sensor_width must be positive"));
+                }
+                (2.0 * (sensor_width / (2.0 * focal_length)).atan()).to_degrees()
+            }
+        };
+        Ok(Perspective { placement: config.placement, fov })
+    }
+}
+
+#[typetag::serde]
+impl Camera for Perspective {
+    fn placement(&self) -> &CameraPlacement {
+        &self.placement
+    }
+
+    // the frame is a screen at unit distance in front of the camera, the eyes have parallel views
+    fn generate_ray(&self, x: f64, y: f64, (dimx, dimy): (u32, u32), eye: f64) -> Option<Ray> {
+        let [b, v, t] = self.placement.basis;
+        let g_x: f64 = (self.fov.to_radians() / 2.0).tan();
+        let g_y: f64 = g_x * (dimy as f64 / dimx as f64);
+
+        let eye_pos = self.placement.cam_pos + self.placement.to_scene(&Vector3::new(eye, 0.0, 0.0));
+        let top_left = eye_pos + t - g_x * b + g_y * v;
+        let q_x = ((2.0 * g_x) / (dimx as f64 - 1.0)) * b;
+        let q_y = ((2.0 * g_y) / (dimy as f64 - 1.0)) * v;
+        let pos_pix = top_left + q_x * x - q_y * y;
+        Some(Ray::new_from_points(&eye_pos, &pos_pix))
+    }
+}
+
+// parallel rays along cam_orient, width is the width of the frame in scene units
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Orthographic {
+    #[serde(flatten)]
+    pub placement: CameraPlacement,
+    pub width: f64,
+}
+
+#[typetag::serde]
+impl Camera for Orthographic {
+    fn placement(&self) -> &CameraPlacement {
+        &self.placement
+    }
+
+    fn generate_ray(&self, x: f64, y: f64, (dimx, dimy): (u32, u32), eye: f64) -> Option<Ray> {
+        let half_width = self.width / 2.0;
+        let half_height = half_width * dimy as f64 / dimx as f64;
+        let offset = Vector3::new(
+            (2.0 * x / (dimx as f64 - 1.0) - 1.0) * half_width + eye,
+            (1.0 - 2.0 * y / (dimy as f64 - 1.0)) * half_height,
+            0.0,
+        );
+        let origin = self.placement.cam_pos + self.placement.to_scene(&offset);
+        Some(Ray::new_from_origine_and_direction(&origin, &self.placement.to_scene(&Vector3::z())))
+    }
+}
+
+// equidistant fisheye: the angle to cam_orient grows linearly with the distance to the centre of the frame,
+// up to fov / 2 degrees on the circle inscribed in the frame, the rest of the frame stays black
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Fisheye {
+    #[serde(flatten)]
+    pub placement: CameraPlacement,
+    #[serde(default = "default_fisheye_fov")]
+    pub fov: f64,
+}
+
+#[typetag::serde]
+impl Camera for Fisheye {
+    fn placement(&self) -> &CameraPlacement {
+        &self.placement
+    }
+
+    fn generate_ray(&self, x: f64, y: f64, (dimx, dimy): (u32, u32), eye: f64) -> Option<Ray> {
+        let radius = dimx.min(dimy) as f64 / 2.0;
+        let dx = (x - (dimx as f64 - 1.0) / 2.0) / radius;
+        let dy = ((dimy as f64 - 1.0) / 2.0 - y) / radius;
+        let r = dx.hypot(dy);
+        if r > 1.0 {
+            return None;
+        }
+        let theta = r * self.fov.to_radians() / 2.0;
+        let (sin_phi, cos_phi) = if r > 0.0 { (dy / r, dx / r) } else { (0.0, 1.0) };
+        let direction = Vector3::new(theta.sin() * cos_phi, theta.sin() * sin_phi, theta.cos());
+        let eye_pos = self.placement.cam_pos + self.placement.to_scene(&Vector3::new(eye, 0.0, 0.0));
+        Some(Ray::new_from_origine_and_direction(&eye_pos, &self.placement.to_scene(&direction)))
+    }
+}
+
+// full 360 by 180 degrees panorama, longitude along x with cam_orient in the middle and latitude along y;
+// in stereo the eyes turn with the longitude, each column is seen from eyes perpendicular to it (omnidirectional stereo)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Equirectangular {
+    #[serde(flatten)]
+    pub placement: CameraPlacement,
+}
+
+#[typetag::serde]
+impl Camera for Equirectangular {
+    fn placement(&self) -> &CameraPlacement {
+        &self.placement
+    }
+
+    fn generate_ray(&self, x: f64, y: f64, (dimx, dimy): (u32, u32), eye: f64) -> Option<Ray> {
+        let longitude = ((x + 0.5) / dimx as f64 - 0.5) * 2.0 * PI;
+        let latitude = (0.5 - (y + 0.5) / dimy as f64) * PI;
+        let direction = Vector3::new(latitude.cos() * longitude.sin(), latitude.sin(), latitude.cos() * longitude.cos());
+        let eye_offset = Vector3::new(longitude.cos(), 0.0, -longitude.sin()) * eye;
+        let eye_pos = self.placement.cam_pos + self.placement.to_scene(&eye_offset);
+        Some(Ray::new_from_origine_and_direction(&eye_pos, &self.placement.to_scene(&direction)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f64 = 1e-10;
+
+    // at the origin, looking along z
+    fn create_placement() -> CameraPlacement {
+        CameraPlacement::new(Vector3::zeros(), Some(Vector3::new(0.0, 0.0, 1.0)), None, Vector3::new(0.0, 1.0, 0.0), 0.0).unwrap()
+    }
+
+    fn assert_direction(ray: &Ray, direction: Vector3<f64>) {
+        assert!((ray.unit_vec - direction.normalize()).norm() < EPSILON, "{:?} is not along {:?}", ray.unit_vec, direction);
+    }
+
+    #[test]
+    fn test_camera_without_type_is_perspective() {
+        let yaml = "cam_orient: [0.0, 0.0, 1.0]\ncam_pos: [0.0, 0.0, 0.0]\nup_vec: [0.0, 1.0, 0.0]\n";
+        let camera = deserialize_camera(serde_yaml::Deserializer::from_str(yaml)).unwrap();
+        assert_eq!(camera.placement().basis()[2], Vector3::new(0.0, 0.0, 1.0));
+        let ray = camera.generate_ray(0.0, 0.0, (3, 3), 0.0).unwrap();
+        // the corners of the default 45 degrees field of view
+        let g = (22.5_f64).to_radians().tan();
+        assert_direction(&ray, Vector3::new(-g, g, 1.0));
+
+        let fisheye = deserialize_camera(serde_yaml::Deserializer::from_str(&format!("type: Fisheye\n{}", yaml))).unwrap();
+        assert!(fisheye.generate_ray(0.0, 0.0, (5, 5), 0.0).is_none());
+    }
+
+    #[test]
+    fn test_placement_is_orthonormalized() {
+        // a long view direction and a slanted up vector give the same frame as unit perpendicular ones
+        let placement = CameraPlacement::new(Vector3::zeros(), Some(Vector3::new(0.0, 0.0, 3.0)), None, Vector3::new(0.0, 2.0, 1.0), 0.0).unwrap();
+        assert_eq!(placement.basis(), create_placement().basis());
+
+        let camera = Perspective { placement, fov: 90.0 };
+        assert_direction(&camera.generate_ray(0.0, 0.0, (3, 3), 0.0).unwrap(), Vector3::new(-1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_look_at_and_roll() {
+        let placement = CameraPlacement::new(Vector3::new(1.0, 0.0, 0.0), None, Some(Vector3::new(1.0, 0.0, 5.0)), Vector3::y(), 90.0).unwrap();
+        let [right, up, forward] = placement.basis();
+        assert!((forward - Vector3::z()).norm() < EPSILON);
+        // rolled counterclockwise, the top of the camera leans to the left
+        assert!((up + Vector3::x()).norm() < EPSILON);
+        assert!((right - Vector3::y()).norm() < EPSILON);
+    }
+
+    #[test]
+    fn test_degenerate_placements_are_rejected() {
+        let new = |cam_orient, look_at, up_vec| CameraPlacement::new(Vector3::zeros(), cam_orient, look_at, up_vec, 0.0);
+        assert!(new(Some(Vector3::z()), None, Vector3::z() * 2.0).unwrap_err().contains("parallel"));
+        assert!(new(Some(Vector3::zeros()), None, Vector3::y()).is_err());
+        assert!(new(None, Some(Vector3::zeros()), Vector3::y()).unwrap_err().contains("look_at"));
+        assert!(new(None, None, Vector3::y()).is_err());
+        assert!(new(Some(Vector3::z()), Some(Vector3::z()), Vector3::y()).is_err());
+        assert!(new(Some(Vector3::new(f64::NAN, 0.0, 1.0)), None, Vector3::y()).is_err());
+
+        let yaml = "camera:\n  look_at: [0.0, 1.0, 0.0]\n  cam_pos: [0.0, 0.0, 0.0]\nshapes: []\ndistant_lights: []\n";
+        let err = serde_yaml::from_str::<crate::raytracer::Scene>(yaml).err().unwrap().to_string();
+        assert!(err.contains("parallel"), "{}", err);
+    }
+
+    #[test]
+    fn test_orthographic_rays_are_parallel() {
+        let camera = Orthographic { placement: create_placement(), width: 4.0 };
+        let top_left = camera.generate_ray(0.0, 0.0, (5, 3), 0.0).unwrap();
+        let bottom_right = camera.generate_ray(4.0, 2.0, (5, 3), 0.0).unwrap();
+        assert_direction(&top_left, Vector3::z());
+        assert_direction(&bottom_right, Vector3::z());
+        assert!((top_left.origin - Vector3::new(-2.0, 1.2, 0.0)).norm() < EPSILON);
+        assert!((bottom_right.origin - Vector3::new(2.0, -1.2, 0.0)).norm() < EPSILON);
+    }
+
+    #[test]
+    fn test_fisheye_angles_grow_with_the_radius() {
+        let camera = Fisheye { placement: create_placement(), fov: 180.0 };
+        assert_direction(&camera.generate_ray(2.0, 2.0, (5, 5), 0.0).unwrap(), Vector3::z());
+        // the edge of the circle is 90 degrees away from the centre, half way is 45 degrees
+        assert_direction(&camera.generate_ray(4.5, 2.0, (5, 5), 0.0).unwrap(), Vector3::x());
+        assert_direction(&camera.generate_ray(2.0, 0.75, (5, 5), 0.0).unwrap(), Vector3::new(0.0, 1.0, 1.0));
+        assert!(camera.generate_ray(4.4, 4.4, (5, 5), 0.0).is_none());
+    }
+
+    #[test]
+    fn test_equirectangular_covers_the_sphere() {
+        let camera = Equirectangular { placement: create_placement() };
+        let frame = (8, 4);
+        // pixel edges, from the frame centre
+        assert_direction(&camera.generate_ray(3.5, 1.5, frame, 0.0).unwrap(), Vector3::z());
+        assert_direction(&camera.generate_ray(5.5, 1.5, frame, 0.0).unwrap(), Vector3::x());
+        assert_direction(&camera.generate_ray(-0.5, 1.5, frame, 0.0).unwrap(), -Vector3::z());
+        assert_direction(&camera.generate_ray(3.5, -0.5, frame, 0.0).unwrap(), Vector3::y());
+    }
+
+    #[test]
+    fn test_side_by_side_eyes_look_in_parallel() {
+        let mut placement = create_placement();
+        placement.stereo = Some(Stereo { interpupillary_distance: 0.2, layout: StereoLayout::SideBySide });
+        let camera = Perspective { placement, fov: 90.0 };
+        // the centre of each eye's half of a 10x5 frame
+        let left = camera.frame_ray(2.0, 2.0, (10, 5)).unwrap().0;
+        let right = camera.frame_ray(7.0, 2.0, (10, 5)).unwrap().0;
+        assert!((left.origin - Vector3::new(-0.1, 0.0, 0.0)).norm() < EPSILON);
+        assert!((right.origin - Vector3::new(0.1, 0.0, 0.0)).norm() < EPSILON);
+        assert_direction(&left, Vector3::z());
+        assert_direction(&right, Vector3::z());
+    }
+
+    #[test]
+    fn test_side_by_side_odd_width_gives_the_extra_column_to_the_right_eye() {
+        let mut placement = create_placement();
+        placement.stereo = Some(Stereo { interpupillary_distance: 0.2, layout: StereoLayout::SideBySide });
+        let stereo = placement.stereo.clone().unwrap();
+        // the left eye has columns 0 to 4 of an 11 pixel wide frame, the right eye columns 5 to 10
+        assert_eq!(stereo.split(4.0, 2.0, (11, 5)), (4.0, 2.0, (5, 5), -0.1));
+        assert_eq!(stereo.split(10.0, 2.0, (11, 5)), (5.0, 2.0, (6, 5), 0.1));
+
+        let camera = Perspective { placement, fov: 90.0 };
+        // the centre of each eye's part of the frame looks straight ahead
+        assert_direction(&camera.frame_ray(2.0, 2.0, (11, 5)).unwrap().0, Vector3::z());
+        assert_direction(&camera.frame_ray(7.5, 2.0, (11, 5)).unwrap().0, Vector3::z());
+    }
+
+    #[test]
+    fn test_exposure_and_vignetting_weight_the_rays() {
+        let yaml = "
+type: Perspective
+cam_pos: [0.0, 0.0, 0.0]
+cam_orient: [0.0, 0.0, 1.0]
+exposure:
+  iso: 400
+stereo:
+  interpupillary_distance: 0.2
+lens:
+  vignetting: 0.5
+";
+        let camera: Box<dyn Camera> = serde_yaml::from_str(yaml).unwrap();
+        // vignetting is centred on each eye's half of the frame
+        let (_, centre) = camera.frame_ray(7.0, 2.0, (10, 5)).unwrap();
+        assert!((centre - Vector3::from_element(4.0)).norm() < EPSILON);
+        let (_, corner) = camera.frame_ray(5.0, 0.0, (10, 5)).unwrap();
+        assert!((corner - Vector3::from_element(2.0)).norm() < EPSILON);
+
+        assert!(serde_yaml::from_str::<Box<dyn Camera>>(&yaml.replace("0.5", "1.5")).is_err());
+    }
+
+    #[test]
+    fn test_omnidirectional_stereo_eyes_turn_with_the_columns() {
+        let mut placement = create_placement();
+        placement.stereo = Some(Stereo { interpupillary_distance: 0.2, layout: StereoLayout::TopBottom });
+        let camera = Equirectangular { placement };
+        let frame = (8, 8);
+        // looking right from the top (left eye) half, the left eye is in front of the camera
+        let left = camera.frame_ray(5.5, 1.5, frame).unwrap().0;
+        assert_direction(&left, Vector3::x());
+        assert!((left.origin - Vector3::new(0.0, 0.0, 0.1)).norm() < EPSILON);
+        let right = camera.frame_ray(5.5, 5.5, frame).unwrap().0;
+        assert!((right.origin - Vector3::new(0.0, 0.0, -0.1)).norm() < EPSILON);
+        assert!(right.origin.dot(&right.unit_vec).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_focal_length_sets_the_fov() {
+        let yaml = "
+type: Perspective
+cam_pos: [0.0, 0.0, 0.0]
+cam_orient: [0.0, 0.0, 1.0]
+focal_length: 18.0
+";
+        let fov = |yaml: &str| serde_yaml::from_str::<Perspective>(yaml).map(|camera| camera.fov);
+        assert!((fov(yaml).unwrap() - 90.0).abs() < EPSILON);
+        assert!((fov(&format!("{}sensor_width: 24.0\n", yaml)).unwrap() - 2.0 * (24.0f64 / 36.0).atan().to_degrees()).abs() < EPSILON);
+        assert!((fov(&yaml.replace("focal_length: 18.0", "")).unwrap() - 45.0).abs() < EPSILON);
+
+        assert!(fov(&format!("{}fov: 60.0\n", yaml)).is_err());
+        assert!(fov(&yaml.replace("18.0", "0.0")).is_err());
+        assert!(fov(&yaml.replace("18.0", "-18.0")).is_err());
+        assert!(fov(&format!("{}sensor_width: 0.0\n", yaml)).is_err());
+        assert!(fov(&yaml.replace("focal_length: 18.0", "sensor_width: 24.0")).is_err());
+    }
+
+    #[test]
+    fn test_instant_shutter_takes_no_sample() {
+        let mut sampler = crate::raytracer::samplers::independent::IndependentSampler::new(0, 0);
+        let mut camera = Perspective { placement: create_placement(), fov: 45.0 };
+        camera.placement.shutter = [0.5, 0.5];
+        assert_eq!(camera.sample_time(&mut sampler), 0.5);
+        camera.placement.shutter = [0.0, 2.0];
+        let time = camera.sample_time(&mut sampler);
+        assert!((0.0..2.0).contains(&time));
+    }
+}
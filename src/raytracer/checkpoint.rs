@@ -0,0 +1,227 @@
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use serde_yaml::Value;
+
+use super::film::{Film, PixelStats};
+use super::samplers::hash;
+use super::scene::Scene;
+
+// "RTCHECKPOINT" and a version byte, then little endian values: the scene hash and the seed as u64,
+// the width, height, origin and frame size of the film as u32, and the pixels in row order as encoded by PixelStats
+const CHECKPOINT_MAGIC: &[u8] = b"RTCHECKPOINT";
+const CHECKPOINT_VERSION: u8 = 2;
+const HEADER_SIZE: usize = 12 + 1 + 8 + 8 + 6 * 4;
+
+// state of an unfinished render; samplers only depend on the seed, the pixel and the sample index,
+// so the sample counts of the film are all the sampler state needed to go on with the same samples
+#[derive(Debug)]
+pub struct Checkpoint {
+    pub scene_hash: u64,
+    pub seed: u64,
+    pub film: Film,
+}
+
+impl Checkpoint {
+    // written next to the path then renamed over it, so that killing the render never leaves a truncated checkpoint
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let partial_path = path.with_extension("partial");
+        fs::write(&partial_path, self.to_bytes())
+            .and_then(|_| fs::rename(&partial_path, path))
+            .map_err(|err| format!("cannot write checkpoint {}: {}", path.display(), err))
+    }
+
+    pub fn load(path: &Path) -> Result<Checkpoint, String> {
+        let bytes = fs::read(path).map_err(|err| format!("cannot read checkpoint {}: {}", path.display(), err))?;
+        Checkpoint::from_bytes(&bytes).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    // a checkpoint can only be resumed by the render it was saved from
+    pub fn check_resumable(&self, scene_hash: u64, seed: u64, film: &Film) -> Result<(), String> {
+        if self.scene_hash != scene_hash {
+            return Err(String::from("the scene changed since the checkpoint was saved"));
+        }
+        if self.seed != seed {
+            return Err(format!("the checkpoint was rendered with seed {}, not {}", self.seed, seed));
+        }
+        if self.film.frame_size != film.frame_size {
+            return Err(format!("the checkpoint frame is {:?}, not {:?}", self.film.frame_size, film.frame_size));
+        }
+        if (self.film.origin, self.film.width, self.film.height) != (film.origin, film.width, film.height) {
+            return Err(String::from("the checkpoint was rendered for another region"));
+        }
+        Ok(())
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + self.film.pixels.len() * PixelStats::ENCODED_SIZE);
+        bytes.extend_from_slice(CHECKPOINT_MAGIC);
+        bytes.push(CHECKPOINT_VERSION);
+        bytes.extend_from_slice(&self.scene_hash.to_le_bytes());
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        bytes.extend_from_slice(&self.film.width.to_le_bytes());
+        bytes.extend_from_slice(&self.film.height.to_le_bytes());
+        for value in [self.film.origin.0, self.film.origin.1, self.film.frame_size.0, self.film.frame_size.1] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        for pixel in &self.film.pixels {
+            pixel.write_bytes(&mut bytes);
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Checkpoint, String> {
+        if !bytes.starts_with(CHECKPOINT_MAGIC) {
+            return Err(String::from("not a render checkpoint"));
+        }
+        if bytes.len() < HEADER_SIZE {
+            return Err(String::from("truncated checkpoint header"));
+        }
+        if bytes[CHECKPOINT_MAGIC.len()] != CHECKPOINT_VERSION {
+            return Err(format!("unsupported checkpoint version {}", bytes[CHECKPOINT_MAGIC.len()]));
+        }
+        let u64_at = |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+        let u32_at = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+
+        let (scene_hash, seed, width, height) = (u64_at(13), u64_at(21), u32_at(29), u32_at(33));
+        let (origin, frame_size) = ((u32_at(37), u32_at(41)), (u32_at(45), u32_at(49)));
+        let pixel_count = width as usize * height as usize;
+        let expected_size = HEADER_SIZE + pixel_count * PixelStats::ENCODED_SIZE;
+        if bytes.len() != expected_size {
+            return Err(format!("checkpoint of {}x{} pixels should have {} bytes, got {}", width, height, expected_size, bytes.len()));
+        }
+
+        let pixels = bytes[HEADER_SIZE..].chunks_exact(PixelStats::ENCODED_SIZE).map(PixelStats::read_bytes).collect();
+        Ok(Checkpoint { scene_hash, seed, film: Film { width, height, origin, frame_size, pixels } })
+    }
+}
+
+// hash of the scene as yaml, command line overrides included, and of the size and modification time of the files
+// it refers to (textures, grids); the tiles and the time limit are left out so that a checkpointed render can be
+// resumed with other tiles or more time
+pub fn scene_hash(scene: &Scene) -> Result<u64, String> {
+    let mut value = serde_yaml::to_value(scene).map_err(|err| format!("cannot serialize the scene: {}", err))?;
+    if let Value::Mapping(mapping) = &mut value {
+        mapping.remove(&Value::from("tiles"));
+        if let Some(Value::Mapping(sampling)) = mapping.get_mut(&Value::from("sampling")) {
+            sampling.remove(&Value::from("time_limit"));
+        }
+    }
+    let yaml = serde_yaml::to_string(&value).map_err(|err| format!("cannot serialize the scene: {}", err))?;
+
+    let mut values: Vec<u64> = yaml.bytes().map(|byte| byte as u64).collect();
+    let mut paths = Vec::new();
+    asset_paths(&value, &mut paths);
+    for path in paths {
+        let metadata = fs::metadata(&path).map_err(|err| format!("cannot read {}: {}", path, err))?;
+        let modified = metadata.modified().ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |modified| modified.as_nanos() as u64);
+        values.extend([metadata.len(), modified]);
+    }
+    Ok(hash(&values))
+}
+
+// files of the image textures and volume grids found in the serialized scene
+fn asset_paths(value: &Value, paths: &mut Vec<String>) {
+    match value {
+        Value::Mapping(mapping) => {
+            let path = match mapping.get(&Value::from("type")).and_then(Value::as_str) {
+                Some("ImageTexture") => mapping.get(&Value::from("path")),
+                Some("Volume") => mapping.get(&Value::from("grid")),
+                _ => None,
+            };
+            paths.extend(path.and_then(Value::as_str).map(String::from));
+            mapping.iter().for_each(|(_, value)| asset_paths(value, paths));
+        }
+        Value::Sequence(values) => values.iter().for_each(|value| asset_paths(value, paths)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raytracer::tiles::TileOrder;
+    use na::Vector3;
+
+    const SCENE: &str = "camera:\n  cam_orient: [0.0, 0.0, 1.0]\n  cam_pos: [0.0, 0.0, 0.0]\n  up_vec: [0.0, 1.0, 0.0]\nshapes: []\ndistant_lights: []\n";
+
+    fn create_checkpoint() -> Checkpoint {
+        let mut film = Film::region(10, 8, 4, 5, 3, 2).unwrap();
+        film.pixels[1].add_sample(&Vector3::new(0.25, 0.5, 1.0));
+        film.pixels[4].add_sample(&Vector3::new(1.0, 0.0, 0.0));
+        film.pixels[4].add_sample(&Vector3::new(0.0, 0.125, 0.0));
+        Checkpoint { scene_hash: 17, seed: 3, film }
+    }
+
+    #[test]
+    fn test_checkpoint_bytes_round_trip() {
+        let checkpoint = create_checkpoint();
+        let loaded = Checkpoint::from_bytes(&checkpoint.to_bytes()).unwrap();
+        assert_eq!((loaded.scene_hash, loaded.seed), (17, 3));
+        assert_eq!((loaded.film.width, loaded.film.height), (3, 2));
+        assert_eq!((loaded.film.origin, loaded.film.frame_size), ((4, 5), (10, 8)));
+        assert_eq!(loaded.film.pixels, checkpoint.film.pixels);
+    }
+
+    #[test]
+    fn test_invalid_checkpoints_are_rejected() {
+        let bytes = create_checkpoint().to_bytes();
+        assert!(Checkpoint::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Checkpoint::from_bytes(&bytes[..HEADER_SIZE - 1]).is_err());
+        assert!(Checkpoint::from_bytes(b"FGRID 1 1 1\n").is_err());
+    }
+
+    #[test]
+    fn test_checkpoint_only_resumes_its_render() {
+        let checkpoint = create_checkpoint();
+        let film = Film::region(10, 8, 4, 5, 3, 2).unwrap();
+        assert!(checkpoint.check_resumable(17, 3, &film).is_ok());
+        assert!(checkpoint.check_resumable(18, 3, &film).is_err());
+        assert!(checkpoint.check_resumable(17, 4, &film).is_err());
+        assert!(checkpoint.check_resumable(17, 3, &Film::region(10, 8, 4, 4, 3, 2).unwrap()).is_err());
+        assert!(checkpoint.check_resumable(17, 3, &Film::new(10, 8)).is_err());
+    }
+
+    #[test]
+    fn test_scene_hash_changes_with_scene() {
+        let scene: Scene = serde_yaml::from_str(SCENE).unwrap();
+        let same_scene: Scene = serde_yaml::from_str(SCENE).unwrap();
+        let mut other_scene: Scene = serde_yaml::from_str(SCENE).unwrap();
+        other_scene.sampling.samples_per_pixel = 8;
+        assert_eq!(scene_hash(&scene).unwrap(), scene_hash(&same_scene).unwrap());
+        assert_ne!(scene_hash(&scene).unwrap(), scene_hash(&other_scene).unwrap());
+    }
+
+    #[test]
+    fn test_scene_hash_ignores_tiles() {
+        let scene: Scene = serde_yaml::from_str(SCENE).unwrap();
+        let mut retiled_scene: Scene = serde_yaml::from_str(SCENE).unwrap();
+        retiled_scene.tiles.size = 32;
+        retiled_scene.tiles.order = TileOrder::Hilbert;
+        retiled_scene.sampling.time_limit = Some(60.0);
+        assert_eq!(scene_hash(&scene).unwrap(), scene_hash(&retiled_scene).unwrap());
+    }
+
+    #[test]
+    fn test_scene_hash_changes_with_textures() {
+        let path = std::env::temp_dir().join(format!("rust-tracer-checkpoint-{}.png", std::process::id()));
+        let write_texture = |width: u32| image::RgbImage::new(width, 1).save(&path).unwrap();
+        let scene_yaml = format!(
+            "{}  - type: Sphere\n    centre: [0.0, 0.0, 5.0]\n    r: 1.0\n    color: {{type: ImageTexture, path: {}}}\n    \
+             emissive_color: [0.0, 0.0, 0.0]\n    albedo: 1.0\n    reflectivity: 0.0\n",
+            SCENE.replace("shapes: []\n", "").replace("distant_lights: []\n", "distant_lights: []\nshapes:\n"),
+            path.display()
+        );
+
+        write_texture(1);
+        let first_hash = scene_hash(&serde_yaml::from_str(&scene_yaml).unwrap()).unwrap();
+        write_texture(2);
+        let second_hash = scene_hash(&serde_yaml::from_str(&scene_yaml).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_ne!(first_hash, second_hash);
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::time::SystemTime;
+
+use na::Vector3;
+
+// header of the raw float grid format: the ascii line "FGRID <nx> <ny> <nz>\n" followed by
+// nx * ny * nz little endian f32 densities, x varying fastest then y then z
+const FGRID_MAGIC: &str = "FGRID";
+// Mitsuba dense volume format: "VOL", version 3, encoding 1 (f32), resolution, channels and bounding box
+const VOL_MAGIC: &[u8] = b"VOL";
+const VOL_HEADER_SIZE: usize = 48;
+
+struct CachedGrid {
+    modified: Option<SystemTime>,
+    grid: Weak<DensityGrid>,
+}
+
+static GRID_CACHE: OnceLock<Mutex<HashMap<PathBuf, CachedGrid>>> = OnceLock::new();
+
+#[derive(Debug)]
+pub struct DensityGrid {
+    pub resolution: [usize; 3],
+    densities: Vec<f32>,
+    // upper bound of the interpolated density, used as the majorant for tracking
+    max_density: f64,
+}
+
+impl DensityGrid {
+    pub fn new(resolution: [usize; 3], densities: Vec<f32>) -> Result<DensityGrid, String> {
+        let voxel_count = resolution.iter().product::<usize>();
+        if voxel_count == 0 {
+            return Err(format!("density grid resolution {:?} is empty", resolution));
+        }
+        if densities.len() != voxel_count {
+            return Err(format!("density grid of resolution {:?} needs {} voxels, got {}", resolution, voxel_count, densities.len()));
+        }
+        if let Some(density) = densities.iter().find(|density| !density.is_finite() || **density < 0.0) {
+            return Err(format!("density grid contains the invalid density {}", density));
+        }
+        let max_density = densities.iter().fold(0.0_f32, |max, density| max.max(*density)) as f64;
+        Ok(DensityGrid { resolution, densities, max_density })
+    }
+
+    // loads a .fgrid raw grid or a Mitsuba .vol grid, depending on the file header
+    pub fn load(path: &Path) -> Result<DensityGrid, String> {
+        let bytes = fs::read(path).map_err(|err| format!("cannot read density grid {}: {}", path.display(), err))?;
+        let grid = if bytes.starts_with(FGRID_MAGIC.as_bytes()) {
+            DensityGrid::parse_fgrid(&bytes)
+        } else if bytes.starts_with(VOL_MAGIC) {
+            DensityGrid::parse_vol(&bytes)
+        } else {
+            Err(String::from("unknown density grid format"))
+        };
+        grid.map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    // like load, but reuses the grid if another volume, for instance of the previous animation frame, still holds the same unchanged file
+    pub fn load_shared(path: &Path) -> Result<Arc<DensityGrid>, String> {
+        let key = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let modified = fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+
+        let mut cache = GRID_CACHE.get_or_init(Default::default).lock().unwrap();
+        if let Some(cached) = cache.get(&key) {
+            if cached.modified == modified {
+                if let Some(grid) = cached.grid.upgrade() {
+                    return Ok(grid);
+                }
+            }
+        }
+        let grid = Arc::new(DensityGrid::load(path)?);
+        cache.insert(key, CachedGrid { modified, grid: Arc::downgrade(&grid) });
+        Ok(grid)
+    }
+
+    fn parse_fgrid(bytes: &[u8]) -> Result<DensityGrid, String> {
+        let header_end = bytes.iter().position(|byte| *byte == b'\n').ok_or("missing FGRID header line")?;
+        let header = std::str::from_utf8(&bytes[..header_end]).map_err(|_| "FGRID header is not ascii")?;
+        let sizes = header.split_whitespace()
+            .skip(1)
+            .map(|size| size.parse::<usize>().map_err(|_| format!("invalid FGRID resolution '{}'", size)))
+            .collect::<Result<Vec<usize>, String>>()?;
+        let resolution: [usize; 3] = sizes.try_into().map_err(|_| "FGRID header needs three resolutions")?;
+
+        DensityGrid::new(resolution, read_f32s(&bytes[header_end + 1..]))
+    }
+
+    fn parse_vol(bytes: &[u8]) -> Result<DensityGrid, String> {
+        if bytes.len() < VOL_HEADER_SIZE {
+            return Err(String::from("truncated VOL header"));
+        }
+        if bytes[3] != 3 {
+            return Err(format!("unsupported VOL version {}", bytes[3]));
+        }
+        let header = bytes[4..24].chunks_exact(4)
+            .map(|chunk| i32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect::<Vec<i32>>();
+        let (encoding, channels) = (header[0], header[4]);
+        if encoding != 1 {
+            return Err(format!("unsupported VOL encoding {}, only 32 bit floats are read", encoding));
+        }
+        if header[1..].iter().any(|value| *value <= 0) {
+            return Err(String::from("invalid VOL resolution or channel count"));
+        }
+        let resolution = [header[1] as usize, header[2] as usize, header[3] as usize];
+
+        // multichannel grids are reduced to their mean, the bounding box comes from the scene file instead
+        let values = read_f32s(&bytes[VOL_HEADER_SIZE..]);
+        let densities = values.chunks_exact(channels as usize)
+            .map(|voxel| voxel.iter().sum::<f32>() / channels as f32)
+            .collect();
+        DensityGrid::new(resolution, densities)
+    }
+
+    pub fn max_density(&self) -> f64 {
+        self.max_density
+    }
+
+    fn voxel(&self, x: usize, y: usize, z: usize) -> f64 {
+        let [nx, ny, _] = self.resolution;
+        self.densities[x + nx * (y + ny * z)] as f64
+    }
+
+    // trilinear interpolation between voxel centers, point is in [0, 1]^3 over the whole grid
+    // and the density is zero outside of it
+    pub fn density(&self, point: &Vector3<f64>) -> f64 {
+        if point.iter().any(|coordinate| !(0.0..=1.0).contains(coordinate)) {
+            return 0.0;
+        }
+
+        let mut lower = [0_usize; 3];
+        let mut upper = [0_usize; 3];
+        let mut fraction = [0.0_f64; 3];
+        for axis in 0..3 {
+            let size = self.resolution[axis];
+            let position = (point[axis] * size as f64 - 0.5).clamp(0.0, (size - 1) as f64);
+            lower[axis] = position.floor() as usize;
+            upper[axis] = (lower[axis] + 1).min(size - 1);
+            fraction[axis] = position - lower[axis] as f64;
+        }
+
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        let along_x = |y: usize, z: usize| lerp(self.voxel(lower[0], y, z), self.voxel(upper[0], y, z), fraction[0]);
+        let along_y = |z: usize| lerp(along_x(lower[1], z), along_x(upper[1], z), fraction[1]);
+        lerp(along_y(lower[2]), along_y(upper[2]), fraction[2])
+    }
+}
+
+fn read_f32s(bytes: &[u8]) -> Vec<f32> {
+    bytes.chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f64 = 1e-6;
+
+    fn write_temp_file(name: &str, bytes: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("rust-tracer-{}-{}", std::process::id(), name));
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    fn f32_bytes(values: &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|value| value.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn test_trilinear_density() {
+        // densities increase along x only
+        let grid = DensityGrid::new([2, 1, 1], vec![0.0, 1.0]).unwrap();
+
+        assert!((grid.density(&Vector3::new(0.25, 0.5, 0.5)) - 0.0).abs() < EPSILON);
+        assert!((grid.density(&Vector3::new(0.5, 0.5, 0.5)) - 0.5).abs() < EPSILON);
+        assert!((grid.density(&Vector3::new(0.9, 0.5, 0.5)) - 1.0).abs() < EPSILON);
+        assert_eq!(grid.density(&Vector3::new(1.5, 0.5, 0.5)), 0.0);
+        assert_eq!(grid.max_density(), 1.0);
+    }
+
+    #[test]
+    fn test_invalid_grids_are_rejected() {
+        assert!(DensityGrid::new([2, 2, 2], vec![0.0; 7]).is_err());
+        assert!(DensityGrid::new([1, 1, 1], vec![-1.0]).is_err());
+        assert!(DensityGrid::new([0, 1, 1], vec![]).is_err());
+    }
+
+    #[test]
+    fn test_load_fgrid() {
+        let mut bytes = b"FGRID 2 1 2\n".to_vec();
+        bytes.extend(f32_bytes(&[0.0, 1.0, 2.0, 3.0]));
+        let path = write_temp_file("grid.fgrid", &bytes);
+
+        let grid = DensityGrid::load(&path).unwrap();
+        assert_eq!(grid.resolution, [2, 1, 2]);
+        assert_eq!(grid.voxel(0, 0, 1), 2.0);
+        assert_eq!(grid.max_density(), 3.0);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_shared_grids_are_loaded_once() {
+        let mut bytes = b"FGRID 1 1 1\n".to_vec();
+        bytes.extend(f32_bytes(&[1.0]));
+        let path = write_temp_file("shared.fgrid", &bytes);
+
+        let first = DensityGrid::load_shared(&path).unwrap();
+        assert!(Arc::ptr_eq(&first, &DensityGrid::load_shared(&path).unwrap()));
+        drop(first);
+        assert_eq!(DensityGrid::load_shared(&path).unwrap().voxel(0, 0, 0), 1.0);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_load_mitsuba_vol() {
+        let mut bytes = b"VOL".to_vec();
+        bytes.push(3);
+        for value in [1_i32, 1, 2, 1, 2] {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.extend(f32_bytes(&[0.0, 0.0, 0.0, 1.0, 1.0, 1.0]));
+        // two channels per voxel, averaged
+        bytes.extend(f32_bytes(&[1.0, 3.0, 4.0, 6.0]));
+        let path = write_temp_file("grid.vol", &bytes);
+
+        let grid = DensityGrid::load(&path).unwrap();
+        assert_eq!(grid.resolution, [1, 2, 1]);
+        assert_eq!(grid.voxel(0, 0, 0), 2.0);
+        assert_eq!(grid.voxel(0, 1, 0), 5.0);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_load_unknown_format() {
+        let path = write_temp_file("grid.txt", b"not a grid");
+        assert!(DensityGrid::load(&path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
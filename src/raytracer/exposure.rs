@@ -0,0 +1,276 @@
+use serde::{Serialize, Deserialize};
+use na::{Matrix3, Vector2, Vector3};
+
+use std::convert::TryFrom;
+
+// range of the planckian locus approximation used for the white balance, in kelvins
+const MIN_TEMPERATURE: f64 = 1667.0;
+const MAX_TEMPERATURE: f64 = 25000.0;
+// temperature of the daylight white of the sRGB output, at which the white balance leaves colors unchanged
+const NEUTRAL_TEMPERATURE: f64 = 6500.0;
+// fixed point iterations inverting the lens distortion, enough for the distortions of real lenses
+const UNDISTORT_ITERATIONS: usize = 20;
+// largest distance, in half image widths, between the image point and the distorted inverse for it to be used
+const UNDISTORT_TOLERANCE: f64 = 1e-6;
+// coefficients are kept in the range of real lenses, stronger ones fold the image over itself
+const MAX_DISTORTION_COEFFICIENT: f64 = 1.0;
+
+fn default_iso() -> f64 {
+    100.0
+}
+
+fn default_shutter_speed() -> f64 {
+    1.0
+}
+
+fn default_f_number() -> f64 {
+    1.0
+}
+
+fn default_white_balance() -> f64 {
+    NEUTRAL_TEMPERATURE
+}
+
+// photographic settings scaling the radiance reaching the film; a radiance of 1 gives a white pixel
+// at ISO 100, 1 s and f/1, and each stop down halves it. the defaults keep the radiance as it is
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "ExposureConfig")]
+pub struct Exposure {
+    pub iso: f64,
+    // time the sensor is exposed in seconds, independent of the shutter interval used for motion blur
+    pub shutter_speed: f64,
+    pub f_number: f64,
+    // color temperature in kelvins of the light that comes out white like daylight does by default,
+    // e.g. 3200 for tungsten lights
+    pub white_balance: f64,
+    // factor of each channel of the radiance
+    #[serde(skip)]
+    scale: Vector3<f64>,
+}
+
+#[derive(Deserialize)]
+struct ExposureConfig {
+    #[serde(default = "default_iso")]
+    iso: f64,
+    #[serde(default = "default_shutter_speed")]
+    shutter_speed: f64,
+    #[serde(default = "default_f_number")]
+    f_number: f64,
+    #[serde(default = "default_white_balance")]
+    white_balance: f64,
+}
+
+impl TryFrom<ExposureConfig> for Exposure {
+    type Error = String;
+
+    fn try_from(config: ExposureConfig) -> Result<Self, Self::Error> {
+        Exposure::new(config.iso, config.shutter_speed, config.f_number, config.white_balance)
+    }
+}
+
+impl Default for Exposure {
+    fn default() -> Exposure {
+        Exposure::new(default_iso(), default_shutter_speed(), default_f_number(), default_white_balance()).unwrap()
+    }
+}
+
+impl Exposure {
+    pub fn new(iso: f64, shutter_speed: f64, f_number: f64, white_balance: f64) -> Result<Exposure, String> {
+        if [iso, shutter_speed, f_number].iter().any(|value| !(value.is_finite() && *value > 0.0)) {
+            return Err(String::from("iso, shutter_speed and f_number must be positive"));
+        }
+        if !(MIN_TEMPERATURE..=MAX_TEMPERATURE).contains(&white_balance) {
+            return Err(format!("white_balance must be between {} and {} kelvins", MIN_TEMPERATURE, MAX_TEMPERATURE));
+        }
+
+        let exposure = iso / 100.0 * shutter_speed / (f_number * f_number);
+        // the light of the chosen temperature is brought to the color of neutral white, keeping green as it is
+        let gains = blackbody_rgb(NEUTRAL_TEMPERATURE).component_div(&blackbody_rgb(white_balance));
+        let scale = gains / gains.y * exposure;
+        Ok(Exposure { iso, shutter_speed, f_number, white_balance, scale })
+    }
+
+    pub fn scale(&self) -> Vector3<f64> {
+        self.scale
+    }
+}
+
+// linear sRGB color of a black body at a temperature, with a luminance of 1;
+// the chromaticity comes from the cubic spline approximation of the planckian locus by Kim et al.
+fn blackbody_rgb(temperature: f64) -> Vector3<f64> {
+    let t = temperature;
+    let x = if t <= 4000.0 {
+        -0.2661239e9 / (t * t * t) - 0.2343589e6 / (t * t) + 0.8776956e3 / t + 0.179910
+    } else {
+        -3.0258469e9 / (t * t * t) + 2.1070379e6 / (t * t) + 0.2226347e3 / t + 0.240390
+    };
+    let y = if t <= 2222.0 {
+        -1.1063814 * x * x * x - 1.34811020 * x * x + 2.18555832 * x - 0.20219683
+    } else if t <= 4000.0 {
+        -0.9549476 * x * x * x - 1.37418593 * x * x + 2.09137015 * x - 0.16748867
+    } else {
+        3.0817580 * x * x * x - 5.87338670 * x * x + 3.75112997 * x - 0.37001483
+    };
+
+    let xyz = Vector3::new(x / y, 1.0, (1.0 - x - y) / y);
+    let xyz_to_srgb = Matrix3::new(
+        3.2404542, -1.5371385, -0.4985314,
+        -0.9692660, 1.8760108, 0.0415560,
+        0.0556434, -0.2040259, 1.0572252,
+    );
+    xyz_to_srgb * xyz
+}
+
+// imperfections of the lens, applied in the frame of each eye
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+#[serde(try_from = "LensConfig")]
+pub struct Lens {
+    // fraction of the light lost in the corners of the image, falling off with the square of the distance to the centre
+    pub vignetting: f64,
+    // brown-conrady coefficients [k1, k2, p1, p2]: radial distortion, barrel when k1 < 0 and pincushion when k1 > 0,
+    // then tangential distortion; image positions are measured from the centre in half image widths, y going down
+    pub distortion: [f64; 4],
+}
+
+#[derive(Deserialize)]
+struct LensConfig {
+    #[serde(default)]
+    vignetting: f64,
+    #[serde(default)]
+    distortion: [f64; 4],
+}
+
+impl TryFrom<LensConfig> for Lens {
+    type Error = String;
+
+    fn try_from(config: LensConfig) -> Result<Self, Self::Error> {
+        if !(0.0..=1.0).contains(&config.vignetting) {
+            return Err(String::from("vignetting must be between 0 and 1"));
+        }
+        if config.distortion.iter().any(|coefficient| !(-MAX_DISTORTION_COEFFICIENT..=MAX_DISTORTION_COEFFICIENT).contains(coefficient)) {
+            return Err(format!("the distortion coefficients must be between -{0} and {0}", MAX_DISTORTION_COEFFICIENT));
+        }
+        Ok(Lens { vignetting: config.vignetting, distortion: config.distortion })
+    }
+}
+
+impl Lens {
+    // point of the undistorted frame seen at a point of the image, and the fraction of its light left by the vignetting;
+    // None where the distortion cannot be inverted, past the edge of a strong barrel distortion
+    pub fn apply(&self, x: f64, y: f64, (dimx, dimy): (u32, u32)) -> Option<(f64, f64, f64)> {
+        // frame coordinates are measured from the centre of the top left pixel
+        let centre = Vector2::new((dimx as f64 - 1.0) / 2.0, (dimy as f64 - 1.0) / 2.0);
+        let half_width = (dimx as f64 / 2.0).max(f64::MIN_POSITIVE);
+        let image = (Vector2::new(x, y) - centre) / half_width;
+
+        let corner = centre / half_width;
+        let transmitted = 1.0 - self.vignetting * (image.norm_squared() / corner.norm_squared()).min(1.0);
+
+        if self.distortion == [0.0; 4] {
+            return Some((x, y, transmitted));
+        }
+        // the model distorts undistorted points, it is inverted by fixed point iteration
+        let mut undistorted = image;
+        for _ in 0..UNDISTORT_ITERATIONS {
+            let (radial, tangential) = self.distortion_terms(&undistorted);
+            if radial <= 0.0 {
+                return None;
+            }
+            undistorted = (image - tangential) / radial;
+        }
+        // the iteration may diverge or oscillate instead of converging
+        let (radial, tangential) = self.distortion_terms(&undistorted);
+        let error = (undistorted * radial + tangential - image).norm();
+        if error.is_nan() || error > UNDISTORT_TOLERANCE {
+            return None;
+        }
+        let frame = undistorted * half_width + centre;
+        Some((frame.x, frame.y, transmitted))
+    }
+
+    // radial factor and tangential offset of the brown-conrady model at an undistorted point
+    fn distortion_terms(&self, point: &Vector2<f64>) -> (f64, Vector2<f64>) {
+        let [k1, k2, p1, p2] = self.distortion;
+        let (x, y) = (point.x, point.y);
+        let r2 = x * x + y * y;
+        let radial = 1.0 + k1 * r2 + k2 * r2 * r2;
+        let tangential = Vector2::new(
+            2.0 * p1 * x * y + p2 * (r2 + 2.0 * x * x),
+            p1 * (r2 + 2.0 * y * y) + 2.0 * p2 * x * y,
+        );
+        (radial, tangential)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f64 = 1e-9;
+
+    #[test]
+    fn test_default_exposure_keeps_radiance() {
+        assert!((Exposure::default().scale() - Vector3::from_element(1.0)).norm() < EPSILON);
+    }
+
+    #[test]
+    fn test_exposure_stops() {
+        // one stop more sensitivity, one stop shorter, two stops smaller aperture
+        let exposure = Exposure::new(200.0, 0.5, 2.0, NEUTRAL_TEMPERATURE).unwrap();
+        assert!((exposure.scale() - Vector3::from_element(0.25)).norm() < EPSILON);
+
+        assert!(Exposure::new(0.0, 1.0, 1.0, NEUTRAL_TEMPERATURE).is_err());
+        assert!(Exposure::new(100.0, 1.0, 1.0, 1000.0).is_err());
+    }
+
+    #[test]
+    fn test_white_balance() {
+        // set for warm tungsten light, the camera makes that light white and renders daylight blue
+        let tungsten = Exposure::new(100.0, 1.0, 1.0, 3200.0).unwrap();
+        let tungsten_light = blackbody_rgb(3200.0).component_mul(&tungsten.scale());
+        assert!((tungsten_light.normalize() - blackbody_rgb(NEUTRAL_TEMPERATURE).normalize()).norm() < EPSILON);
+        assert!(tungsten.scale().z > 1.0 && tungsten.scale().x < 1.0);
+
+        let shade = Exposure::new(100.0, 1.0, 1.0, 8000.0).unwrap();
+        assert!(shade.scale().z < 1.0 && shade.scale().x > 1.0);
+    }
+
+    #[test]
+    fn test_vignetting() {
+        let lens = Lens { vignetting: 0.5, distortion: [0.0; 4] };
+        assert_eq!(lens.apply(1.5, 0.5, (4, 2)), Some((1.5, 0.5, 1.0)));
+        assert!((lens.apply(0.0, 0.0, (4, 2)).unwrap().2 - 0.5).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_distortion_is_inverted() {
+        let lens = Lens { vignetting: 0.0, distortion: [-0.2, 0.05, 0.01, -0.02] };
+        let (x, y, _) = lens.apply(70.0, 20.0, (100, 60)).unwrap();
+        // distorting the frame point gives back the image point
+        let point = (Vector2::new(x, y) - Vector2::new(49.5, 29.5)) / 50.0;
+        let (radial, tangential) = lens.distortion_terms(&point);
+        let image = (point * radial + tangential) * 50.0 + Vector2::new(49.5, 29.5);
+        assert!((image - Vector2::new(70.0, 20.0)).norm() < 1e-6);
+        // barrel distortion shows more of the frame towards the edges
+        assert!(x > 70.0);
+    }
+
+    #[test]
+    fn test_strong_barrel_distortion() {
+        let lens = Lens { vignetting: 0.0, distortion: [-1.0, 0.0, 0.0, 0.0] };
+        // the middle of the image is still seen, the corners are past the fold of the distortion
+        let (x, y, _) = lens.apply(60.0, 35.0, (100, 60)).unwrap();
+        assert!(x > 60.0 && y > 35.0);
+        assert_eq!(lens.apply(0.0, 0.0, (100, 60)), None);
+        for (x, y) in (0..100).flat_map(|x| (0..60).map(move |y| (x as f64, y as f64))) {
+            if let Some((x, y, transmitted)) = lens.apply(x, y, (100, 60)) {
+                assert!(x.is_finite() && y.is_finite() && transmitted.is_finite());
+            }
+        }
+
+        let config = |k1: f64| LensConfig { vignetting: 0.0, distortion: [k1, 0.0, 0.0, 0.0] };
+        assert!(Lens::try_from(config(-1.0)).is_ok());
+        assert!(Lens::try_from(config(-1.5)).is_err());
+        assert!(Lens::try_from(config(f64::NAN)).is_err());
+    }
+}
@@ -0,0 +1,213 @@
+use std::convert::TryInto;
+
+use na::Vector3;
+
+// relative errors are measured against at least this luminance, so that black pixels can converge
+const MIN_ERROR_LUMINANCE: f64 = 0.01;
+
+fn luminance(color: &Vector3<f64>) -> f64 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
+// running sums of the samples of a pixel, enough to get its mean and the variance of the mean
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PixelStats {
+    pub color_sum: Vector3<f64>,
+    pub luminance_sum: f64,
+    pub luminance_squared_sum: f64,
+    pub sample_count: u32,
+}
+
+impl PixelStats {
+    // size of a pixel encoded by write_bytes
+    pub const ENCODED_SIZE: usize = 5 * 8 + 4;
+
+    // little endian color sum, luminance sum and luminance squared sum as f64, then the sample count as u32
+    pub fn write_bytes(&self, bytes: &mut Vec<u8>) {
+        for value in self.color_sum.iter().chain([self.luminance_sum, self.luminance_squared_sum].iter()) {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.sample_count.to_le_bytes());
+    }
+
+    // reads a pixel written by write_bytes, bytes must hold at least ENCODED_SIZE bytes
+    pub fn read_bytes(bytes: &[u8]) -> PixelStats {
+        let f64_at = |offset: usize| f64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+        PixelStats {
+            color_sum: Vector3::new(f64_at(0), f64_at(8), f64_at(16)),
+            luminance_sum: f64_at(24),
+            luminance_squared_sum: f64_at(32),
+            sample_count: u32::from_le_bytes(bytes[40..44].try_into().unwrap()),
+        }
+    }
+
+    pub fn add_sample(&mut self, color: &Vector3<f64>) {
+        let sample_luminance = luminance(color);
+        self.color_sum += color;
+        self.luminance_sum += sample_luminance;
+        self.luminance_squared_sum += sample_luminance * sample_luminance;
+        self.sample_count += 1;
+    }
+
+    pub fn mean(&self) -> Vector3<f64> {
+        if self.sample_count == 0 {
+            return Vector3::zeros();
+        }
+        self.color_sum / self.sample_count as f64
+    }
+
+    // standard error of the mean luminance relative to the mean luminance, infinite before two samples
+    pub fn relative_error(&self) -> f64 {
+        if self.sample_count < 2 {
+            return f64::INFINITY;
+        }
+        let count = self.sample_count as f64;
+        let mean = self.luminance_sum / count;
+        let variance = ((self.luminance_squared_sum - count * mean * mean) / (count - 1.0)).max(0.0);
+        (variance / count).sqrt() / mean.abs().max(MIN_ERROR_LUMINANCE)
+    }
+}
+
+// image being rendered, with the sample statistics of each pixel in row order;
+// it covers the region of the camera frame starting at origin, the whole frame by default
+#[derive(Clone, Debug)]
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    pub origin: (u32, u32),
+    pub frame_size: (u32, u32),
+    pub pixels: Vec<PixelStats>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Film {
+        // in usize, a u32 product of large sizes would wrap to a small film
+        let pixel_count = (width as usize).checked_mul(height as usize).expect("the film has too many pixels to be addressed");
+        Film { width, height, origin: (0, 0), frame_size: (width, height), pixels: vec![PixelStats::default(); pixel_count] }
+    }
+
+    // film for the width x height crop at (x, y) of a frame_width x frame_height render
+    pub fn region(frame_width: u32, frame_height: u32, x: u32, y: u32, width: u32, height: u32) -> Result<Film, String> {
+        if width == 0 || height == 0 || x as u64 + width as u64 > frame_width as u64 || y as u64 + height as u64 > frame_height as u64 {
+            return Err(format!("region {},{},{},{} is not inside the {}x{} frame", x, y, width, height, frame_width, frame_height));
+        }
+        let mut film = Film::new(width, height);
+        film.origin = (x, y);
+        film.frame_size = (frame_width, frame_height);
+        Ok(film)
+    }
+
+    // mean number of samples taken per pixel
+    pub fn samples_per_pixel(&self) -> f64 {
+        let sample_count = self.pixels.iter().map(|pixel| pixel.sample_count as u64).sum::<u64>();
+        sample_count as f64 / self.pixels.len().max(1) as f64
+    }
+
+    // 16 bit rgb values of the pixel means, as written to the output image
+    pub fn to_rgb16(&self) -> Vec<u16> {
+        self.pixels.iter()
+            .flat_map(|pixel| {
+                let color = pixel.mean();
+                [
+                    (color.x * 65535.0) as u16,
+                    (color.y * 65535.0) as u16,
+                    (color.z * 65535.0) as u16
+                ]
+            })
+            .collect()
+    }
+
+    // debug image of the sample count of each pixel, from black (fewest) through red and yellow to white (most)
+    pub fn sample_count_heatmap(&self) -> Vec<u16> {
+        let counts = self.pixels.iter().map(|pixel| pixel.sample_count);
+        let (fewest, most) = counts.clone().fold((u32::MAX, 0), |(fewest, most), count| (fewest.min(count), most.max(count)));
+        let range = most.saturating_sub(fewest).max(1) as f64;
+
+        counts
+            .flat_map(|count| {
+                let heat = (count - fewest) as f64 / range;
+                [3.0 * heat, 3.0 * heat - 1.0, 3.0 * heat - 2.0]
+                    .map(|channel| (channel.clamp(0.0, 1.0) * 65535.0) as u16)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f64 = 1e-10;
+
+    #[test]
+    fn test_pixel_mean() {
+        let mut pixel = PixelStats::default();
+        assert_eq!(pixel.mean(), Vector3::zeros());
+        pixel.add_sample(&Vector3::new(1.0, 0.0, 0.5));
+        pixel.add_sample(&Vector3::new(0.0, 1.0, 0.5));
+        assert!((pixel.mean() - Vector3::new(0.5, 0.5, 0.5)).norm() < EPSILON);
+        assert_eq!(pixel.sample_count, 2);
+    }
+
+    #[test]
+    fn test_pixel_bytes_round_trip() {
+        let mut pixel = PixelStats::default();
+        pixel.add_sample(&Vector3::new(0.25, 0.5, 1.0));
+        pixel.add_sample(&Vector3::new(1e-300, 3.0, 0.0));
+        let mut bytes = Vec::new();
+        pixel.write_bytes(&mut bytes);
+        assert_eq!(bytes.len(), PixelStats::ENCODED_SIZE);
+        assert_eq!(PixelStats::read_bytes(&bytes), pixel);
+    }
+
+    #[test]
+    fn test_relative_error() {
+        let mut constant = PixelStats::default();
+        constant.add_sample(&Vector3::from_element(0.5));
+        assert_eq!(constant.relative_error(), f64::INFINITY);
+        constant.add_sample(&Vector3::from_element(0.5));
+        assert!(constant.relative_error() < EPSILON);
+
+        // samples 0 and 1: variance 1 / 2, standard error 1 / 2, relative to the mean 1 / 2
+        let mut noisy = PixelStats::default();
+        noisy.add_sample(&Vector3::zeros());
+        noisy.add_sample(&Vector3::from_element(1.0));
+        assert!((noisy.relative_error() - 1.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_heatmap_ramp() {
+        let mut film = Film::new(3, 1);
+        for (index, count) in [1, 3, 5].iter().enumerate() {
+            (0..*count).for_each(|_| film.pixels[index].add_sample(&Vector3::zeros()));
+        }
+        let heatmap = film.sample_count_heatmap();
+        assert_eq!(&heatmap[0..3], &[0, 0, 0]);
+        assert_eq!(&heatmap[3..6], &[65535, 32767, 0]);
+        assert_eq!(&heatmap[6..9], &[65535, 65535, 65535]);
+    }
+
+    #[test]
+    fn test_samples_per_pixel() {
+        let mut film = Film::new(2, 1);
+        assert_eq!(film.samples_per_pixel(), 0.0);
+        (0..3).for_each(|_| film.pixels[1].add_sample(&Vector3::zeros()));
+        assert_eq!(film.samples_per_pixel(), 1.5);
+    }
+
+    #[test]
+    fn test_region_must_be_inside_frame() {
+        let film = Film::region(640, 480, 600, 0, 40, 10).unwrap();
+        assert_eq!((film.width, film.height, film.pixels.len()), (40, 10, 400));
+        assert_eq!((film.origin, film.frame_size), ((600, 0), (640, 480)));
+        assert!(Film::region(640, 480, 601, 0, 40, 10).is_err());
+        assert!(Film::region(640, 480, 0, 0, 0, 10).is_err());
+    }
+
+    #[test]
+    fn test_to_rgb16() {
+        let mut film = Film::new(1, 1);
+        film.pixels[0].add_sample(&Vector3::new(1.0, 0.5, 0.0));
+        assert_eq!(film.to_rgb16(), vec![65535, 32767, 0]);
+    }
+}
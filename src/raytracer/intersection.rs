@@ -1,4 +1,4 @@
-use na::Vector3;
+use na::{Vector2, Vector3};
 
 use super::shape::Shape3D;
 
@@ -7,7 +7,19 @@ pub struct Intersection<'a> {
     pub location: Vector3<f64>,
     pub biased_location: Vector3<f64>,
     pub distance: f64,
+    // shading normal, may be perturbed by the material's normal or bump map
     pub normal: Vector3<f64>,
+    // normal of the actual surface, biased_location is always offset along it
+    pub geometric_normal: Vector3<f64>,
+    // surface coordinates of the hit, used for texture lookups
+    pub uv: Vector2<f64>,
+    // partial derivatives of the location along u and v, they span the tangent plane
+    pub dpdu: Vector3<f64>,
+    pub dpdv: Vector3<f64>,
+    // true when the ray arrives on the side the shape's outward normal points to
+    pub front_face: bool,
+    // time of the ray that hit the shape
+    pub time: f64,
     pub shape: &'a dyn Shape3D
     // pub color: [u8; 3]
 }
@@ -19,9 +31,32 @@ impl<'a> Intersection<'a> {
             // we use this biased_location to account for error margin in location and avoid ray colision with shape's surface
             biased_location: &location + 0.001 * &normal, 
             distance,
-            normal,shape
+            normal,
+            geometric_normal: normal,
+            uv: Vector2::zeros(),
+            dpdu: Vector3::zeros(),
+            dpdv: Vector3::zeros(),
+            front_face: true,
+            time: 0.0,
+            shape
         }
     }
+
+    // fills in the uv and tangents from the shape, which only computes them for the hits that get shaded
+    pub fn complete_surface_frame(&mut self) {
+        let (uv, dpdu, dpdv) = self.shape.surface_frame(self);
+        self.uv = uv;
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+    }
+
+    pub fn with_surface_frame(mut self, uv: Vector2<f64>, dpdu: Vector3<f64>, dpdv: Vector3<f64>, front_face: bool) -> Intersection<'a> {
+        self.uv = uv;
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+        self.front_face = front_face;
+        self
+    }
 }
 
 #[cfg(test)]
@@ -124,7 +159,49 @@ mod tests {
         let intersection = Intersection::new(location, 1.0, normal, &shape);
         
         // Verify shape properties are accessible
-        assert_eq!(intersection.shape.get_color(), Vector3::new(1.0, 0.0, 0.0));
-        assert!((intersection.shape.get_albedo() - 0.5).abs() < EPSILON);
+        assert_eq!(intersection.shape.get_color(&intersection), Vector3::new(1.0, 0.0, 0.0));
+        assert!((intersection.shape.get_albedo(&intersection) - 0.5).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_completed_tangent_frame_is_orthogonal_to_normal() {
+        let shape = create_test_shape();
+        let normal = Vector3::new(0.0, 0.0, 1.0);
+
+        let mut intersection = Intersection::new(normal, 1.0, normal, &shape);
+        assert_eq!(intersection.dpdu, Vector3::zeros());
+        intersection.complete_surface_frame();
+
+        assert!(intersection.dpdu.dot(&normal).abs() < EPSILON);
+        assert!(intersection.dpdv.dot(&normal).abs() < EPSILON);
+        assert!(intersection.dpdu.dot(&intersection.dpdv).abs() < EPSILON);
+        assert!(intersection.front_face);
+    }
+
+    #[test]
+    fn test_geometric_normal_starts_as_normal() {
+        let shape = create_test_shape();
+        let normal = Vector3::new(0.0, 0.0, -1.0);
+
+        let intersection = Intersection::new(Vector3::zeros(), 1.0, normal, &shape);
+
+        assert_eq!(intersection.geometric_normal, normal);
+    }
+
+    #[test]
+    fn test_with_surface_frame_overrides_defaults() {
+        let shape = create_test_shape();
+        let intersection = Intersection::new(Vector3::zeros(), 1.0, Vector3::new(0.0, 1.0, 0.0), &shape)
+            .with_surface_frame(
+                Vector2::new(0.25, 0.75),
+                Vector3::new(2.0, 0.0, 0.0),
+                Vector3::new(0.0, 0.0, 3.0),
+                false,
+            );
+
+        assert_eq!(intersection.uv, Vector2::new(0.25, 0.75));
+        assert_eq!(intersection.dpdu, Vector3::new(2.0, 0.0, 0.0));
+        assert_eq!(intersection.dpdv, Vector3::new(0.0, 0.0, 3.0));
+        assert!(!intersection.front_face);
     }
 }
\ No newline at end of file
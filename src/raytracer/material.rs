@@ -0,0 +1,348 @@
+use serde::{Serialize, Deserialize};
+use std::sync::Arc;
+use na::Vector3;
+
+use super::Intersection;
+use super::textures::Texture;
+use super::medium::HomogeneousMedium;
+use super::samplers::Sampler;
+
+// a color is either given inline as [r, g, b] or by a texture definition
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ColorParameter {
+    Constant(Vector3<f64>),
+    Texture(Arc<dyn Texture>),
+}
+
+impl ColorParameter {
+    pub fn evaluate(&self, intersection: &Intersection) -> Vector3<f64> {
+        match self {
+            ColorParameter::Constant(color) => *color,
+            ColorParameter::Texture(texture) => texture.evaluate(intersection),
+        }
+    }
+}
+
+// a scalar is either given inline or by a texture, in which case the mean of its channels is used
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ScalarParameter {
+    Constant(f64),
+    Texture(Arc<dyn Texture>),
+}
+
+impl ScalarParameter {
+    pub fn evaluate(&self, intersection: &Intersection) -> f64 {
+        match self {
+            ScalarParameter::Constant(value) => *value,
+            ScalarParameter::Texture(texture) => texture.evaluate(intersection).mean(),
+        }
+    }
+}
+
+fn default_bump_scale() -> f64 {
+    1.0
+}
+
+fn default_alpha_threshold() -> f64 {
+    0.5
+}
+
+// uv step used to differentiate bump maps
+const BUMP_DELTA: f64 = 1e-3;
+
+// translucent interior of a closed shape, light enters the surface and random walks inside before leaving it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Subsurface {
+    // average distance between two interactions inside the material, per color channel
+    pub mean_free_path: Vector3<f64>,
+    // probability of scattering rather than being absorbed at each interaction, per color channel
+    pub scatter_color: Vector3<f64>,
+    #[serde(default)]
+    pub g: f64,
+}
+
+impl Subsurface {
+    pub fn new(mean_free_path: Vector3<f64>, scatter_color: Vector3<f64>, g: f64) -> Subsurface {
+        Subsurface { mean_free_path, scatter_color, g }
+    }
+
+    // medium the walk goes through
+    pub fn medium(&self) -> HomogeneousMedium {
+        let sigma_t = self.mean_free_path.map(|distance| 1.0 / distance.max(1e-6));
+        let sigma_s = sigma_t.component_mul(&self.scatter_color);
+        HomogeneousMedium::new(sigma_t - sigma_s, sigma_s, self.g)
+    }
+}
+
+// surface properties shared by every shape, flattened into the shape in scene files
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Material {
+    pub color: ColorParameter,
+    pub emissive_color: ColorParameter,
+    pub albedo: ScalarParameter,
+    pub reflectivity: ScalarParameter,
+    // tangent space normal map, rgb in [0, 1] encodes a normal in [-1, 1] with z along the surface normal
+    #[serde(default)]
+    pub normal_map: Option<Arc<dyn Texture>>,
+    // height field displacing the surface along its normal, only used when there is no normal map
+    #[serde(default)]
+    pub bump_map: Option<ScalarParameter>,
+    // world units of displacement per bump map unit
+    #[serde(default = "default_bump_scale")]
+    pub bump_scale: f64,
+    // cutout mask, 0 is fully transparent and 1 fully opaque
+    #[serde(default)]
+    pub opacity: Option<ScalarParameter>,
+    // hits where the opacity is below this value are ignored
+    #[serde(default = "default_alpha_threshold")]
+    pub alpha_threshold: f64,
+    // ignore hits randomly with probability 1 - opacity instead of thresholding, for partially transparent surfaces
+    #[serde(default)]
+    pub stochastic_alpha: bool,
+    // fills a closed shape with a participating medium, its surface then only delimits the medium and is not rendered
+    #[serde(default)]
+    pub interior_medium: Option<HomogeneousMedium>,
+    // replaces the lambertian albedo model of closed shapes by subsurface scattering
+    #[serde(default)]
+    pub subsurface: Option<Subsurface>,
+}
+
+impl Material {
+    pub fn new(
+        color: Vector3<f64>,
+        emissive_color: Vector3<f64>,
+        albedo: f64,
+        reflectivity: f64
+    ) -> Material {
+        Material {
+            color: ColorParameter::Constant(color),
+            emissive_color: ColorParameter::Constant(emissive_color),
+            albedo: ScalarParameter::Constant(albedo),
+            reflectivity: ScalarParameter::Constant(reflectivity),
+            normal_map: None,
+            bump_map: None,
+            bump_scale: default_bump_scale(),
+            opacity: None,
+            alpha_threshold: default_alpha_threshold(),
+            stochastic_alpha: false,
+            interior_medium: None,
+            subsurface: None,
+        }
+    }
+
+    // true when the opacity mask removes the surface at this intersection, rays should then go through it
+    pub fn is_cut_out(&self, intersection: &Intersection, sampler: &mut dyn Sampler) -> bool {
+        match &self.opacity {
+            None => false,
+            Some(opacity) => {
+                let alpha = opacity.evaluate(intersection);
+                if self.stochastic_alpha {
+                    sampler.next_1d() >= alpha
+                } else {
+                    alpha < self.alpha_threshold
+                }
+            }
+        }
+    }
+
+    // the geometric normal is left untouched, only the normal used for shading is perturbed
+    pub fn shading_normal(&self, intersection: &Intersection) -> Vector3<f64> {
+        let normal = intersection.normal;
+
+        if let Some(normal_map) = &self.normal_map {
+            let tangent = (intersection.dpdu - normal * normal.dot(&intersection.dpdu)).normalize();
+            let mut bitangent = normal.cross(&tangent);
+            if bitangent.dot(&intersection.dpdv) < 0.0 {
+                bitangent = -bitangent;
+            }
+            let mapped = normal_map.evaluate(intersection) * 2.0 - Vector3::from_element(1.0);
+            return (tangent * mapped.x + bitangent * mapped.y + normal * mapped.z).normalize();
+        }
+
+        if let Some(bump_map) = &self.bump_map {
+            let height = bump_map.evaluate(intersection);
+            let height_at = |du: f64, dv: f64| {
+                let mut shifted = intersection.clone();
+                shifted.uv.x += du;
+                shifted.uv.y += dv;
+                shifted.location += intersection.dpdu * du + intersection.dpdv * dv;
+                bump_map.evaluate(&shifted)
+            };
+            let dhdu = self.bump_scale * (height_at(BUMP_DELTA, 0.0) - height) / BUMP_DELTA;
+            let dhdv = self.bump_scale * (height_at(0.0, BUMP_DELTA) - height) / BUMP_DELTA;
+
+            // derivatives of the displaced surface p + h * n, ignoring the variation of n itself
+            let displaced_dpdu = intersection.dpdu + normal * dhdu;
+            let displaced_dpdv = intersection.dpdv + normal * dhdv;
+            let bumped = displaced_dpdu.cross(&displaced_dpdv).normalize();
+            return if bumped.dot(&normal) < 0.0 { -bumped } else { bumped };
+        }
+
+        normal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raytracer::samplers::independent::IndependentSampler;
+    use crate::raytracer::shapes::*;
+
+    #[test]
+    fn test_constant_parameters_deserialize_from_plain_values() {
+        let material: Material = serde_yaml::from_str(
+            "color: [1.0, 0.5, 0]\nemissive_color: [0.0, 0.0, 0.0]\nalbedo: 1\nreflectivity: 0.25"
+        ).unwrap();
+
+        let sphere = Sphere::new(Vector3::zeros(), 1.0, Vector3::zeros(), Vector3::zeros(), 0.0, 0.0);
+        let hit = Intersection::new(Vector3::zeros(), 1.0, Vector3::y(), &sphere);
+        assert_eq!(material.color.evaluate(&hit), Vector3::new(1.0, 0.5, 0.0));
+        assert_eq!(material.albedo.evaluate(&hit), 1.0);
+        assert_eq!(material.reflectivity.evaluate(&hit), 0.25);
+    }
+
+    #[test]
+    fn test_shape_keeps_flat_material_fields() {
+        let shape: Box<dyn Shape3D> = serde_yaml::from_str(
+            "type: Sphere\ncentre: [0.0, 0.0, 0.0]\nr: 1.0\ncolor: [0.0, 1.0, 0.0]\nemissive_color: [0.0, 0.0, 0.0]\nalbedo: 0.5\nreflectivity: 0.0"
+        ).unwrap();
+
+        let hit = Intersection::new(Vector3::zeros(), 1.0, Vector3::y(), shape.as_ref());
+        assert_eq!(shape.get_color(&hit), Vector3::new(0.0, 1.0, 0.0));
+        assert_eq!(shape.get_albedo(&hit), 0.5);
+    }
+
+    fn create_bumped_material(texture: &str) -> Material {
+        let mut material = Material::new(Vector3::zeros(), Vector3::zeros(), 1.0, 0.0);
+        material.bump_map = Some(serde_yaml::from_str(texture).unwrap());
+        material
+    }
+
+    fn create_floor_hit(plane: &Plane) -> Intersection<'_> {
+        let ray = crate::raytracer::ray::Ray::new_from_origine_and_direction(
+            &Vector3::new(0.3, 1.0, 0.2),
+            &Vector3::new(0.0, -1.0, 0.0),
+        );
+        let mut hit = plane.ray_closest_intersections(&ray).unwrap();
+        hit.complete_surface_frame();
+        hit
+    }
+
+    #[test]
+    fn test_flat_normal_map_keeps_normal() {
+        let plane = Plane::new(Vector3::zeros(), Vector3::y(), Vector3::zeros(), Vector3::zeros(), 1.0, 0.0);
+        let hit = create_floor_hit(&plane);
+        let mut material = Material::new(Vector3::zeros(), Vector3::zeros(), 1.0, 0.0);
+        material.normal_map = Some(serde_yaml::from_str(
+            "type: Checkerboard\neven: [0.5, 0.5, 1.0]\nodd: [0.5, 0.5, 1.0]"
+        ).unwrap());
+
+        assert!((material.shading_normal(&hit) - hit.normal).norm() < 1e-10);
+    }
+
+    #[test]
+    fn test_normal_map_tilts_towards_tangent() {
+        let plane = Plane::new(Vector3::zeros(), Vector3::y(), Vector3::zeros(), Vector3::zeros(), 1.0, 0.0);
+        let hit = create_floor_hit(&plane);
+        let mut material = Material::new(Vector3::zeros(), Vector3::zeros(), 1.0, 0.0);
+        material.normal_map = Some(serde_yaml::from_str(
+            "type: Checkerboard\neven: [1.0, 0.5, 0.5]\nodd: [1.0, 0.5, 0.5]"
+        ).unwrap());
+
+        // (1, 0.5, 0.5) encodes the tangent direction itself
+        let shading_normal = material.shading_normal(&hit);
+        assert!((shading_normal - hit.dpdu.normalize()).norm() < 1e-10);
+        assert_eq!(hit.geometric_normal, Vector3::y());
+    }
+
+    #[test]
+    fn test_constant_bump_map_keeps_normal() {
+        let plane = Plane::new(Vector3::zeros(), Vector3::y(), Vector3::zeros(), Vector3::zeros(), 1.0, 0.0);
+        let hit = create_floor_hit(&plane);
+        let mut material = Material::new(Vector3::zeros(), Vector3::zeros(), 1.0, 0.0);
+        material.bump_map = Some(ScalarParameter::Constant(0.5));
+
+        assert!((material.shading_normal(&hit) - hit.normal).norm() < 1e-10);
+    }
+
+    #[test]
+    fn test_bump_map_slope_tilts_normal_downhill() {
+        let plane = Plane::new(Vector3::zeros(), Vector3::y(), Vector3::zeros(), Vector3::zeros(), 1.0, 0.0);
+        let hit = create_floor_hit(&plane);
+        // height rising by 0.5 per unit along world x
+        let material = create_bumped_material(
+            "type: Gradient\nstart: [0.0, 0.0, 0.0]\nend: [1.0, 1.0, 1.0]\ndirection: [2.0, 0.0, 0.0]\nspace: World"
+        );
+
+        let shading_normal = material.shading_normal(&hit);
+        let expected = Vector3::new(-0.5, 1.0, 0.0).normalize();
+        assert!((shading_normal - expected).norm() < 1e-6);
+        // the location used to spawn secondary rays is still offset along the geometric normal
+        assert!((hit.biased_location.y - 0.001).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_opacity_threshold() {
+        let plane = Plane::new(Vector3::zeros(), Vector3::y(), Vector3::zeros(), Vector3::zeros(), 1.0, 0.0);
+        let hit = create_floor_hit(&plane);
+        let mut sampler = IndependentSampler::new(0, 0);
+        let mut material = Material::new(Vector3::zeros(), Vector3::zeros(), 1.0, 0.0);
+        assert!(!material.is_cut_out(&hit, &mut sampler));
+
+        material.opacity = Some(ScalarParameter::Constant(0.4));
+        assert!(material.is_cut_out(&hit, &mut sampler));
+
+        material.alpha_threshold = 0.3;
+        assert!(!material.is_cut_out(&hit, &mut sampler));
+    }
+
+    #[test]
+    fn test_stochastic_opacity_extremes() {
+        let plane = Plane::new(Vector3::zeros(), Vector3::y(), Vector3::zeros(), Vector3::zeros(), 1.0, 0.0);
+        let hit = create_floor_hit(&plane);
+        let mut sampler = IndependentSampler::new(0, 0);
+        let mut material = Material::new(Vector3::zeros(), Vector3::zeros(), 1.0, 0.0);
+        material.stochastic_alpha = true;
+
+        material.opacity = Some(ScalarParameter::Constant(1.0));
+        assert!((0..100).all(|_| !material.is_cut_out(&hit, &mut sampler)));
+
+        material.opacity = Some(ScalarParameter::Constant(0.0));
+        assert!((0..100).all(|_| material.is_cut_out(&hit, &mut sampler)));
+    }
+
+    #[test]
+    fn test_invalid_texture_fails_to_parse() {
+        let result: Result<Material, _> = serde_yaml::from_str(
+            "color: {type: ImageTexture, path: does/not/exist.png}\nemissive_color: [0.0, 0.0, 0.0]\nalbedo: 1\nreflectivity: 0"
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_subsurface_medium_coefficients() {
+        use crate::raytracer::medium::Medium;
+        use crate::raytracer::ray::Ray;
+
+        let subsurface = Subsurface::new(Vector3::new(1.0, 2.0, 4.0), Vector3::new(0.5, 0.5, 1.0), 0.0);
+        let medium = subsurface.medium();
+        let ray = Ray::new_from_origine_and_direction(&Vector3::zeros(), &Vector3::z());
+
+        // one mean free path attenuates by 1 / e
+        let transmittance = medium.transmittance(&ray, 1.0, &mut IndependentSampler::new(0, 0));
+        assert!((transmittance - Vector3::new(-1.0_f64, -0.5, -0.25).map(f64::exp)).norm() < 1e-10);
+        assert_eq!(medium.sigma_a, Vector3::new(0.5, 0.25, 0.0));
+    }
+
+    #[test]
+    fn test_subsurface_is_parsed() {
+        let material: Material = serde_yaml::from_str(
+            "color: [1.0, 1.0, 1.0]\nemissive_color: [0.0, 0.0, 0.0]\nalbedo: 1\nreflectivity: 0\nsubsurface:\n  mean_free_path: [0.5, 0.2, 0.1]\n  scatter_color: [0.9, 0.8, 0.7]"
+        ).unwrap();
+        let subsurface = material.subsurface.unwrap();
+        assert_eq!(subsurface.scatter_color, Vector3::new(0.9, 0.8, 0.7));
+        assert_eq!(subsurface.g, 0.0);
+    }
+}
@@ -0,0 +1,348 @@
+use serde::{Serialize, Deserialize};
+use std::sync::Arc;
+use na::Vector3;
+
+use super::ray::*;
+use super::density_grid::DensityGrid;
+use super::samplers::Sampler;
+use super::utils::create_coordinate_system_from_up_vector;
+
+pub enum MediumSample {
+    // the ray scattered at this distance, weight already includes the scattering albedo
+    Scattered { distance: f64, weight: Vector3<f64> },
+    // the ray went through the medium up to the queried distance
+    Passed { weight: Vector3<f64> },
+}
+
+pub trait Medium : Sync + Send + std::fmt::Debug
+{
+    // samples the distance of the next scattering event along the ray, up to max_distance
+    fn sample_distance(&self, ray: &Ray, max_distance: f64, sampler: &mut dyn Sampler) -> MediumSample;
+
+    // fraction of light going through the medium from the ray origin to the given distance
+    fn transmittance(&self, ray: &Ray, distance: f64, sampler: &mut dyn Sampler) -> Vector3<f64>;
+
+    // Henyey-Greenstein asymmetry, -1 scatters backward, 0 isotropically and 1 forward
+    fn phase_asymmetry(&self) -> f64;
+}
+
+// Henyey-Greenstein phase function, cos_theta is taken between the propagation directions before and after scattering
+pub fn henyey_greenstein(cos_theta: f64, g: f64) -> f64 {
+    let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * std::f64::consts::PI * denominator * denominator.sqrt())
+}
+
+// samples a new propagation direction proportionally to the phase function, the weight is therefore 1
+pub fn sample_henyey_greenstein(direction: &Vector3<f64>, g: f64, sampler: &mut dyn Sampler) -> Vector3<f64> {
+    let (xi_1, xi_2) = sampler.next_2d();
+    let cos_theta = if g.abs() < 1e-3 {
+        1.0 - 2.0 * xi_1
+    } else {
+        let ratio = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi_1);
+        (1.0 + g * g - ratio * ratio) / (2.0 * g)
+    };
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * std::f64::consts::PI * xi_2;
+
+    let [tangent, axis, bitangent] = create_coordinate_system_from_up_vector(direction);
+    tangent * (sin_theta * phi.cos()) + axis * cos_theta + bitangent * (sin_theta * phi.sin())
+}
+
+fn exponential_transmittance(sigma_t: &Vector3<f64>, distance: f64) -> Vector3<f64> {
+    // a component without extinction transmits everything, even over an infinite distance
+    sigma_t.map(|sigma| if sigma > 0.0 { (-sigma * distance).exp() } else { 1.0 })
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HomogeneousMedium {
+    // absorption and scattering coefficients per unit of distance, for each color channel
+    pub sigma_a: Vector3<f64>,
+    pub sigma_s: Vector3<f64>,
+    #[serde(default)]
+    pub g: f64,
+}
+
+impl HomogeneousMedium {
+    pub fn new(sigma_a: Vector3<f64>, sigma_s: Vector3<f64>, g: f64) -> HomogeneousMedium {
+        HomogeneousMedium { sigma_a, sigma_s, g }
+    }
+
+    fn sigma_t(&self) -> Vector3<f64> {
+        self.sigma_a + self.sigma_s
+    }
+}
+
+impl Medium for HomogeneousMedium {
+    fn sample_distance(&self, _ray: &Ray, max_distance: f64, sampler: &mut dyn Sampler) -> MediumSample {
+        let sigma_t = self.sigma_t();
+        // distances are sampled with the mean extinction, the weights correct for the per channel difference
+        let sampling_sigma = sigma_t.mean();
+        if sampling_sigma <= 0.0 {
+            return MediumSample::Passed { weight: Vector3::from_element(1.0) };
+        }
+
+        let distance = -(1.0 - sampler.next_1d()).ln() / sampling_sigma;
+        if distance < max_distance {
+            let pdf = sampling_sigma * (-sampling_sigma * distance).exp();
+            let weight = self.sigma_s.component_mul(&exponential_transmittance(&sigma_t, distance)) / pdf;
+            MediumSample::Scattered { distance, weight }
+        } else {
+            let probability = (-sampling_sigma * max_distance).exp();
+            MediumSample::Passed { weight: exponential_transmittance(&sigma_t, max_distance) / probability }
+        }
+    }
+
+    fn transmittance(&self, _ray: &Ray, distance: f64, _sampler: &mut dyn Sampler) -> Vector3<f64> {
+        exponential_transmittance(&self.sigma_t(), distance)
+    }
+
+    fn phase_asymmetry(&self) -> f64 {
+        self.g
+    }
+}
+
+// medium whose coefficients are scaled by the density of a voxel grid stretched over a box
+#[derive(Clone, Debug)]
+pub struct GridMedium {
+    grid: Arc<DensityGrid>,
+    // scene coordinates of the grid corner with the lowest coordinates, and size of the grid box
+    corner: Vector3<f64>,
+    size: Vector3<f64>,
+    sigma_a: Vector3<f64>,
+    sigma_s: Vector3<f64>,
+    g: f64,
+}
+
+impl GridMedium {
+    // the coefficients are those of a unit density, the density scale is folded into them
+    pub fn new(grid: Arc<DensityGrid>, corner: Vector3<f64>, size: Vector3<f64>, sigma_a: Vector3<f64>, sigma_s: Vector3<f64>, g: f64) -> GridMedium {
+        GridMedium { grid, corner, size, sigma_a, sigma_s, g }
+    }
+
+    fn density(&self, point: &Vector3<f64>) -> f64 {
+        self.grid.density(&(point - self.corner).component_div(&self.size))
+    }
+
+    // extinction upper bound over the whole grid and all channels
+    fn majorant(&self) -> f64 {
+        self.grid.max_density() * (self.sigma_a + self.sigma_s).max()
+    }
+
+    // distances along the ray between which it is inside the grid box, clipped to [0, max_distance]
+    fn overlap(&self, ray: &Ray, max_distance: f64) -> Option<(f64, f64)> {
+        let mut start: f64 = 0.0;
+        let mut end = max_distance;
+        for axis in 0..3 {
+            let to_lower = (self.corner[axis] - ray.origin[axis]) * ray.inverse[axis];
+            let to_upper = (self.corner[axis] + self.size[axis] - ray.origin[axis]) * ray.inverse[axis];
+            start = start.max(to_lower.min(to_upper));
+            end = end.min(to_lower.max(to_upper));
+        }
+        if start < end { Some((start, end)) } else { None }
+    }
+}
+
+impl Medium for GridMedium {
+    // delta tracking: tentative collisions are sampled with the majorant, then classified as absorption,
+    // scattering or null collision proportionally to the local coefficients
+    fn sample_distance(&self, ray: &Ray, max_distance: f64, sampler: &mut dyn Sampler) -> MediumSample {
+        let majorant = self.majorant();
+        let (mut distance, end) = match self.overlap(ray, max_distance) {
+            Some(range) if majorant > 0.0 => range,
+            _ => return MediumSample::Passed { weight: Vector3::from_element(1.0) },
+        };
+
+        let mut weight = Vector3::from_element(1.0);
+        loop {
+            distance -= (1.0 - sampler.next_1d()).ln() / majorant;
+            if distance >= end {
+                return MediumSample::Passed { weight };
+            }
+
+            let density = self.density(&(ray.origin + ray.unit_vec * distance));
+            let sigma_a = self.sigma_a * density;
+            let sigma_s = self.sigma_s * density;
+            let sigma_n = Vector3::from_element(majorant) - sigma_a - sigma_s;
+
+            // the mean channel drives the choice, the weights correct the other channels
+            let event = sampler.next_1d() * majorant;
+            if event < sigma_a.mean() {
+                return MediumSample::Passed { weight: Vector3::zeros() };
+            }
+            if event < sigma_a.mean() + sigma_s.mean() {
+                return MediumSample::Scattered { distance, weight: weight.component_mul(&sigma_s) / sigma_s.mean() };
+            }
+            weight.component_mul_assign(&(sigma_n / sigma_n.mean()));
+        }
+    }
+
+    // ratio tracking: product of the null collision probabilities at the tentative collisions
+    fn transmittance(&self, ray: &Ray, distance: f64, sampler: &mut dyn Sampler) -> Vector3<f64> {
+        let majorant = self.majorant();
+        let mut transmittance = Vector3::from_element(1.0);
+        let (mut traveled, end) = match self.overlap(ray, distance) {
+            Some(range) if majorant > 0.0 => range,
+            _ => return transmittance,
+        };
+
+        loop {
+            traveled -= (1.0 - sampler.next_1d()).ln() / majorant;
+            if traveled >= end {
+                return transmittance;
+            }
+            let sigma_t = (self.sigma_a + self.sigma_s) * self.density(&(ray.origin + ray.unit_vec * traveled));
+            transmittance.component_mul_assign(&(Vector3::from_element(1.0) - sigma_t / majorant));
+        }
+    }
+
+    fn phase_asymmetry(&self) -> f64 {
+        self.g
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raytracer::samplers::independent::IndependentSampler;
+
+    const EPSILON: f64 = 1e-10;
+
+    fn create_ray() -> Ray {
+        Ray::new_from_origine_and_direction(&Vector3::zeros(), &Vector3::new(0.0, 0.0, 1.0))
+    }
+
+    #[test]
+    fn test_henyey_greenstein_is_normalized() {
+        for g in [-0.7, 0.0, 0.5, 0.9] {
+            // integrate over the sphere: 2 pi * integral of p(cos) d(cos)
+            let steps = 20000;
+            let integral: f64 = (0..steps)
+                .map(|i| {
+                    let cos_theta = -1.0 + 2.0 * (i as f64 + 0.5) / steps as f64;
+                    henyey_greenstein(cos_theta, g) * 2.0 / steps as f64
+                })
+                .sum::<f64>() * 2.0 * std::f64::consts::PI;
+            assert!((integral - 1.0).abs() < 1e-3, "g = {} integrates to {}", g, integral);
+        }
+    }
+
+    #[test]
+    fn test_isotropic_phase_is_uniform() {
+        let expected = 1.0 / (4.0 * std::f64::consts::PI);
+        assert!((henyey_greenstein(1.0, 0.0) - expected).abs() < EPSILON);
+        assert!((henyey_greenstein(-1.0, 0.0) - expected).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_henyey_greenstein_sampling_mean_cosine_is_g() {
+        let mut sampler = IndependentSampler::new(0, 0);
+        let direction = Vector3::new(0.0, 1.0, 0.0);
+        for g in [-0.5, 0.0, 0.8] {
+            let samples = 20000;
+            let mean_cosine = (0..samples)
+                .map(|_| sample_henyey_greenstein(&direction, g, &mut sampler).dot(&direction))
+                .sum::<f64>() / samples as f64;
+            assert!((mean_cosine - g).abs() < 0.03, "g = {} gives mean cosine {}", g, mean_cosine);
+        }
+    }
+
+    #[test]
+    fn test_homogeneous_transmittance() {
+        let mut sampler = IndependentSampler::new(0, 0);
+        let medium = HomogeneousMedium::new(Vector3::new(0.5, 0.0, 0.0), Vector3::new(0.5, 1.0, 0.0), 0.0);
+        let transmittance = medium.transmittance(&create_ray(), 2.0, &mut sampler);
+
+        assert!((transmittance.x - (-2.0_f64).exp()).abs() < EPSILON);
+        assert!((transmittance.y - (-2.0_f64).exp()).abs() < EPSILON);
+        assert!((transmittance.z - 1.0).abs() < EPSILON);
+        assert!((medium.transmittance(&create_ray(), f64::INFINITY, &mut sampler).z - 1.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_grey_medium_scattering_weight_is_albedo() {
+        let mut sampler = IndependentSampler::new(0, 0);
+        let medium = HomogeneousMedium::new(Vector3::from_element(0.25), Vector3::from_element(0.75), 0.0);
+        for _ in 0..100 {
+            match medium.sample_distance(&create_ray(), f64::INFINITY, &mut sampler) {
+                MediumSample::Scattered { weight, .. } => assert!((weight - Vector3::from_element(0.75)).norm() < 1e-9),
+                MediumSample::Passed { .. } => panic!("an infinite ray always scatters"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_distance_sampling_is_unbiased() {
+        let mut sampler = IndependentSampler::new(0, 0);
+        // the average passing weight times the pass rate must equal the transmittance
+        let medium = HomogeneousMedium::new(Vector3::new(0.2, 0.4, 0.6), Vector3::zeros(), 0.0);
+        let samples = 20000;
+        let estimate = (0..samples)
+            .map(|_| match medium.sample_distance(&create_ray(), 1.0, &mut sampler) {
+                MediumSample::Passed { weight } => weight,
+                MediumSample::Scattered { .. } => Vector3::zeros(),
+            })
+            .sum::<Vector3<f64>>() / samples as f64;
+        let expected = medium.transmittance(&create_ray(), 1.0, &mut sampler);
+        assert!((estimate - expected).norm() < 0.05);
+    }
+
+    fn create_grid_medium(densities: Vec<f32>, sigma_a: Vector3<f64>, sigma_s: Vector3<f64>) -> GridMedium {
+        // a 2 unit box from z = 1 to z = 3 on the ray's axis, densities vary along z
+        let grid = DensityGrid::new([1, 1, densities.len()], densities).unwrap();
+        GridMedium::new(Arc::new(grid), Vector3::new(-1.0, -1.0, 1.0), Vector3::from_element(2.0), sigma_a, sigma_s, 0.0)
+    }
+
+    #[test]
+    fn test_constant_grid_ratio_tracking_matches_homogeneous() {
+        let mut sampler = IndependentSampler::new(0, 0);
+        let sigma_a = Vector3::new(0.1, 0.3, 0.5);
+        let medium = create_grid_medium(vec![1.0; 4], sigma_a, Vector3::zeros());
+        let samples = 20000;
+        let estimate = (0..samples)
+            .map(|_| medium.transmittance(&create_ray(), 10.0, &mut sampler))
+            .sum::<Vector3<f64>>() / samples as f64;
+        // only the 2 units inside the box attenuate
+        let expected = HomogeneousMedium::new(sigma_a, Vector3::zeros(), 0.0).transmittance(&create_ray(), 2.0, &mut sampler);
+        assert!((estimate - expected).norm() < 0.02, "{} instead of {}", estimate, expected);
+    }
+
+    #[test]
+    fn test_grid_transmittance_outside_box_is_one() {
+        let mut sampler = IndependentSampler::new(0, 0);
+        let medium = create_grid_medium(vec![5.0; 2], Vector3::from_element(1.0), Vector3::zeros());
+        assert_eq!(medium.transmittance(&create_ray(), 0.5, &mut sampler), Vector3::from_element(1.0));
+
+        let away = Ray::new_from_origine_and_direction(&Vector3::zeros(), &Vector3::new(0.0, 0.0, -1.0));
+        assert_eq!(medium.transmittance(&away, f64::INFINITY, &mut sampler), Vector3::from_element(1.0));
+    }
+
+    #[test]
+    fn test_delta_tracking_is_unbiased() {
+        let mut sampler = IndependentSampler::new(0, 0);
+        // passing weights must average to the transmittance of a varying density
+        let medium = create_grid_medium(vec![0.0, 0.5, 2.0, 1.0], Vector3::new(0.2, 0.4, 0.6), Vector3::new(0.3, 0.1, 0.0));
+        let samples = 40000;
+        let passed = (0..samples)
+            .map(|_| match medium.sample_distance(&create_ray(), 10.0, &mut sampler) {
+                MediumSample::Passed { weight } => weight,
+                MediumSample::Scattered { .. } => Vector3::zeros(),
+            })
+            .sum::<Vector3<f64>>() / samples as f64;
+        let transmittance = (0..samples)
+            .map(|_| medium.transmittance(&create_ray(), 10.0, &mut sampler))
+            .sum::<Vector3<f64>>() / samples as f64;
+        assert!((passed - transmittance).norm() < 0.03, "{} instead of {}", passed, transmittance);
+    }
+
+    #[test]
+    fn test_grid_scatters_inside_box() {
+        let mut sampler = IndependentSampler::new(0, 0);
+        let medium = create_grid_medium(vec![1.0; 2], Vector3::zeros(), Vector3::from_element(3.0));
+        for _ in 0..100 {
+            if let MediumSample::Scattered { distance, weight } = medium.sample_distance(&create_ray(), 10.0, &mut sampler) {
+                assert!((1.0..3.0).contains(&distance));
+                assert!((weight - Vector3::from_element(1.0)).norm() < 1e-9);
+            }
+        }
+    }
+}
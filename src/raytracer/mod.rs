@@ -1,7 +1,17 @@
 pub mod ray;
 pub mod shapes;
+pub mod textures;
+pub mod material;
+pub mod medium;
+pub mod density_grid;
+pub mod samplers;
+pub mod film;
+pub mod checkpoint;
+pub mod tiles;
 pub mod scene;
+pub mod animation;
 pub mod camera;
+pub mod exposure;
 pub mod intersection;
 pub mod distant_light;
 pub mod utils;
@@ -8,7 +8,10 @@ pub struct Ray {
     pub origin: Vector3<f64>,
     pub unit_vec: Vector3<f64>,
     pub inverse: Vector3<f64>,
-    pub sign: [usize; 3]
+    pub sign: [usize; 3],
+    // instant the ray is traced at, moving shapes are hit where they are at that time
+    #[serde(default)]
+    pub time: f64,
 }
 
 impl Ray {
@@ -25,7 +28,7 @@ impl Ray {
             (inverse.y < 0.0) as usize,
             (inverse.z < 0.0) as usize,
         ];
-        Ray {origin : (*origin).clone(), unit_vec: unit, inverse: inverse, sign: sign}
+        Ray {origin : (*origin).clone(), unit_vec: unit, inverse: inverse, sign: sign, time: 0.0}
     }
 
     pub fn new_from_origine_and_direction(origin: &Vector3<f64>, direction: &Vector3<f64>) -> Ray {
@@ -40,7 +43,13 @@ impl Ray {
             (inverse.y < 0.0) as usize,
             (inverse.z < 0.0) as usize,
         ];
-        Ray {origin : (*origin).clone(), unit_vec: unit, inverse: inverse, sign: sign}
+        Ray {origin : (*origin).clone(), unit_vec: unit, inverse: inverse, sign: sign, time: 0.0}
+    }
+
+    // secondary rays are traced at the time of the ray they come from
+    pub fn with_time(mut self, time: f64) -> Ray {
+        self.time = time;
+        self
     }
 }
 
@@ -119,6 +128,13 @@ mod tests {
         assert_eq!(ray.sign, [1, 1, 1]);
     }
 
+    #[test]
+    fn test_ray_time() {
+        let ray = Ray::new_from_points(&Vector3::zeros(), &Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(ray.time, 0.0);
+        assert_eq!(ray.with_time(0.25).time, 0.25);
+    }
+
     #[test]
     fn test_ray_sign_mixed_direction() {
         let origin = Vector3::new(0.0, 0.0, 0.0);
@@ -0,0 +1,197 @@
+use std::sync::OnceLock;
+
+use super::sampler::*;
+use super::sobol::SobolSampler;
+
+// side of the tileable blue noise mask, in pixels
+const MASK_SIZE: usize = 64;
+// standard deviation of the gaussian energy used to find clusters and voids
+const MASK_SIGMA: f64 = 1.5;
+
+static BLUE_NOISE_MASK: OnceLock<Vec<f64>> = OnceLock::new();
+
+// every pixel uses the same scrambled Sobol points, rotated by a blue noise mask value:
+// neighbouring pixels get very different rotations, so the remaining error looks like fine grain
+// instead of blotches (Georgiev and Fajardo's "Blue-noise Dithered Sampling")
+pub struct BlueNoiseSampler {
+    seed: u64,
+    pixel: (u32, u32),
+    sequence: SobolSampler,
+    dimension: u64,
+}
+
+impl BlueNoiseSampler {
+    pub fn new(seed: u64, pixel: (u32, u32)) -> BlueNoiseSampler {
+        BlueNoiseSampler { seed, pixel, sequence: SobolSampler::new(seed, 0), dimension: 0 }
+    }
+
+    // each dimension reads the mask with its own toroidal shift, so that dimensions are not correlated
+    fn rotation(&self) -> f64 {
+        let shift = hash(&[self.seed, self.dimension]);
+        let x = (self.pixel.0 as usize + (shift as usize % MASK_SIZE)) % MASK_SIZE;
+        let y = (self.pixel.1 as usize + ((shift >> 32) as usize % MASK_SIZE)) % MASK_SIZE;
+        blue_noise_mask()[x + y * MASK_SIZE]
+    }
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn start_sample(&mut self, sample_index: u64) {
+        self.sequence.start_sample(sample_index);
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let value = (self.sequence.next_1d() + self.rotation()).fract();
+        self.dimension += 1;
+        value
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        let (x, y) = self.sequence.next_2d();
+        let x = (x + self.rotation()).fract();
+        self.dimension += 1;
+        let y = (y + self.rotation()).fract();
+        self.dimension += 1;
+        (x, y)
+    }
+}
+
+// values in (0, 1), one per pixel of the tile, generated on first use
+pub fn blue_noise_mask() -> &'static [f64] {
+    BLUE_NOISE_MASK.get_or_init(|| {
+        let pixel_count = MASK_SIZE * MASK_SIZE;
+        generate_ranks()
+            .into_iter()
+            .map(|rank| (rank as f64 + 0.5) / pixel_count as f64)
+            .collect()
+    })
+}
+
+// binary pattern on the torus with the gaussian energy each pixel receives from the set pixels
+struct Pattern {
+    set: Vec<bool>,
+    energy: Vec<f64>,
+    kernel: Vec<f64>,
+}
+
+impl Pattern {
+    fn new() -> Pattern {
+        let pixel_count = MASK_SIZE * MASK_SIZE;
+        let wrapped = |coordinate: usize| coordinate.min(MASK_SIZE - coordinate) as f64;
+        let kernel = (0..pixel_count)
+            .map(|offset| {
+                let (dx, dy) = (wrapped(offset % MASK_SIZE), wrapped(offset / MASK_SIZE));
+                (-(dx * dx + dy * dy) / (2.0 * MASK_SIGMA * MASK_SIGMA)).exp()
+            })
+            .collect();
+        Pattern { set: vec![false; pixel_count], energy: vec![0.0; pixel_count], kernel }
+    }
+
+    fn toggle(&mut self, pixel: usize) {
+        self.set[pixel] = !self.set[pixel];
+        let sign = if self.set[pixel] { 1.0 } else { -1.0 };
+        let (px, py) = (pixel % MASK_SIZE, pixel / MASK_SIZE);
+        for (other, energy) in self.energy.iter_mut().enumerate() {
+            let dx = (other % MASK_SIZE + MASK_SIZE - px) % MASK_SIZE;
+            let dy = (other / MASK_SIZE + MASK_SIZE - py) % MASK_SIZE;
+            *energy += sign * self.kernel[dx + dy * MASK_SIZE];
+        }
+    }
+
+    // set pixel with the most energy around it
+    fn tightest_cluster(&self) -> usize {
+        (0..self.set.len())
+            .filter(|pixel| self.set[*pixel])
+            .max_by(|a, b| self.energy[*a].partial_cmp(&self.energy[*b]).unwrap())
+            .unwrap()
+    }
+
+    // unset pixel with the least energy around it
+    fn largest_void(&self) -> usize {
+        (0..self.set.len())
+            .filter(|pixel| !self.set[*pixel])
+            .min_by(|a, b| self.energy[*a].partial_cmp(&self.energy[*b]).unwrap())
+            .unwrap()
+    }
+}
+
+// Ulichney's void-and-cluster: ranks the pixels so that every threshold of the ranks is evenly spread
+fn generate_ranks() -> Vec<usize> {
+    let pixel_count = MASK_SIZE * MASK_SIZE;
+    let mut pattern = Pattern::new();
+
+    // random initial pattern, relaxed by moving its tightest cluster into its largest void until stable
+    let initial_count = pixel_count / 10;
+    let mut candidate = 0;
+    while pattern.set.iter().filter(|set| **set).count() < initial_count {
+        let pixel = (hash(&[candidate]) % pixel_count as u64) as usize;
+        if !pattern.set[pixel] {
+            pattern.toggle(pixel);
+        }
+        candidate += 1;
+    }
+    loop {
+        let cluster = pattern.tightest_cluster();
+        pattern.toggle(cluster);
+        let void = pattern.largest_void();
+        pattern.toggle(void);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; pixel_count];
+    // lower ranks: remove the tightest clusters of a copy of the initial pattern
+    let mut shrinking = Pattern { set: pattern.set.clone(), energy: pattern.energy.clone(), kernel: pattern.kernel.clone() };
+    for rank in (0..initial_count).rev() {
+        let cluster = shrinking.tightest_cluster();
+        shrinking.toggle(cluster);
+        ranks[cluster] = rank;
+    }
+    // higher ranks: fill the largest voids, which are also the tightest clusters of unset pixels
+    for rank in initial_count..pixel_count {
+        let void = pattern.largest_void();
+        pattern.toggle(void);
+        ranks[void] = rank;
+    }
+    ranks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mask_is_a_permutation_of_ranks() {
+        let pixel_count = MASK_SIZE * MASK_SIZE;
+        let mut ranks: Vec<usize> = blue_noise_mask().iter().map(|value| (value * pixel_count as f64) as usize).collect();
+        ranks.sort();
+        assert_eq!(ranks, (0..pixel_count).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn test_mask_neighbours_differ_more_than_white_noise() {
+        // uniform white noise gives a mean absolute difference of 1 / 3 between neighbours,
+        // blue noise has little low frequency content so neighbours are further apart
+        let mask = blue_noise_mask();
+        let differences = (0..MASK_SIZE * MASK_SIZE)
+            .map(|pixel| {
+                let right = (pixel % MASK_SIZE + 1) % MASK_SIZE + pixel / MASK_SIZE * MASK_SIZE;
+                (mask[pixel] - mask[right]).abs()
+            })
+            .sum::<f64>() / (MASK_SIZE * MASK_SIZE) as f64;
+        assert!(differences > 0.4, "mean neighbour difference {}", differences);
+    }
+
+    #[test]
+    fn test_same_sequence_with_pixel_rotation() {
+        let first_values = |pixel| {
+            let mut sampler = BlueNoiseSampler::new(0, pixel);
+            sampler.start_sample(0);
+            sampler.next_1d()
+        };
+        // the rotation only depends on the pixel position within the tile
+        assert_eq!(first_values((3, 4)), first_values((3 + MASK_SIZE as u32, 4)));
+        assert_ne!(first_values((3, 4)), first_values((4, 4)));
+    }
+}
@@ -0,0 +1,96 @@
+use super::sampler::*;
+
+// one prime base per dimension, later dimensions fall back to independent values
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+];
+
+// the samples of every pixel are the first points of the Halton sequence,
+// shifted by a per pixel random rotation (Cranley-Patterson) so that neighbouring pixels differ
+#[derive(Clone, Debug)]
+pub struct HaltonSampler {
+    seed: u64,
+    pixel: u64,
+    sample_index: u64,
+    dimension: u64,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64, pixel: u64) -> HaltonSampler {
+        HaltonSampler { seed, pixel, sample_index: 0, dimension: 0 }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, sample_index: u64) {
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let rotation = hash_to_unit(hash(&[self.seed, self.pixel, self.dimension]));
+        let value = match PRIMES.get(self.dimension as usize) {
+            Some(base) => (radical_inverse(*base, self.sample_index) + rotation).fract(),
+            None => hash_to_unit(hash(&[self.seed, self.pixel, self.sample_index, self.dimension])),
+        };
+        self.dimension += 1;
+        value
+    }
+}
+
+// mirrors the digits of the index in the given base around the decimal point
+pub fn radical_inverse(base: u32, mut index: u64) -> f64 {
+    let inverse_base = 1.0 / base as f64;
+    let mut digit_weight = inverse_base;
+    let mut value = 0.0;
+    while index > 0 {
+        value += (index % base as u64) as f64 * digit_weight;
+        index /= base as u64;
+        digit_weight *= inverse_base;
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f64 = 1e-12;
+
+    #[test]
+    fn test_radical_inverse() {
+        let base_2: Vec<f64> = (0..5).map(|index| radical_inverse(2, index)).collect();
+        assert_eq!(base_2, vec![0.0, 0.5, 0.25, 0.75, 0.125]);
+        assert!((radical_inverse(3, 1) - 1.0 / 3.0).abs() < EPSILON);
+        assert!((radical_inverse(3, 5) - (2.0 / 3.0 + 1.0 / 9.0)).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_first_dimension_is_stratified() {
+        // 8 consecutive base 2 points fall in 8 different eighths, even after the rotation
+        let mut sampler = HaltonSampler::new(4, 9);
+        let mut eighths: Vec<u32> = (0..8)
+            .map(|sample_index| {
+                sampler.start_sample(sample_index);
+                (sampler.next_1d() * 8.0) as u32
+            })
+            .collect();
+        eighths.sort();
+        assert_eq!(eighths, (0..8).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn test_dimensions_past_primes_are_still_uniform() {
+        let mut sampler = HaltonSampler::new(0, 0);
+        let samples = 10000;
+        let mean = (0..samples)
+            .map(|sample_index| {
+                sampler.start_sample(sample_index);
+                (0..PRIMES.len()).for_each(|_| { sampler.next_1d(); });
+                sampler.next_1d()
+            })
+            .sum::<f64>() / samples as f64;
+        assert!((mean - 0.5).abs() < 0.02);
+    }
+}
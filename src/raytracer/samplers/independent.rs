@@ -0,0 +1,72 @@
+use super::sampler::*;
+
+// each value is a hash of (seed, pixel, sample index, dimension)
+#[derive(Clone, Debug)]
+pub struct IndependentSampler {
+    seed: u64,
+    pixel: u64,
+    sample_index: u64,
+    // number of values already drawn for the current sample
+    dimension: u64,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64, pixel: u64) -> IndependentSampler {
+        IndependentSampler { seed, pixel, sample_index: 0, dimension: 0 }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_sample(&mut self, sample_index: u64) {
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let value = hash_to_unit(hash(&[self.seed, self.pixel, self.sample_index, self.dimension]));
+        self.dimension += 1;
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_streams_differ_by_seed_pixel_and_sample() {
+        let value = |seed, pixel, sample_index| {
+            let mut sampler = IndependentSampler::new(seed, pixel);
+            sampler.start_sample(sample_index);
+            sampler.next_1d()
+        };
+        let reference = value(0, 0, 0);
+        assert_ne!(reference, value(1, 0, 0));
+        assert_ne!(reference, value(0, 1, 0));
+        assert_ne!(reference, value(0, 0, 1));
+        assert_eq!(reference, value(0, 0, 0));
+    }
+
+    #[test]
+    fn test_start_sample_restarts_dimensions() {
+        let mut sampler = IndependentSampler::new(3, 5);
+        sampler.start_sample(2);
+        let first = sampler.next_2d();
+        sampler.next_1d();
+        sampler.start_sample(2);
+        assert_eq!(sampler.next_2d(), first);
+    }
+
+    #[test]
+    fn test_values_are_uniform() {
+        let mut sampler = IndependentSampler::new(0, 0);
+        let samples = 100000;
+        let values: Vec<f64> = (0..samples).map(|_| sampler.next_1d()).collect();
+        assert!(values.iter().all(|value| (0.0..1.0).contains(value)));
+
+        let mean = values.iter().sum::<f64>() / samples as f64;
+        assert!((mean - 0.5).abs() < 0.01);
+        let below_quarter = values.iter().filter(|value| **value < 0.25).count() as f64 / samples as f64;
+        assert!((below_quarter - 0.25).abs() < 0.01);
+    }
+}
@@ -0,0 +1,8 @@
+pub mod sampler;
+pub mod independent;
+pub mod stratified;
+pub mod halton;
+pub mod sobol;
+pub mod blue_noise;
+
+pub use sampler::*;
@@ -0,0 +1,154 @@
+use serde::{Serialize, Deserialize};
+use std::str::FromStr;
+
+use super::independent::IndependentSampler;
+use super::stratified::StratifiedSampler;
+use super::halton::HaltonSampler;
+use super::sobol::SobolSampler;
+use super::blue_noise::BlueNoiseSampler;
+
+// source of the sample values in [0, 1) used by the camera, the materials and the media;
+// a pixel draws the same values whatever thread renders it and in whatever order
+pub trait Sampler {
+    // restarts the sequence of dimensions for the given sample of the pixel
+    fn start_sample(&mut self, sample_index: u64);
+
+    fn next_1d(&mut self) -> f64;
+
+    // two dimensions drawn together, low-discrepancy samplers stratify them jointly
+    fn next_2d(&mut self) -> (f64, f64) {
+        (self.next_1d(), self.next_1d())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Default)]
+pub enum SamplerKind {
+    // independent uniform values
+    #[default]
+    Independent,
+    // jittered strata over the samples of a pixel
+    Stratified,
+    // Halton sequence rotated per pixel
+    Halton,
+    // Owen-scrambled Sobol sequence
+    Sobol,
+    // Sobol sequence shared by all pixels, offset by a blue noise mask
+    BlueNoise,
+}
+
+impl SamplerKind {
+    // samples_per_pixel is the number of samples the pixel will take, stratified samplers divide [0, 1) by it
+    pub fn create(&self, seed: u64, pixel: (u32, u32), pixel_index: u64, samples_per_pixel: u32) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(seed, pixel_index)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(seed, pixel_index, samples_per_pixel)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed, pixel_index)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed, pixel_index)),
+            SamplerKind::BlueNoise => Box::new(BlueNoiseSampler::new(seed, pixel)),
+        }
+    }
+}
+
+// command line names: independent, stratified, halton, sobol, blue-noise
+impl FromStr for SamplerKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().replace(['-', '_'], "").as_str() {
+            "independent" => Ok(SamplerKind::Independent),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            "bluenoise" => Ok(SamplerKind::BlueNoise),
+            _ => Err(format!("unknown sampler '{}', expected independent, stratified, halton, sobol or blue-noise", name)),
+        }
+    }
+}
+
+// splitmix64 finalizer, a cheap mix where every input bit affects every output bit
+fn mix(mut value: u64) -> u64 {
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d049bb133111eb);
+    value ^ (value >> 31)
+}
+
+pub fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x9e3779b97f4a7c15, |hash, value| mix(hash ^ mix(*value)))
+}
+
+// uniform value in [0, 1) from the 53 high bits of a hash, they fill the mantissa of a double
+pub fn hash_to_unit(hash: u64) -> f64 {
+    (hash >> 11) as f64 * (1.0 / (1_u64 << 53) as f64)
+}
+
+// uniform value in [0, 1) from 32 fixed point bits
+pub fn bits_to_unit(bits: u32) -> f64 {
+    bits as f64 * (1.0 / (1_u64 << 32) as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_kinds() -> [SamplerKind; 5] {
+        [SamplerKind::Independent, SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol, SamplerKind::BlueNoise]
+    }
+
+    #[test]
+    fn test_every_sampler_is_deterministic_and_in_range() {
+        for kind in all_kinds() {
+            let draw = || {
+                let mut sampler = kind.create(3, (5, 7), 453, 16);
+                (0..16_u64)
+                    .flat_map(|sample_index| {
+                        sampler.start_sample(sample_index);
+                        let (x, y) = sampler.next_2d();
+                        vec![x, y, sampler.next_1d()]
+                    })
+                    .collect::<Vec<f64>>()
+            };
+            let values = draw();
+            assert!(values.iter().all(|value| (0.0..1.0).contains(value)), "{:?} out of range", kind);
+            assert_eq!(values, draw(), "{:?} is not deterministic", kind);
+        }
+    }
+
+    #[test]
+    fn test_low_discrepancy_converges_faster() {
+        // integrate x * y over the unit square in many pixels, the exact value is 1 / 4
+        let rms_error = |kind: SamplerKind| {
+            let pixels = 64;
+            let squared_errors = (0..pixels)
+                .map(|pixel| {
+                    let mut sampler = kind.create(0, (pixel as u32, 0), pixel, 64);
+                    let estimate = (0..64)
+                        .map(|sample_index| {
+                            sampler.start_sample(sample_index);
+                            let (x, y) = sampler.next_2d();
+                            x * y
+                        })
+                        .sum::<f64>() / 64.0;
+                    (estimate - 0.25).powi(2)
+                })
+                .sum::<f64>();
+            (squared_errors / pixels as f64).sqrt()
+        };
+        let independent = rms_error(SamplerKind::Independent);
+        for kind in [SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol, SamplerKind::BlueNoise] {
+            assert!(rms_error(kind) < independent / 2.0, "{:?} does not beat independent sampling", kind);
+        }
+    }
+
+    #[test]
+    fn test_sampler_kind_from_str() {
+        assert_eq!("sobol".parse::<SamplerKind>(), Ok(SamplerKind::Sobol));
+        assert_eq!("blue-noise".parse::<SamplerKind>(), Ok(SamplerKind::BlueNoise));
+        assert_eq!("Halton".parse::<SamplerKind>(), Ok(SamplerKind::Halton));
+        assert!("random".parse::<SamplerKind>().is_err());
+    }
+
+    #[test]
+    fn test_sampler_kind_from_yaml() {
+        assert_eq!(serde_yaml::from_str::<SamplerKind>("BlueNoise").unwrap(), SamplerKind::BlueNoise);
+    }
+}
@@ -0,0 +1,122 @@
+use super::sampler::*;
+
+// Sobol (0, 2) sequence padded to any number of dimensions: each pair of dimensions uses the first two
+// Sobol dimensions with a shuffled sample index, and every coordinate is Owen-scrambled,
+// following Burley's "Practical Hash-based Owen Scrambling"
+#[derive(Clone, Debug)]
+pub struct SobolSampler {
+    seed: u64,
+    pixel: u64,
+    sample_index: u64,
+    dimension: u64,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64, pixel: u64) -> SobolSampler {
+        SobolSampler { seed, pixel, sample_index: 0, dimension: 0 }
+    }
+
+    fn scramble_seed(&self, salt: u64) -> u32 {
+        hash(&[self.seed, self.pixel, self.dimension, salt]) as u32
+    }
+
+    fn shuffled_index(&self) -> u32 {
+        nested_uniform_scramble(self.sample_index as u32, self.scramble_seed(0))
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, sample_index: u64) {
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let index = self.shuffled_index();
+        let value = bits_to_unit(nested_uniform_scramble(index.reverse_bits(), self.scramble_seed(1)));
+        self.dimension += 1;
+        value
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        let index = self.shuffled_index();
+        let value = (
+            bits_to_unit(nested_uniform_scramble(index.reverse_bits(), self.scramble_seed(1))),
+            bits_to_unit(nested_uniform_scramble(sobol_second_dimension(index), self.scramble_seed(2))),
+        );
+        self.dimension += 2;
+        value
+    }
+}
+
+// second Sobol dimension as 32 bit fixed point, the first one is the bit reversed index
+pub fn sobol_second_dimension(mut index: u32) -> u32 {
+    let mut direction = 1_u32 << 31;
+    let mut value = 0;
+    while index != 0 {
+        if index & 1 != 0 {
+            value ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    value
+}
+
+// hash whose low bits only depend on lower input bits, with Vegdahl's constants
+fn laine_karras_permutation(mut value: u32, seed: u32) -> u32 {
+    value = value.wrapping_add(seed);
+    value ^= value.wrapping_mul(0x6c50b47c);
+    value ^= value.wrapping_mul(0xb82f1e52);
+    value ^= value.wrapping_mul(0xc7afe638);
+    value ^= value.wrapping_mul(0x8d22f6e6);
+    value
+}
+
+// Owen scrambling of fixed point bits: each bit is flipped depending on the bits above it
+pub fn nested_uniform_scramble(value: u32, seed: u32) -> u32 {
+    laine_karras_permutation(value.reverse_bits(), seed).reverse_bits()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sobol_second_dimension() {
+        let values: Vec<f64> = (0..8).map(|index| bits_to_unit(sobol_second_dimension(index))).collect();
+        assert_eq!(values, vec![0.0, 0.5, 0.75, 0.25, 0.625, 0.125, 0.375, 0.875]);
+    }
+
+    #[test]
+    fn test_scrambled_points_keep_stratification() {
+        // 16 points of a (0, 2) sequence fill every 4x4 cell, and every 16x1 and 1x16 strip
+        let mut sampler = SobolSampler::new(11, 42);
+        let points: Vec<(f64, f64)> = (0..16)
+            .map(|sample_index| {
+                sampler.start_sample(sample_index);
+                sampler.next_2d()
+            })
+            .collect();
+
+        let sorted_cells = |cell: &dyn Fn(&(f64, f64)) -> u32| {
+            let mut cells: Vec<u32> = points.iter().map(cell).collect();
+            cells.sort();
+            cells
+        };
+        let all_cells = (0..16).collect::<Vec<u32>>();
+        assert_eq!(sorted_cells(&|(x, y)| (x * 4.0) as u32 + 4 * (y * 4.0) as u32), all_cells);
+        assert_eq!(sorted_cells(&|(x, _)| (x * 16.0) as u32), all_cells);
+        assert_eq!(sorted_cells(&|(_, y)| (y * 16.0) as u32), all_cells);
+    }
+
+    #[test]
+    fn test_scrambling_depends_on_pixel() {
+        let first_point = |pixel| {
+            let mut sampler = SobolSampler::new(0, pixel);
+            sampler.start_sample(0);
+            sampler.next_2d()
+        };
+        assert_ne!(first_point(0), first_point(1));
+    }
+}
@@ -0,0 +1,140 @@
+use super::sampler::*;
+
+// every dimension is split in samples_per_pixel strata and each sample of the pixel falls in a different one;
+// the strata are shuffled per dimension so that dimensions stay uncorrelated (latin hypercube),
+// pairs of dimensions use a jittered grid when the sample count is a square
+#[derive(Clone, Debug)]
+pub struct StratifiedSampler {
+    seed: u64,
+    pixel: u64,
+    samples_per_pixel: u32,
+    sample_index: u64,
+    dimension: u64,
+}
+
+impl StratifiedSampler {
+    pub fn new(seed: u64, pixel: u64, samples_per_pixel: u32) -> StratifiedSampler {
+        StratifiedSampler { seed, pixel, samples_per_pixel: samples_per_pixel.max(1), sample_index: 0, dimension: 0 }
+    }
+
+    fn dimension_hash(&self, salt: u64) -> u64 {
+        hash(&[self.seed, self.pixel, self.dimension, salt])
+    }
+
+    // stratum of the current sample, samples beyond the expected count wrap around
+    fn stratum(&self, strata: u32) -> u32 {
+        let index = (self.sample_index % strata as u64) as u32;
+        permutation_element(index, strata, self.dimension_hash(0) as u32)
+    }
+
+    fn jitter(&self, salt: u64) -> f64 {
+        hash_to_unit(hash(&[self.seed, self.pixel, self.dimension, self.sample_index, salt]))
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, sample_index: u64) {
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let strata = self.samples_per_pixel;
+        let value = (self.stratum(strata) as f64 + self.jitter(1)) / strata as f64;
+        self.dimension += 1;
+        value
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        let side = (self.samples_per_pixel as f64).sqrt() as u32;
+        if side * side != self.samples_per_pixel {
+            return (self.next_1d(), self.next_1d());
+        }
+        let cell = self.stratum(side * side);
+        let value = (
+            ((cell % side) as f64 + self.jitter(1)) / side as f64,
+            ((cell / side) as f64 + self.jitter(2)) / side as f64,
+        );
+        self.dimension += 2;
+        value
+    }
+}
+
+// element i of a pseudo-random permutation of [0, length) chosen by the seed,
+// from Kensler's "Correlated Multi-Jittered Sampling"
+pub fn permutation_element(mut i: u32, length: u32, seed: u32) -> u32 {
+    let mut mask = length - 1;
+    mask |= mask >> 1;
+    mask |= mask >> 2;
+    mask |= mask >> 4;
+    mask |= mask >> 8;
+    mask |= mask >> 16;
+    // cycle walking: permute within the next power of two until landing inside the range
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & mask) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & mask) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & mask) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & mask) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & mask) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= mask;
+        i ^= i >> 5;
+        if i < length {
+            break;
+        }
+    }
+    i.wrapping_add(seed) % length
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permutation_is_a_bijection() {
+        for length in [1, 5, 16, 100] {
+            let mut elements: Vec<u32> = (0..length).map(|i| permutation_element(i, length, 1234)).collect();
+            elements.sort();
+            assert_eq!(elements, (0..length).collect::<Vec<u32>>());
+        }
+    }
+
+    #[test]
+    fn test_each_stratum_is_used_once() {
+        let samples_per_pixel = 10;
+        let mut sampler = StratifiedSampler::new(1, 2, samples_per_pixel);
+        let mut strata: Vec<u32> = (0..samples_per_pixel as u64)
+            .map(|sample_index| {
+                sampler.start_sample(sample_index);
+                sampler.next_1d();
+                (sampler.next_1d() * samples_per_pixel as f64) as u32
+            })
+            .collect();
+        strata.sort();
+        assert_eq!(strata, (0..samples_per_pixel).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn test_square_counts_use_a_jittered_grid() {
+        let mut sampler = StratifiedSampler::new(1, 2, 16);
+        let mut cells: Vec<u32> = (0..16)
+            .map(|sample_index| {
+                sampler.start_sample(sample_index);
+                let (x, y) = sampler.next_2d();
+                (x * 4.0) as u32 + 4 * (y * 4.0) as u32
+            })
+            .collect();
+        cells.sort();
+        assert_eq!(cells, (0..16).collect::<Vec<u32>>());
+    }
+}
@@ -2,141 +2,463 @@ extern crate image;
 
 use serde::{Serialize, Deserialize};
 use rayon::prelude::*;
-use indicatif::ParallelProgressIterator;
+use indicatif::ProgressBar;
 
 
 use super::{DistantLight, intersection, shapes::*};
 use super::camera::*;
 use super::ray::*;
 use super::utils::*;
+use super::medium::*;
+use super::material::Subsurface;
+use super::samplers::{Sampler, SamplerKind};
+use super::film::{Film, PixelStats};
+use super::tiles::TileSettings;
 use intersection::Intersection;
 
 
 use na::{Vector3, Rotation3};
 
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+const MAX_CUTOUT_CROSSINGS: usize = 8;
+const MAX_MEDIUM_BOUNDARIES: usize = 32;
+const SUBSURFACE_WALKS: usize = 16;
+const MAX_SUBSURFACE_BOUNCES: usize = 256;
+
+fn default_samples_per_pixel() -> u32 {
+    1
+}
+
+// keeps sampling noisy pixels, in batches of samples_per_pixel
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AdaptiveSettings {
+    // a pixel is done once the standard error of its mean luminance, relative to that mean, is below this
+    pub error_threshold: f64,
+    pub max_samples_per_pixel: u32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SamplingSettings {
+    #[serde(default)]
+    pub sampler: SamplerKind,
+    // camera rays per pixel, each one jittered inside the pixel
+    #[serde(default = "default_samples_per_pixel")]
+    pub samples_per_pixel: u32,
+    #[serde(default)]
+    pub adaptive: Option<AdaptiveSettings>,
+    // no sample limit: pixels keep taking batches of samples_per_pixel until the render is stopped
+    // or, with adaptive sampling, until they converge
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unlimited: bool,
+    // seconds after which progressive rendering stops at the end of a pass
+    #[serde(default)]
+    pub time_limit: Option<f64>,
+}
+
+impl Default for SamplingSettings {
+    fn default() -> Self {
+        SamplingSettings {
+            sampler: SamplerKind::default(),
+            samples_per_pixel: default_samples_per_pixel(),
+            adaptive: None,
+            unlimited: false,
+            time_limit: None,
+        }
+    }
+}
+
+impl SamplingSettings {
+    // None when unlimited
+    pub fn max_samples_per_pixel(&self) -> Option<u32> {
+        if self.unlimited {
+            return None;
+        }
+        let samples_per_pixel = self.samples_per_pixel.max(1);
+        match &self.adaptive {
+            Some(adaptive) => Some(adaptive.max_samples_per_pixel.max(samples_per_pixel)),
+            None => Some(samples_per_pixel),
+        }
+    }
+
+    // fraction of the render done: of the time limit when there is one, of the samples otherwise,
+    // and 0 for unlimited renders that only end when they are stopped
+    pub fn progress(&self, film: &Film, elapsed_seconds: f64) -> f64 {
+        match (self.time_limit, self.max_samples_per_pixel()) {
+            (Some(limit), _) => (elapsed_seconds / limit).min(1.0),
+            (None, Some(max_samples_per_pixel)) => film.samples_per_pixel() / max_samples_per_pixel as f64,
+            (None, None) => 0.0,
+        }
+    }
+
+    // done once all the samples are taken, or with adaptive sampling once a full batch leaves the pixel converged
+    pub fn is_pixel_done(&self, pixel: &PixelStats) -> bool {
+        let samples_per_pixel = self.samples_per_pixel.max(1);
+        let converged = match &self.adaptive {
+            Some(adaptive) => pixel.sample_count.is_multiple_of(samples_per_pixel) && pixel.relative_error() <= adaptive.error_threshold,
+            None => false,
+        };
+        converged || self.max_samples_per_pixel().is_some_and(|max_samples_per_pixel| pixel.sample_count >= max_samples_per_pixel)
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Scene {
-    pub camera: Camera,
+    #[serde(deserialize_with = "deserialize_camera")]
+    pub camera: Box<dyn Camera>,
     pub shapes: Vec<Box<dyn Shape3D + Sync>>,
-    pub distant_lights: Vec<DistantLight>
+    pub distant_lights: Vec<DistantLight>,
+    // medium filling all the space that is not inside a medium-filled shape
+    #[serde(default)]
+    pub fog: Option<HomogeneousMedium>,
+    #[serde(default)]
+    pub sampling: SamplingSettings,
+    // only changes the order in which pixels are rendered
+    #[serde(default)]
+    pub tiles: TileSettings,
+}
+
+// ray going on from just past a hit, used to cross medium boundaries
+fn continue_past(ray: &Ray, intersection: &Intersection) -> Ray {
+    let origin = intersection.location + ray.unit_vec * 0.001;
+    Ray::new_from_origine_and_direction(&origin, &ray.unit_vec).with_time(ray.time)
 }
 
 impl Scene {
-    pub fn render_scene(& self, dimx: u32, dimy: u32) -> Vec<u16> {
-        let num_pix: u32             = dimx * dimy; 
-        let fov = std::f64::consts::PI / 4.0;
-
-        let t = &self.camera.cam_orient;
-        let w = &self.camera.up_vec;
-        let b = w.cross(&t);
-        let v = t.cross(&b);
-        
-        let g_x: f64 = (fov / 2.0).tan();
-        let g_y: f64 = g_x * (dimy as f64 / dimx as f64);
+    pub fn render_scene(& self, dimx: u32, dimy: u32, seed: u64) -> Film {
+        let mut film = Film::new(dimx, dimy);
+        self.render_progressive(&mut film, seed, &AtomicBool::new(false), |_, _| true);
+        film
+    }
 
-        
-        let P_1_1 = &self.camera.cam_pos + t - g_x * &b + g_y * &v;
-        
-        let q_x = ((2.0 * g_x) / (dimx as f64 - 1.0)) * &b;
-        let q_y = ((2.0 * g_y) / (dimy as f64 - 1.0)) * &v;
-
-        let pixels: Vec<u16> = (0..num_pix)
-            .into_par_iter() // create parralel iterator with rayon
-            .progress_count(num_pix as u64) 
-            .flat_map(
-                |i| {
-                    let pi_x: u32 = i % dimx;
-                    let pi_y: u32 = i / dimx;
+    // renders passes into the film until every pixel is done, the time limit is reached, on_pass, called with
+    // the film and the pass number after each pass, returns false, or cancel is set, which stops the current pass
+    // at once; the film may already hold samples, rendering then goes on from them
+    pub fn render_progressive<F: FnMut(&Film, u32) -> bool>(&self, film: &mut Film, seed: u64, cancel: &AtomicBool, mut on_pass: F) {
+        let start = Instant::now();
+        // in seconds with a time limit, in samples per pixel otherwise
+        let progress = match (self.sampling.time_limit, self.sampling.max_samples_per_pixel()) {
+            (Some(limit), _) => ProgressBar::new(limit.ceil() as u64),
+            (None, Some(max_samples_per_pixel)) => ProgressBar::new(max_samples_per_pixel as u64),
+            (None, None) => ProgressBar::new_spinner(),
+        };
+        let mut pass = 0;
+        while film.pixels.iter().any(|pixel| !self.sampling.is_pixel_done(pixel)) {
+            self.render_pass(film, seed, cancel);
+            if cancel.load(Ordering::Relaxed) {
+                break;
+            }
+            pass += 1;
+            let elapsed_seconds = start.elapsed().as_secs_f64();
+            match self.sampling.time_limit {
+                Some(_) => progress.set_position(elapsed_seconds as u64),
+                None => progress.set_position(film.pixels.iter().map(|pixel| pixel.sample_count).max().unwrap_or(0) as u64),
+            }
+            let out_of_time = self.sampling.time_limit.is_some_and(|limit| elapsed_seconds >= limit);
+            if !on_pass(film, pass) || out_of_time {
+                break;
+            }
+        }
+        progress.finish();
+    }
+
+    // adds one sample to every pixel that is not done, tile by tile; once cancel is set the remaining pixels are skipped
+    pub fn render_pass(&self, film: &mut Film, seed: u64, cancel: &AtomicBool) {
+        // the camera covers the whole frame, of which the film may only be a region
+        let (dimx, _) = film.frame_size;
+        // without a sample limit, stratified samplers spread each batch of samples_per_pixel over their strata
+        let max_samples_per_pixel = self.sampling.max_samples_per_pixel().unwrap_or(self.sampling.samples_per_pixel.max(1));
+
+        let film_pixels = &film.pixels;
+        let samples: Vec<(usize, PixelStats)> = self.tiles.tiles(film.width, film.height)
+            .into_iter()
+            .par_bridge() // threads take the tiles in order
+            .flat_map_iter(|tile| {
+                (tile.y..tile.y + tile.height).flat_map(move |y| (tile.x..tile.x + tile.width).map(move |x| (x, y)))
+            })
+            .filter_map(
+                |(x, y)| {
+                    let index = (x + y * film.width) as usize;
+                    if cancel.load(Ordering::Relaxed) || self.sampling.is_pixel_done(&film_pixels[index]) {
+                        return None;
+                    }
+                    let mut stats = film_pixels[index].clone();
+                    let pi_x: u32 = film.origin.0 + x;
+                    let pi_y: u32 = film.origin.1 + y;
                     
-                    let pos_pix = &P_1_1 + &q_x * pi_x as f64 - &q_y * pi_y as f64;
-                    let ray: Ray = Ray::new_from_points(&self.camera.cam_pos, &pos_pix);
-                        
-                    let shaded_color = self.trace_ray(ray, 2);
-                    // <[u16;3]>::from(shaded_color)
-                    [
-                        (shaded_color.x * 65535.0) as u16, 
-                        (shaded_color.y * 65535.0) as u16, 
-                        (shaded_color.z * 65535.0) as u16
-                    ]
+                    // random values only depend on the seed, the pixel of the frame and the sample index,
+                    // not on the thread, the pass or the region being rendered
+                    let mut sampler = self.sampling.sampler.create(seed, (pi_x, pi_y), pi_x as u64 + pi_y as u64 * dimx as u64, max_samples_per_pixel);
+                    sampler.start_sample(stats.sample_count as u64);
+                    // position inside the pixel, centered on the pixel grid point
+                    let (jitter_x, jitter_y) = sampler.next_2d();
+                    let time = self.camera.sample_time(sampler.as_mut());
+                    let color = match self.camera.frame_ray(pi_x as f64 + jitter_x - 0.5, pi_y as f64 + jitter_y - 0.5, film.frame_size) {
+                        Some((ray, weight)) => self.trace_ray(ray.with_time(time), 2, sampler.as_mut()).component_mul(&weight),
+                        // the projection does not reach this part of the frame
+                        None => Vector3::zeros(),
+                    };
+                    stats.add_sample(&color);
+                    Some((index, stats))
                 }
-            ).collect();
-        pixels
+            )
+            .collect();
+
+        for (index, stats) in samples {
+            film.pixels[index] = stats;
+        }
     }
 
 
-    fn trace_ray(&self, ray: Ray, depth: u8) -> Vector3<f64> {
-        let result = self.shapes
+    // closest hit on a single shape, going through the parts cut out by its opacity mask
+    fn shape_intersection<'a>(&self, shape: &'a dyn Shape3D, ray: &Ray, sampler: &mut dyn Sampler) -> Option<Intersection<'a>> {
+        let mut intersection = shape.ray_closest_intersections(ray)?;
+        intersection.time = ray.time;
+        if shape.get_material().opacity.is_none() {
+            return Some(intersection);
+        }
+        let mut traveled = 0.0;
+        // a convex shape can only be crossed a few times, the bound guards against numerical ping-pong
+        for _ in 0..MAX_CUTOUT_CROSSINGS {
+            // the opacity mask may be textured
+            intersection.complete_surface_frame();
+            if !shape.get_material().is_cut_out(&intersection, sampler) {
+                intersection.distance += traveled;
+                return Some(intersection);
+            }
+            // restart just past the discarded hit
+            traveled += intersection.distance + 0.001;
+            let continued_origin = ray.origin + ray.unit_vec * traveled;
+            let continued_ray = Ray::new_from_origine_and_direction(&continued_origin, &ray.unit_vec).with_time(ray.time);
+            intersection = shape.ray_closest_intersections(&continued_ray)?;
+            intersection.time = ray.time;
+        }
+        None
+    }
+
+    fn closest_intersection(&self, ray: &Ray, sampler: &mut dyn Sampler) -> Option<Intersection<'_>> {
+        self.shapes
         .iter()
-        .flat_map(|shape| shape.ray_closest_intersections(&ray))
+        .flat_map(|shape| self.shape_intersection(shape.as_ref(), ray, sampler))
         .min_by(
             |intersection_1, intersection_2| {
                 intersection_1.distance
                     .partial_cmp(&intersection_2.distance)
                     .unwrap()
             }
-        );
+        )
+    }
+
+    // medium around the point: the first medium-filled shape containing it, the scene fog otherwise
+    fn medium_at(&self, point: &Vector3<f64>, time: f64) -> Option<&dyn Medium> {
+        self.shapes.iter()
+            .filter(|shape| shape.contains_at(point, time))
+            .find_map(|shape| shape.get_interior_medium())
+            .or_else(|| self.fog.as_ref().map(|fog| fog as &dyn Medium))
+    }
 
-        if let Some(intersection) = &result {
-            let distant_light_shading: Vector3<f64> = self.distant_light_shading(intersection);
-            let global_illumination = self.path_tracing(&intersection, depth);
-            let reflection_shading: Vector3<f64> = self.reflection_shading(&intersection, &ray, depth);
+    // fraction of light reaching the ray origin from infinitely far along the ray:
+    // zero behind an opaque surface, attenuated by the media crossed otherwise
+    fn shadow_transmittance(&self, ray: &Ray, sampler: &mut dyn Sampler) -> Vector3<f64> {
+        let mut transmittance = Vector3::<f64>::from_element(1.0);
+        let mut ray = ray.clone();
 
-            return intersection.shape.get_emissive_color() + distant_light_shading + global_illumination + reflection_shading
+        for _ in 0..MAX_MEDIUM_BOUNDARIES {
+            let result = self.closest_intersection(&ray, sampler);
+            if let Some(medium) = self.medium_at(&ray.origin, ray.time) {
+                let distance = result.as_ref().map_or(f64::INFINITY, |intersection| intersection.distance);
+                transmittance.component_mul_assign(&medium.transmittance(&ray, distance, sampler));
+            }
+            match result {
+                None => return transmittance,
+                Some(intersection) if intersection.shape.get_interior_medium().is_some() => {
+                    ray = continue_past(&ray, &intersection);
+                }
+                Some(_) => return Vector3::<f64>::from_element(0_f64),
+            }
+        }
+        Vector3::<f64>::from_element(0_f64)
+    }
+
+    fn trace_ray(&self, ray: Ray, depth: u8, sampler: &mut dyn Sampler) -> Vector3<f64> {
+        let result = self.closest_intersection(&ray, sampler);
+
+        let mut throughput = Vector3::<f64>::from_element(1.0);
+        if let Some(medium) = self.medium_at(&ray.origin, ray.time) {
+            let max_distance = result.as_ref().map_or(f64::INFINITY, |intersection| intersection.distance);
+            match medium.sample_distance(&ray, max_distance, sampler) {
+                MediumSample::Scattered { distance, weight } => {
+                    return weight.component_mul(&self.medium_scattering(medium, &ray, distance, depth, sampler))
+                }
+                MediumSample::Passed { weight } => throughput = weight,
+            }
+            // the ray was absorbed before reaching anything
+            if throughput.max() <= 0.0 {
+                return throughput
+            }
+        }
+
+        if let Some(mut intersection) = result {
+            // surfaces delimiting a medium are not rendered, the ray goes on into or out of the medium
+            if intersection.shape.get_interior_medium().is_some() {
+                return throughput.component_mul(&self.trace_ray(continue_past(&ray, &intersection), depth, sampler))
+            }
+
+            intersection.complete_surface_frame();
+            intersection.normal = intersection.shape.get_material().shading_normal(&intersection);
+            let diffuse_shading = match &intersection.shape.get_material().subsurface {
+                Some(subsurface) => self.subsurface_shading(&intersection, &ray, subsurface, depth, sampler),
+                None => self.distant_light_shading(&intersection, sampler) + self.path_tracing(&intersection, depth, sampler),
+            };
+            let reflection_shading: Vector3<f64> = self.reflection_shading(&intersection, &ray, depth, sampler);
+
+            let surface_shading = intersection.shape.get_emissive_color(&intersection) + diffuse_shading + reflection_shading;
+            return throughput.component_mul(&surface_shading)
         }
         
         Vector3::<f64>::from_element(0_f64)
     }
 
-    fn reflection_shading(&self, intersection: &Intersection, ray: &Ray, depth: u8) -> Vector3<f64> {
-        let reflectivity = intersection.shape.get_reflectivity();
+    // light scattered back along the ray at a point inside a medium:
+    // single scattering of the distant lights plus one phase function sample for the rest
+    fn medium_scattering(&self, medium: &dyn Medium, ray: &Ray, distance: f64, depth: u8, sampler: &mut dyn Sampler) -> Vector3<f64> {
+        let location = ray.origin + ray.unit_vec * distance;
+        let g = medium.phase_asymmetry();
+
+        let direct_lighting = self.distant_lights.iter()
+            .map(|light| {
+                let light_direction = light.direction.normalize();
+                let reverse_lightray = Ray::new_from_origine_and_direction(&location, &-light_direction).with_time(ray.time);
+                let phase = henyey_greenstein(light_direction.dot(&-ray.unit_vec), g);
+                self.shadow_transmittance(&reverse_lightray, sampler) * phase * light.intensity
+            }).sum::<Vector3<f64>>();
+
+        if depth == 0 {
+            return direct_lighting
+        }
+        let scattered_direction = sample_henyey_greenstein(&ray.unit_vec, g, sampler);
+        let scattered_ray = Ray::new_from_origine_and_direction(&location, &scattered_direction).with_time(ray.time);
+        direct_lighting + self.trace_ray(scattered_ray, depth - 1, sampler)
+    }
+
+    // random walks inside the shape, from where the ray enters it to where the light leaves it;
+    // only the shape itself bounds the walk, other shapes inside it are ignored
+    fn subsurface_shading(&self, intersection: &Intersection, ray: &Ray, subsurface: &Subsurface, depth: u8, sampler: &mut dyn Sampler) -> Vector3<f64> {
+        let medium = subsurface.medium();
+        let shape = intersection.shape;
+        let inward = if ray.unit_vec.dot(&intersection.geometric_normal) < 0.0 {
+            -intersection.geometric_normal
+        } else {
+            intersection.geometric_normal
+        };
+        let entry_rotation = Rotation3::from_basis_unchecked(&create_coordinate_system_from_up_vector(&inward));
+
+        let walks_sum = (0..SUBSURFACE_WALKS)
+            .map(|_| {
+                let mut location = intersection.location + inward * 0.001;
+                // diffuse transmission through the surface, weighted by 2 * cos like path_tracing
+                let mut direction = entry_rotation * uniform_sampling_hemisphere(sampler);
+                let mut throughput = Vector3::<f64>::from_element(2.0 * direction.dot(&inward));
+
+                for _ in 0..MAX_SUBSURFACE_BOUNCES {
+                    let walk_ray = Ray::new_from_origine_and_direction(&location, &direction).with_time(ray.time);
+                    let exit = match shape.ray_closest_intersections(&walk_ray) {
+                        Some(exit) => exit,
+                        None => break,
+                    };
+                    match medium.sample_distance(&walk_ray, exit.distance, sampler) {
+                        MediumSample::Scattered { distance, weight } => {
+                            throughput.component_mul_assign(&weight);
+                            location += direction * distance;
+                            direction = sample_henyey_greenstein(&direction, subsurface.g, sampler);
+                        }
+                        MediumSample::Passed { weight } => {
+                            let outward = if direction.dot(&exit.geometric_normal) > 0.0 {
+                                exit.geometric_normal
+                            } else {
+                                -exit.geometric_normal
+                            };
+                            let exitance = self.diffuse_irradiance(&(exit.location + outward * 0.001), &outward, ray.time, depth, sampler);
+                            return throughput.component_mul(&weight).component_mul(&exitance)
+                        }
+                    }
+                }
+                Vector3::<f64>::from_element(0_f64)
+            }).sum::<Vector3<f64>>();
+
+        walks_sum / SUBSURFACE_WALKS as f64
+    }
+
+    // light arriving at a point of a surface divided by pi, the radiance of a white lambertian surface:
+    // distant lights plus one uniformly sampled indirect ray
+    fn diffuse_irradiance(&self, location: &Vector3<f64>, normal: &Vector3<f64>, time: f64, depth: u8, sampler: &mut dyn Sampler) -> Vector3<f64> {
+        let direct_lighting = self.distant_lights.iter()
+            .map(|light| {
+                let light_direction_inverse = -light.direction.normalize();
+                let cos_angle = light_direction_inverse.dot(normal);
+                if cos_angle <= 0.0 {
+                    return Vector3::<f64>::from_element(0_f64)
+                }
+                let reverse_lightray = Ray::new_from_origine_and_direction(location, &light_direction_inverse).with_time(time);
+                self.shadow_transmittance(&reverse_lightray, sampler) * (light.intensity * cos_angle / std::f64::consts::PI)
+            }).sum::<Vector3<f64>>();
+
+        if depth == 0 {
+            return direct_lighting
+        }
+        let rotation = Rotation3::from_basis_unchecked(&create_coordinate_system_from_up_vector(normal));
+        let rand_direction = rotation * uniform_sampling_hemisphere(sampler);
+        let indirect_ray = Ray::new_from_origine_and_direction(location, &rand_direction).with_time(time);
+        direct_lighting + self.trace_ray(indirect_ray, depth - 1, sampler) * (2.0 * rand_direction.dot(normal))
+    }
+
+    fn reflection_shading(&self, intersection: &Intersection, ray: &Ray, depth: u8, sampler: &mut dyn Sampler) -> Vector3<f64> {
+        let reflectivity = intersection.shape.get_reflectivity(intersection);
 
         if reflectivity > 0.0 && depth > 0 {
             let reflection_vector = ray.unit_vec - 2.0 * intersection.normal.dot(&ray.unit_vec) * intersection.normal;
             let reflection_origine = &intersection.biased_location;
-            let reflection_ray = Ray::new_from_origine_and_direction(&reflection_origine, &reflection_vector);
+            let reflection_ray = Ray::new_from_origine_and_direction(&reflection_origine, &reflection_vector).with_time(ray.time);
 
-            reflectivity * self.trace_ray(reflection_ray, depth - 1)
+            reflectivity * self.trace_ray(reflection_ray, depth - 1, sampler)
         } else {
             Vector3::<f64>::from_element(0_f64)
         }
     }
 
-    fn distant_light_shading(&self, intersection: &Intersection) -> Vector3<f64> {
+    fn distant_light_shading(&self, intersection: &Intersection, sampler: &mut dyn Sampler) -> Vector3<f64> {
 
-        let albedo =  intersection.shape.get_albedo();
+        let albedo =  intersection.shape.get_albedo(intersection);
         
-        let diffuse_reflection: f64 = self.distant_lights.iter()
+        let diffuse_reflection: Vector3<f64> = self.distant_lights.iter()
             .map( |light| {
                 let origine = &intersection.biased_location;
 
                 let light_direction_inverse = -light.direction;
-                let reverse_lightray = Ray::new_from_origine_and_direction(&origine, &light_direction_inverse);
-
-                let is_obstructed = self.shapes.iter()
-                    .any(
-                        |shape| shape.ray_closest_intersections(&reverse_lightray).is_some()
-                    );
-                if is_obstructed {
-                    return 0.0
+                let reverse_lightray = Ray::new_from_origine_and_direction(&origine, &light_direction_inverse).with_time(intersection.time);
+
+                let transmittance = self.shadow_transmittance(&reverse_lightray, sampler);
+                if transmittance.max() <= 0.0 {
+                    return Vector3::<f64>::from_element(0_f64)
                 }
 
                 let angle = light_direction_inverse.angle(&intersection.normal);
                 if angle < (std::f64::consts::PI / 2.0) {
-                    albedo / std::f64::consts::PI * light.intensity * angle.cos()
+                    transmittance * (albedo / std::f64::consts::PI * light.intensity * angle.cos())
                 } else {
-                    0.0
+                    Vector3::<f64>::from_element(0_f64)
                 }
-            }).sum::<f64>();
-        let shading_coefficient: f64 = diffuse_reflection;
-        if shading_coefficient > 0.0 {
-            let color = intersection.shape.get_color(); 
-            let shaded_color = color * shading_coefficient;
+            }).sum::<Vector3<f64>>();
+        if diffuse_reflection.max() > 0.0 {
+            let color = intersection.shape.get_color(intersection); 
+            let shaded_color = color.component_mul(&diffuse_reflection);
             return shaded_color;
         }
         Vector3::<f64>::from_element(0_f64)
     }
 
-    fn path_tracing(&self, intersection: &Intersection, depth: u8) -> Vector3<f64> {
+    fn path_tracing(&self, intersection: &Intersection, depth: u8, sampler: &mut dyn Sampler) -> Vector3<f64> {
         if depth == 0 {
             return Vector3::<f64>::from_element(0_f64)
         }
@@ -148,17 +470,17 @@ impl Scene {
             .into_iter()
             .map(
                 |_| {
-                    let rand_direction = rotation * uniform_sampling_hemisphere();
-                    let ray: Ray = Ray::new_from_origine_and_direction(&intersection.biased_location, &rand_direction);
+                    let rand_direction = rotation * uniform_sampling_hemisphere(sampler);
+                    let ray: Ray = Ray::new_from_origine_and_direction(&intersection.biased_location, &rand_direction).with_time(intersection.time);
                     let ray_angle = rand_direction.angle(&intersection.normal);
-                    let indirect_light_color = self.trace_ray(ray, depth - 1);
+                    let indirect_light_color = self.trace_ray(ray, depth - 1, sampler);
                     indirect_light_color * ray_angle.cos()
                 }
             ).sum::<Vector3<f64>>();
             // probability for a ray is 1 / 2 * pi -> divided by 1 / 2 pi ->  2 * pi
         
-        let global_lighting = global_lighting_sum / nbr_of_samples as f64 * 2.0 * intersection.shape.get_albedo();
-        let global_illumination_shading = global_lighting.component_mul(&intersection.shape.get_color());
+        let global_lighting = global_lighting_sum / nbr_of_samples as f64 * 2.0 * intersection.shape.get_albedo(intersection);
+        let global_illumination_shading = global_lighting.component_mul(&intersection.shape.get_color(intersection));
 
         global_illumination_shading
     }
@@ -166,4 +488,353 @@ impl Scene {
     pub fn push_shape(&mut self, shape: Box<dyn Shape3D + Sync>) {
         self.shapes.push(shape);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raytracer::samplers::independent::IndependentSampler;
+    use crate::raytracer::material::ScalarParameter;
+    use crate::raytracer::tiles::TileOrder;
+
+    // perspective camera at the origin looking along z
+    fn create_camera(shutter: [f64; 2]) -> Box<dyn Camera> {
+        let mut placement = CameraPlacement::new(Vector3::zeros(), Some(Vector3::new(0.0, 0.0, 1.0)), None, Vector3::new(0.0, 1.0, 0.0), 0.0).unwrap();
+        placement.shutter = shutter;
+        Box::new(Perspective { placement, fov: 45.0 })
+    }
+
+    fn create_scene(shapes: Vec<Box<dyn Shape3D + Sync>>) -> Scene {
+        Scene {
+            camera: create_camera([0.0, 0.0]),
+            shapes,
+            distant_lights: vec![],
+            fog: None,
+            sampling: SamplingSettings::default(),
+            tiles: TileSettings::default(),
+        }
+    }
+
+    // a wall at z = 2 in front of a sphere centered at z = 5
+    fn create_masked_wall_scene(opacity: f64) -> Scene {
+        let mut wall = Plane::new(
+            Vector3::new(0.0, 0.0, 2.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            Vector3::new(1.0, 1.0, 1.0),
+            Vector3::zeros(),
+            0.5,
+            0.0,
+        );
+        wall.material.opacity = Some(ScalarParameter::Constant(opacity));
+        let sphere = Sphere::new(Vector3::new(0.0, 0.0, 5.0), 1.0, Vector3::new(1.0, 0.0, 0.0), Vector3::zeros(), 0.5, 0.0);
+        create_scene(vec![Box::new(wall), Box::new(sphere)])
+    }
+
+    #[test]
+    fn test_closest_intersection_skips_cut_out_surface() {
+        let scene = create_masked_wall_scene(0.0);
+        let ray = Ray::new_from_origine_and_direction(&Vector3::zeros(), &Vector3::new(0.0, 0.0, 1.0));
+
+        let hit = scene.closest_intersection(&ray, &mut IndependentSampler::new(0, 0)).unwrap();
+        assert!((hit.distance - 4.0).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_closest_intersection_keeps_opaque_surface() {
+        let scene = create_masked_wall_scene(1.0);
+        let ray = Ray::new_from_origine_and_direction(&Vector3::zeros(), &Vector3::new(0.0, 0.0, 1.0));
+
+        let hit = scene.closest_intersection(&ray, &mut IndependentSampler::new(0, 0)).unwrap();
+        assert!((hit.distance - 2.0).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_cut_out_surface_does_not_cast_shadows() {
+        let scene = create_masked_wall_scene(0.0);
+        let ray = Ray::new_from_origine_and_direction(&Vector3::new(3.0, 0.0, 0.0), &Vector3::new(0.0, 0.0, 1.0));
+
+        assert_eq!(scene.shadow_transmittance(&ray, &mut IndependentSampler::new(0, 0)), Vector3::from_element(1.0));
+        assert_eq!(create_masked_wall_scene(1.0).shadow_transmittance(&ray, &mut IndependentSampler::new(0, 0)), Vector3::zeros());
+    }
+
+    #[test]
+    fn test_cut_out_hit_distance_is_measured_from_ray_origin() {
+        // opacity grows with z: the front of the sphere (z = 4) is cut out, its back (z = 6) is not
+        let mut sphere = Sphere::new(Vector3::new(0.0, 0.0, 5.0), 1.0, Vector3::zeros(), Vector3::zeros(), 0.5, 0.0);
+        sphere.material.opacity = Some(serde_yaml::from_str(
+            "type: Gradient\nstart: [0.0, 0.0, 0.0]\nend: [1.0, 1.0, 1.0]\ndirection: [0.0, 0.0, 10.0]\nspace: World"
+        ).unwrap());
+        let scene = create_scene(vec![Box::new(sphere)]);
+        let ray = Ray::new_from_origine_and_direction(&Vector3::zeros(), &Vector3::new(0.0, 0.0, 1.0));
+
+        let hit = scene.closest_intersection(&ray, &mut IndependentSampler::new(0, 0)).unwrap();
+        assert!((hit.distance - 6.0).abs() < 1e-10);
+        assert!(!hit.front_face);
+    }
+
+    fn create_medium_box(sigma_a: f64) -> Cuboid {
+        let mut cuboid = Cuboid::new(
+            Vector3::new(0.0, 0.0, 5.0),
+            [Vector3::from_element(-1.0), Vector3::from_element(1.0)],
+            Vector3::zeros(),
+            Vector3::zeros(),
+            0.0,
+            0.0,
+        );
+        cuboid.material.interior_medium = Some(HomogeneousMedium::new(Vector3::from_element(sigma_a), Vector3::zeros(), 0.0));
+        cuboid
+    }
+
+    #[test]
+    fn test_medium_at_prefers_shape_medium_over_fog() {
+        let mut scene = create_scene(vec![Box::new(create_medium_box(2.0))]);
+        scene.fog = Some(HomogeneousMedium::new(Vector3::from_element(0.1), Vector3::zeros(), 0.0));
+        let ray = create_axis_ray();
+
+        let inside = scene.medium_at(&Vector3::new(0.0, 0.0, 5.0), 0.0).unwrap();
+        assert!((inside.transmittance(&ray, 1.0, &mut IndependentSampler::new(0, 0)).x - (-2.0_f64).exp()).abs() < 1e-10);
+        let outside = scene.medium_at(&Vector3::zeros(), 0.0).unwrap();
+        assert!((outside.transmittance(&ray, 1.0, &mut IndependentSampler::new(0, 0)).x - (-0.1_f64).exp()).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_shadow_transmittance_through_medium_box() {
+        let scene = create_scene(vec![Box::new(create_medium_box(0.5))]);
+
+        // the box is 2 units thick along z, minus the small steps taken across its faces
+        let transmittance = scene.shadow_transmittance(&create_axis_ray(), &mut IndependentSampler::new(0, 0));
+        assert!((transmittance - Vector3::from_element((-1.0_f64).exp())).norm() < 1e-3);
+    }
+
+    #[test]
+    fn test_medium_boundary_is_not_rendered() {
+        // an emissive sphere behind a box filled with a perfectly clear medium
+        let light = Sphere::new(Vector3::new(0.0, 0.0, 10.0), 1.0, Vector3::zeros(), Vector3::new(2.0, 3.0, 4.0), 0.0, 0.0);
+        let scene = create_scene(vec![Box::new(create_medium_box(0.0)), Box::new(light)]);
+
+        assert_eq!(scene.trace_ray(create_axis_ray(), 0, &mut IndependentSampler::new(0, 0)), Vector3::new(2.0, 3.0, 4.0));
+    }
+
+    #[test]
+    fn test_fog_scatters_distant_light() {
+        let mut scene = create_scene(vec![]);
+        scene.fog = Some(HomogeneousMedium::new(Vector3::zeros(), Vector3::from_element(0.5), 0.0));
+        scene.distant_lights.push(DistantLight { direction: Vector3::new(0.0, -1.0, 0.0), intensity: 1.0 });
+
+        // open fog attenuates the light completely on its infinite way down, so it is black
+        assert_eq!(scene.trace_ray(create_axis_ray(), 0, &mut IndependentSampler::new(0, 0)), Vector3::zeros());
+    }
+
+    fn create_axis_ray() -> Ray {
+        Ray::new_from_origine_and_direction(&Vector3::zeros(), &Vector3::new(0.0, 0.0, 1.0))
+    }
+
+    fn create_translucent_sphere(mean_free_path: f64) -> Sphere {
+        let mut sphere = Sphere::new(Vector3::new(0.0, 0.0, 5.0), 1.0, Vector3::from_element(1.0), Vector3::zeros(), 1.0, 0.0);
+        sphere.material.subsurface = Some(Subsurface::new(Vector3::from_element(mean_free_path), Vector3::from_element(1.0), 0.0));
+        sphere
+    }
+
+    #[test]
+    fn test_subsurface_without_light_is_black() {
+        let scene = create_scene(vec![Box::new(create_translucent_sphere(0.1))]);
+        assert_eq!(scene.trace_ray(create_axis_ray(), 0, &mut IndependentSampler::new(0, 0)), Vector3::zeros());
+    }
+
+    #[test]
+    fn test_subsurface_transmits_back_light() {
+        // the light comes from behind the sphere, its front is only lit through the material
+        let back_light = DistantLight { direction: Vector3::new(0.0, 0.0, -1.0), intensity: 1.0 };
+        let mut opaque_scene = create_scene(vec![Box::new(Sphere::new(Vector3::new(0.0, 0.0, 5.0), 1.0, Vector3::from_element(1.0), Vector3::zeros(), 1.0, 0.0))]);
+        opaque_scene.distant_lights.push(back_light.clone());
+        assert_eq!(opaque_scene.trace_ray(create_axis_ray(), 0, &mut IndependentSampler::new(0, 0)), Vector3::zeros());
+
+        let mut translucent_scene = create_scene(vec![Box::new(create_translucent_sphere(100.0))]);
+        translucent_scene.distant_lights.push(back_light);
+        let color = translucent_scene.trace_ray(create_axis_ray(), 0, &mut IndependentSampler::new(0, 0));
+        assert!(color.min() > 0.0);
+        // without absorption a walk carries at most its entry weight 2 * cos times the 1 / pi of a white surface
+        assert!(color.max() < 2.0 / std::f64::consts::PI);
+    }
+
+    #[test]
+    fn test_moving_shape_is_blurred_over_the_shutter() {
+        // an emissive sphere crossing the middle of the image from left to right
+        let sphere = Sphere::new(Vector3::new(-0.5, 0.0, 5.0), 0.3, Vector3::zeros(), Vector3::from_element(1.0), 0.0, 0.0);
+        let moving = moving::Moving { shape: Box::new(sphere), velocity: Vector3::new(1.0, 0.0, 0.0) };
+        let mut scene = create_scene(vec![Box::new(moving)]);
+        scene.sampling.samples_per_pixel = 16;
+        let row = |film: &Film| (0..8).map(|x| film.pixels[x + 8 * 3].mean().x).collect::<Vec<f64>>();
+
+        let still = row(&scene.render_scene(8, 7, 0));
+        scene.camera = create_camera([0.0, 1.0]);
+        let blurred = row(&scene.render_scene(8, 7, 0));
+        // the sphere is on the left at time 0 and leaves a trail over the pixels it crosses while the shutter is open
+        let lit_pixels = |row: &[f64]| row.iter().filter(|value| **value > 0.0).count();
+        assert_eq!(still[5], 0.0);
+        assert!(blurred[5] > 0.0);
+        assert!(lit_pixels(&blurred) > lit_pixels(&still));
+    }
+
+    #[test]
+    fn test_render_only_depends_on_seed() {
+        // a scattering box in front of the camera, lit from above, gives noisy pixels
+        let mut cloud = create_medium_box(0.1);
+        cloud.material.interior_medium = Some(HomogeneousMedium::new(Vector3::from_element(0.1), Vector3::from_element(1.0), 0.3));
+        let mut scene = create_scene(vec![Box::new(cloud)]);
+        scene.distant_lights.push(DistantLight { direction: Vector3::new(0.0, -1.0, 0.0), intensity: 1.0 });
+
+        let first = scene.render_scene(6, 4, 7).pixels;
+        assert_eq!(first, scene.render_scene(6, 4, 7).pixels);
+        assert_ne!(first, scene.render_scene(6, 4, 8).pixels);
+    }
+
+    #[test]
+    fn test_sampling_settings_from_yaml() {
+        let camera = "camera:\n  cam_orient: [0.0, 0.0, 1.0]\n  cam_pos: [0.0, 0.0, 0.0]\n  up_vec: [0.0, 1.0, 0.0]\nshapes: []\ndistant_lights: []\n";
+        let scene: Scene = serde_yaml::from_str(camera).unwrap();
+        assert_eq!(scene.sampling.sampler, SamplerKind::Independent);
+        assert_eq!(scene.sampling.samples_per_pixel, 1);
+
+        let scene: Scene = serde_yaml::from_str(&format!("{}sampling:\n  sampler: Sobol\n  samples_per_pixel: 16\n", camera)).unwrap();
+        assert_eq!(scene.sampling.sampler, SamplerKind::Sobol);
+        assert_eq!(scene.sampling.samples_per_pixel, 16);
+    }
+
+    #[test]
+    fn test_adaptive_sampling_spends_samples_on_noise() {
+        // the left column of the image sees a scattering box, the right one sees only black
+        let mut cloud = create_medium_box(0.1);
+        cloud.position = Vector3::new(-1.2, 0.0, 5.0);
+        cloud.material.interior_medium = Some(HomogeneousMedium::new(Vector3::from_element(0.1), Vector3::from_element(1.0), 0.3));
+        let mut scene = create_scene(vec![Box::new(cloud)]);
+        scene.distant_lights.push(DistantLight { direction: Vector3::new(0.0, -1.0, 0.0), intensity: 1.0 });
+        scene.sampling.samples_per_pixel = 4;
+        scene.sampling.adaptive = Some(AdaptiveSettings { error_threshold: 0.01, max_samples_per_pixel: 64 });
+
+        let film = scene.render_scene(4, 2, 0);
+        let counts: Vec<u32> = film.pixels.iter().map(|pixel| pixel.sample_count).collect();
+        // converged black pixels stop after the first batch, noisy ones go on up to the maximum
+        assert_eq!(counts[3], 4);
+        assert_eq!(counts[0], 64);
+        assert_eq!(counts[4], 64);
+    }
+
+    #[test]
+    fn test_progressive_passes_add_one_sample() {
+        let mut cloud = create_medium_box(0.1);
+        cloud.material.interior_medium = Some(HomogeneousMedium::new(Vector3::from_element(0.1), Vector3::from_element(1.0), 0.3));
+        let mut scene = create_scene(vec![Box::new(cloud)]);
+        scene.distant_lights.push(DistantLight { direction: Vector3::new(0.0, -1.0, 0.0), intensity: 1.0 });
+        scene.sampling.samples_per_pixel = 3;
+
+        let mut film = Film::new(3, 2);
+        let mut passes = Vec::new();
+        scene.render_progressive(&mut film, 5, &AtomicBool::new(false), |film, pass| {
+            passes.push((pass, film.pixels[0].sample_count));
+            true
+        });
+        assert_eq!(passes, vec![(1, 1), (2, 2), (3, 3)]);
+        // same samples as rendering the frame at once
+        assert_eq!(film.pixels, scene.render_scene(3, 2, 5).pixels);
+    }
+
+    #[test]
+    fn test_progressive_rendering_goes_on_from_film() {
+        let mut cloud = create_medium_box(0.1);
+        cloud.material.interior_medium = Some(HomogeneousMedium::new(Vector3::from_element(0.1), Vector3::from_element(1.0), 0.3));
+        let mut scene = create_scene(vec![Box::new(cloud)]);
+        scene.distant_lights.push(DistantLight { direction: Vector3::new(0.0, -1.0, 0.0), intensity: 1.0 });
+        scene.sampling.samples_per_pixel = 2;
+
+        let mut film = Film::new(3, 2);
+        scene.render_pass(&mut film, 5, &AtomicBool::new(false));
+        scene.render_progressive(&mut film, 5, &AtomicBool::new(false), |_, _| true);
+        assert_eq!(film.pixels, scene.render_scene(3, 2, 5).pixels);
+    }
+
+    #[test]
+    fn test_region_of_a_huge_frame_keeps_the_pixel_samplers_apart() {
+        // thin slab of fog lit from behind, seen through an orthographic camera: every pixel sees the same thing,
+        // so their colors only differ by their random samples
+        let mut slab = Cuboid::new(
+            Vector3::new(0.0, 0.0, 5.0),
+            [Vector3::new(-1e6, -1e6, -0.5), Vector3::new(1e6, 1e6, 0.5)],
+            Vector3::zeros(),
+            Vector3::zeros(),
+            0.0,
+            0.0,
+        );
+        slab.material.interior_medium = Some(HomogeneousMedium::new(Vector3::zeros(), Vector3::from_element(2.0), 0.0));
+        let mut scene = create_scene(vec![Box::new(slab)]);
+        scene.distant_lights.push(DistantLight { direction: Vector3::new(0.0, 0.0, -1.0), intensity: 1.0 });
+        let placement = CameraPlacement::new(Vector3::zeros(), Some(Vector3::new(0.0, 0.0, 1.0)), None, Vector3::new(0.0, 1.0, 0.0), 0.0).unwrap();
+        scene.camera = Box::new(Orthographic { placement, width: 1000.0 });
+
+        // the indices of these pixels of a 100000x100000 frame are the same modulo 2^32
+        let render_pixel = |x: u32, y: u32| {
+            let mut film = Film::region(100_000, 100_000, x, y, 1, 1).unwrap();
+            scene.render_progressive(&mut film, 1, &AtomicBool::new(false), |_, _| true);
+            film.pixels[0].mean()
+        };
+        assert_ne!(render_pixel(32_704, 0), render_pixel(0, 42_950));
+        assert_eq!(render_pixel(0, 42_950), render_pixel(0, 42_950));
+    }
+
+    #[test]
+    fn test_region_matches_full_render() {
+        let mut cloud = create_medium_box(0.1);
+        cloud.material.interior_medium = Some(HomogeneousMedium::new(Vector3::from_element(0.1), Vector3::from_element(1.0), 0.3));
+        let mut scene = create_scene(vec![Box::new(cloud)]);
+        scene.distant_lights.push(DistantLight { direction: Vector3::new(0.0, -1.0, 0.0), intensity: 1.0 });
+        scene.tiles = TileSettings { size: 2, order: TileOrder::Hilbert };
+
+        let full = scene.render_scene(6, 4, 1);
+        let mut region = Film::region(6, 4, 2, 1, 3, 2).unwrap();
+        scene.render_progressive(&mut region, 1, &AtomicBool::new(false), |_, _| true);
+        for (y, x) in [(0, 0), (0, 2), (1, 1)] {
+            assert_eq!(region.pixels[x + y * 3], full.pixels[(x + 2) + (y + 1) * 6]);
+        }
+    }
+
+    #[test]
+    fn test_progressive_rendering_stops_when_asked() {
+        let mut scene = create_scene(vec![]);
+        scene.sampling.samples_per_pixel = 8;
+
+        let mut film = Film::new(2, 2);
+        scene.render_progressive(&mut film, 0, &AtomicBool::new(false), |_, pass| pass < 3);
+        assert!(film.pixels.iter().all(|pixel| pixel.sample_count == 3));
+    }
+
+    #[test]
+    fn test_unlimited_sampling_stops_at_the_time_limit() {
+        let mut scene = create_scene(vec![]);
+        scene.sampling.samples_per_pixel = 4;
+        scene.sampling.unlimited = true;
+        assert_eq!(scene.sampling.max_samples_per_pixel(), None);
+        let mut pixel = PixelStats::default();
+        (0..1000).for_each(|_| pixel.add_sample(&Vector3::zeros()));
+        assert!(!scene.sampling.is_pixel_done(&pixel));
+
+        // a limit that is out after the first pass
+        scene.sampling.time_limit = Some(0.0);
+        let mut film = Film::new(2, 2);
+        scene.render_progressive(&mut film, 0, &AtomicBool::new(false), |_, _| true);
+        assert!(film.pixels.iter().all(|pixel| pixel.sample_count == 1));
+        assert_eq!(scene.sampling.progress(&film, 1.0), 1.0);
+    }
+
+    #[test]
+    fn test_cancelled_pass_takes_no_samples() {
+        let scene = create_scene(vec![]);
+        let mut film = Film::new(2, 2);
+        let mut passes = 0;
+        scene.render_progressive(&mut film, 0, &AtomicBool::new(true), |_, _| {
+            passes += 1;
+            true
+        });
+        assert_eq!(passes, 0);
+        assert!(film.pixels.iter().all(|pixel| pixel.sample_count == 0));
+    }
+}
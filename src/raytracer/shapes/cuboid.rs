@@ -1,19 +1,18 @@
 use serde::{Serialize, Deserialize};
-use na::Vector3;
+use na::{Vector2, Vector3};
 
 use super::shape::*; 
 use crate::raytracer::ray::*;
 use crate::raytracer::Intersection;
+use crate::raytracer::material::Material;
 
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Cuboid {
     pub position: Vector3<f64>,
     pub bounds: [Vector3<f64>; 2], 
-    pub color: Vector3<f64>,
-    pub emissive_color: Vector3<f64>,
-    pub albedo: f64,
-    pub reflectivity: f64
+    #[serde(flatten)]
+    pub material: Material
 }
 
 impl Cuboid {
@@ -25,7 +24,7 @@ impl Cuboid {
         albedo: f64,
         reflectivity: f64
     ) -> Cuboid {
-        Cuboid {position, bounds, color, emissive_color, albedo, reflectivity}
+        Cuboid {position, bounds, material: Material::new(color, emissive_color, albedo, reflectivity)}
     }
 }
 
@@ -62,39 +61,68 @@ impl Shape3D for Cuboid {
         let txyzmax: f64 = txymax.min(tzmax);
 
  
-        let distance = txyzmin;
         if txyzmin > txyzmax {
             return None
         }
-        if distance < 0.0 {
+        if txyzmax < 0.0 {
             return None
         }
+        // from inside the cuboid the closest hit is where the ray exits
+        let distance = if txyzmin >= 0.0 { txyzmin } else { txyzmax };
 
         let location = &ray.origin + &ray.unit_vec * distance;
         let normalized_location = &location - &self.position;
 
-        // the normal is on the axis with the highest absolute value
+        // the hit face is the one whose bound is closest to the location
+        let (axis, max_side) = (0..3)
+            .map(|i| {
+                let to_min = (normalized_location[i] - self.bounds[0][i]).abs();
+                let to_max = (normalized_location[i] - self.bounds[1][i]).abs();
+                (i, to_max < to_min, to_min.min(to_max))
+            })
+            .min_by(|a, b| a.2.partial_cmp(&b.2).unwrap())
+            .map(|(i, max_side, _)| (i, max_side))
+            .unwrap();
         let mut normal: Vector3<f64> = Vector3::<f64>::zeros();
-        let normal_abs: f64 = 1.0;
-        normal[normalized_location.iamax()] = normal_abs.copysign(normalized_location[normalized_location.iamax()]);
+        normal[axis] = if max_side { 1.0 } else { -1.0 };
 
-        return Some(Intersection::new(location, distance, normal, self))
+        let mut intersection = Intersection::new(location, distance, normal, self);
+        intersection.front_face = ray.unit_vec.dot(&normal) < 0.0;
+        return Some(intersection)
     }
 
-    fn get_color (&self) -> Vector3<f64> {
-        return self.color;
+    // per-face mapping: each face covers the whole [0, 1] uv square
+    fn surface_frame (&self, intersection: &Intersection) -> (Vector2<f64>, Vector3<f64>, Vector3<f64>) {
+        let normalized_location = intersection.location - self.position;
+        let axis = (0..3).find(|i| intersection.normal[*i] != 0.0).unwrap_or(2);
+        let (u_axis, v_axis) = match axis {
+            0 => (2, 1),
+            1 => (0, 2),
+            _ => (0, 1),
+        };
+        let extent = self.bounds[1] - self.bounds[0];
+        let uv = Vector2::new(
+            (normalized_location[u_axis] - self.bounds[0][u_axis]) / extent[u_axis],
+            (normalized_location[v_axis] - self.bounds[0][v_axis]) / extent[v_axis],
+        );
+        let mut dpdu = Vector3::<f64>::zeros();
+        dpdu[u_axis] = extent[u_axis];
+        let mut dpdv = Vector3::<f64>::zeros();
+        dpdv[v_axis] = extent[v_axis];
+        (uv, dpdu, dpdv)
     }
 
-    fn get_emissive_color (&self) -> Vector3<f64> {
-        return self.emissive_color;
+    fn to_object_space (&self, point: &Vector3<f64>) -> Vector3<f64> {
+        point - self.position
     }
 
-    fn get_albedo (&self) -> f64 {
-        return self.albedo;
+    fn contains (&self, point: &Vector3<f64>) -> bool {
+        let local = point - self.position;
+        (0..3).all(|i| local[i] > self.bounds[0][i] && local[i] < self.bounds[1][i])
     }
 
-    fn get_reflectivity(&self) -> f64 {
-        return self.reflectivity;
+    fn get_material (&self) -> &Material {
+        &self.material
     }
 }
 
@@ -229,20 +257,23 @@ mod tests {
     #[test]
     fn test_cuboid_get_color() {
         let cuboid = create_test_cuboid();
-        let color = cuboid.get_color();
+        let hit = Intersection::new(Vector3::zeros(), 1.0, Vector3::y(), &cuboid);
+        let color = cuboid.get_color(&hit);
         assert_eq!(color, Vector3::new(1.0, 0.0, 1.0));
     }
 
     #[test]
     fn test_cuboid_get_albedo() {
         let cuboid = create_test_cuboid();
-        assert!((cuboid.get_albedo() - 0.5).abs() < EPSILON);
+        let hit = Intersection::new(Vector3::zeros(), 1.0, Vector3::y(), &cuboid);
+        assert!((cuboid.get_albedo(&hit) - 0.5).abs() < EPSILON);
     }
 
     #[test]
     fn test_cuboid_get_reflectivity() {
         let cuboid = create_test_cuboid();
-        assert!(cuboid.get_reflectivity().abs() < EPSILON);
+        let hit = Intersection::new(Vector3::zeros(), 1.0, Vector3::y(), &cuboid);
+        assert!(cuboid.get_reflectivity(&hit).abs() < EPSILON);
     }
 
     #[test]
@@ -267,4 +298,67 @@ mod tests {
         let hit = cuboid.ray_closest_intersections(&ray).unwrap();
         assert!((hit.location.z - (-3.0)).abs() < EPSILON);
     }
+
+    #[test]
+    fn test_cuboid_asymmetric_bounds_normal() {
+        let cuboid = Cuboid::new(
+            Vector3::new(0.0, 0.0, 0.0),
+            [
+                Vector3::new(-1.0, -1.0, -3.0),
+                Vector3::new(1.0, 1.0, 3.0),
+            ],
+            Vector3::new(1.0, 1.0, 1.0),
+            Vector3::new(0.0, 0.0, 0.0),
+            0.5,
+            0.0,
+        );
+
+        // hits the +x face close to the far end of the long z extent
+        let ray = Ray::new_from_origine_and_direction(
+            &Vector3::new(5.0, 0.0, 2.5),
+            &Vector3::new(-1.0, 0.0, 0.0),
+        );
+
+        let hit = cuboid.ray_closest_intersections(&ray).unwrap();
+        assert!((hit.normal.x - 1.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_cuboid_ray_from_inside_hits_exit_face() {
+        let cuboid = create_test_cuboid();
+        let ray = Ray::new_from_origine_and_direction(
+            &Vector3::new(0.0, 0.0, 5.0),  // cuboid center
+            &Vector3::new(0.0, 0.0, 1.0),
+        );
+
+        let hit = cuboid.ray_closest_intersections(&ray).unwrap();
+        assert!((hit.distance - 1.0).abs() < EPSILON);
+        assert!((hit.normal.z - 1.0).abs() < EPSILON);
+        assert!(!hit.front_face);
+    }
+
+    #[test]
+    fn test_cuboid_contains() {
+        let cuboid = create_test_cuboid();
+        assert!(cuboid.contains(&Vector3::new(0.5, -0.5, 5.5)));
+        assert!(!cuboid.contains(&Vector3::new(0.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn test_cuboid_face_uv() {
+        let cuboid = create_test_cuboid();
+        let ray = Ray::new_from_origine_and_direction(
+            &Vector3::new(0.5, -0.5, 0.0),
+            &Vector3::new(0.0, 0.0, 1.0),
+        );
+
+        let mut hit = cuboid.ray_closest_intersections(&ray).unwrap();
+        hit.complete_surface_frame();
+        // front face (-z) maps x to u and y to v over the [-1, 1] bounds
+        assert!((hit.uv.x - 0.75).abs() < EPSILON);
+        assert!((hit.uv.y - 0.25).abs() < EPSILON);
+        assert_eq!(hit.dpdu, Vector3::new(2.0, 0.0, 0.0));
+        assert_eq!(hit.dpdv, Vector3::new(0.0, 2.0, 0.0));
+        assert!(hit.front_face);
+    }
 }
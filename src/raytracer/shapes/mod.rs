@@ -2,8 +2,10 @@ pub mod shape;
 pub mod sphere;
 pub mod plane;
 pub mod cuboid;
+pub mod volume;
+pub mod moving;
 
 pub use shape::*;
 pub use sphere::*;
 pub use plane::*;
-pub use cuboid::*;
\ No newline at end of file
+pub use cuboid::*;
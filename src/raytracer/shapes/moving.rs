@@ -0,0 +1,130 @@
+use serde::{Serialize, Deserialize};
+use na::{Vector2, Vector3};
+
+use super::shape::*;
+use crate::raytracer::ray::*;
+use crate::raytracer::Intersection;
+use crate::raytracer::material::Material;
+use crate::raytracer::medium::Medium;
+
+// another shape moving in a straight line, it is at its scene file position at time 0 and moves by velocity
+// per unit of time; rays hit it where it is at their time, which the camera spreads over its shutter interval.
+// the density of a volume stays in place, only its bounds move
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Moving {
+    pub shape: Box<dyn Shape3D + Sync>,
+    pub velocity: Vector3<f64>,
+}
+
+impl Moving {
+    fn offset(&self, time: f64) -> Vector3<f64> {
+        self.velocity * time
+    }
+}
+
+#[typetag::serde]
+impl Shape3D for Moving {
+    // the ray is moved back by the offset instead of moving the shape forward
+    fn ray_closest_intersections (&self, ray: &Ray) -> Option<Intersection<'_>> {
+        let offset = self.offset(ray.time);
+        let mut shape_ray = ray.clone();
+        shape_ray.origin -= offset;
+
+        let hit = self.shape.ray_closest_intersections(&shape_ray)?;
+        Some(Intersection {
+            location: hit.location + offset,
+            biased_location: hit.biased_location + offset,
+            time: ray.time,
+            shape: self,
+            ..hit
+        })
+    }
+
+    // frame of the shape where it was at the time of the hit
+    fn surface_frame (&self, intersection: &Intersection) -> (Vector2<f64>, Vector3<f64>, Vector3<f64>) {
+        let offset = self.offset(intersection.time);
+        let shape_hit = Intersection {
+            location: intersection.location - offset,
+            biased_location: intersection.biased_location - offset,
+            shape: self.shape.as_ref(),
+            ..intersection.clone()
+        };
+        self.shape.surface_frame(&shape_hit)
+    }
+
+    fn to_object_space (&self, point: &Vector3<f64>) -> Vector3<f64> {
+        self.to_object_space_at(point, 0.0)
+    }
+
+    fn contains (&self, point: &Vector3<f64>) -> bool {
+        self.contains_at(point, 0.0)
+    }
+
+    fn to_object_space_at (&self, point: &Vector3<f64>, time: f64) -> Vector3<f64> {
+        self.shape.to_object_space(&(point - self.offset(time)))
+    }
+
+    fn contains_at (&self, point: &Vector3<f64>, time: f64) -> bool {
+        self.shape.contains(&(point - self.offset(time)))
+    }
+
+    fn get_material (&self) -> &Material {
+        self.shape.get_material()
+    }
+
+    fn get_interior_medium (&self) -> Option<&dyn Medium> {
+        self.shape.get_interior_medium()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raytracer::shapes::Sphere;
+
+    const EPSILON: f64 = 1e-10;
+
+    // unit sphere at z = 5 moving along x
+    fn create_moving_sphere() -> Moving {
+        let sphere = Sphere::new(Vector3::new(0.0, 0.0, 5.0), 1.0, Vector3::from_element(1.0), Vector3::zeros(), 0.5, 0.0);
+        Moving { shape: Box::new(sphere), velocity: Vector3::new(4.0, 0.0, 0.0) }
+    }
+
+    #[test]
+    fn test_rays_hit_the_shape_at_their_time() {
+        let moving = create_moving_sphere();
+        let ray = Ray::new_from_origine_and_direction(&Vector3::new(2.0, 0.0, 0.0), &Vector3::new(0.0, 0.0, 1.0));
+        assert!(moving.ray_closest_intersections(&ray).is_none());
+
+        let hit = moving.ray_closest_intersections(&ray.with_time(0.5)).unwrap();
+        assert!((hit.location - Vector3::new(2.0, 0.0, 4.0)).norm() < EPSILON);
+        assert!((hit.normal - Vector3::new(0.0, 0.0, -1.0)).norm() < EPSILON);
+        assert_eq!(hit.time, 0.5);
+    }
+
+    #[test]
+    fn test_object_space_follows_the_shape() {
+        let moving = create_moving_sphere();
+        assert!(moving.contains_at(&Vector3::new(4.0, 0.0, 5.0), 1.0));
+        assert!(!moving.contains(&Vector3::new(4.0, 0.0, 5.0)));
+        assert_eq!(moving.to_object_space_at(&Vector3::new(4.0, 0.0, 5.0), 1.0), Vector3::zeros());
+    }
+
+    #[test]
+    fn test_moving_shape_from_yaml() {
+        let yaml = "
+type: Moving
+velocity: [1.0, 0.0, 0.0]
+shape:
+  type: Sphere
+  centre: [0.0, 0.0, 5.0]
+  r: 1.0
+  color: [1.0, 1.0, 1.0]
+  emissive_color: [0.0, 0.0, 0.0]
+  albedo: 0.5
+  reflectivity: 0.0
+";
+        let shape: Box<dyn Shape3D + Sync> = serde_yaml::from_str(yaml).unwrap();
+        assert!(shape.contains_at(&Vector3::new(1.0, 0.0, 5.0), 1.0));
+    }
+}
@@ -1,18 +1,18 @@
 use serde::{Serialize, Deserialize};
-use na::Vector3;
+use na::{Vector2, Vector3};
 
 use super::shape::*; 
 use crate::raytracer::ray::*;
 use crate::raytracer::Intersection;
+use crate::raytracer::material::Material;
+use crate::raytracer::utils::create_coordinate_system_from_up_vector;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Plane {
     pub origin: Vector3<f64>,
     pub normal_vec: Vector3<f64>,
-    pub color: Vector3<f64>,
-    pub emissive_color: Vector3<f64>,
-    albedo: f64,
-    pub reflectivity: f64
+    #[serde(flatten)]
+    pub material: Material
 }
 
 impl Plane {
@@ -24,7 +24,7 @@ impl Plane {
         albedo: f64, 
         reflectivity : f64
     ) -> Plane {
-        Plane{ origin, normal_vec, color, emissive_color, albedo, reflectivity}
+        Plane{ origin, normal_vec, material: Material::new(color, emissive_color, albedo, reflectivity)}
     }
 }
 
@@ -46,25 +46,31 @@ impl Shape3D for Plane {
             // println!("{:?}", &ray.unit_vec);
             // println!("{:?}", ray.unit_vec.angle(&self.normal_vec));
             // println!("{:?}", normal);
-            return Some(Intersection::new(location, distance, normal, self))
+
+            let mut intersection = Intersection::new(location, distance, normal, self);
+            intersection.front_face = l_dot_n < 0.0;
+            return Some(intersection)
         }
         None
     }
 
-    fn get_color (&self) -> Vector3<f64> {
-        return self.color;
+    // planar mapping: one uv unit per world unit, textures repeat according to their wrap mode
+    fn surface_frame (&self, intersection: &Intersection) -> (Vector2<f64>, Vector3<f64>, Vector3<f64>) {
+        let [dpdu, _, dpdv] = create_coordinate_system_from_up_vector(&self.normal_vec.normalize());
+        let local = intersection.location - self.origin;
+        (Vector2::new(local.dot(&dpdu), local.dot(&dpdv)), dpdu, dpdv)
     }
 
-    fn get_emissive_color (&self) -> Vector3<f64> {
-        return self.emissive_color;
+    fn to_object_space (&self, point: &Vector3<f64>) -> Vector3<f64> {
+        point - self.origin
     }
 
-    fn get_albedo (&self) -> f64 {
-        return self.albedo;
+    fn contains (&self, _point: &Vector3<f64>) -> bool {
+        false
     }
 
-    fn get_reflectivity(&self) -> f64 {
-        return self.reflectivity;
+    fn get_material (&self) -> &Material {
+        &self.material
     }
 }
 
@@ -206,13 +212,48 @@ mod tests {
     #[test]
     fn test_plane_get_color() {
         let plane = create_floor_plane();
-        let color = plane.get_color();
+        let hit = Intersection::new(Vector3::zeros(), 1.0, Vector3::y(), &plane);
+        let color = plane.get_color(&hit);
         assert_eq!(color, Vector3::new(0.5, 0.5, 0.5));
     }
 
     #[test]
     fn test_plane_get_albedo() {
         let plane = create_floor_plane();
-        assert!((plane.get_albedo() - 0.8).abs() < EPSILON);
+        let hit = Intersection::new(Vector3::zeros(), 1.0, Vector3::y(), &plane);
+        assert!((plane.get_albedo(&hit) - 0.8).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_plane_uv_tiles_with_distance_from_origin() {
+        let plane = create_floor_plane();
+        let ray = Ray::new_from_origine_and_direction(
+            &Vector3::new(3.0, 5.0, -2.0),
+            &Vector3::new(0.0, -1.0, 0.0),
+        );
+
+        let mut hit = plane.ray_closest_intersections(&ray).unwrap();
+        hit.complete_surface_frame();
+        // the uv is the hit location expressed in the (dpdu, dpdv) basis
+        let reconstructed = hit.dpdu * hit.uv.x + hit.dpdv * hit.uv.y;
+        assert!((reconstructed - Vector3::new(3.0, 0.0, -2.0)).norm() < EPSILON);
+        assert!(hit.dpdu.dot(&hit.normal).abs() < EPSILON);
+        assert!(hit.dpdv.dot(&hit.normal).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_plane_front_face() {
+        let plane = create_floor_plane();
+        let from_above = Ray::new_from_origine_and_direction(
+            &Vector3::new(0.0, 5.0, 0.0),
+            &Vector3::new(0.0, -1.0, 0.0),
+        );
+        let from_below = Ray::new_from_origine_and_direction(
+            &Vector3::new(0.0, -5.0, 0.0),
+            &Vector3::new(0.0, 1.0, 0.0),
+        );
+
+        assert!(plane.ray_closest_intersections(&from_above).unwrap().front_face);
+        assert!(!plane.ray_closest_intersections(&from_below).unwrap().front_face);
     }
 }
\ No newline at end of file
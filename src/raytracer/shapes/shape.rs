@@ -3,20 +3,61 @@ extern crate num_complex;
 
 use crate::raytracer::ray::*;
 use crate::raytracer::Intersection;
+use crate::raytracer::material::Material;
+use crate::raytracer::medium::Medium;
 
-use na::Vector3;
+use crate::raytracer::utils::create_coordinate_system_from_up_vector;
+
+use na::{Vector2, Vector3};
 
 #[typetag::serde(tag = "type")]
 pub trait Shape3D : Sync + std::fmt::Debug
 {
-    // returns (intersection point, distance from camera to intersection, normal vector)
+    // returns (intersection point, distance from camera to intersection, normal vector), the surface frame is left empty
     fn ray_closest_intersections (&self, ray: &Ray) -> Option<Intersection>;
 
-    fn get_color (&self) -> Vector3<f64>;
+    // uv and tangents (dpdu, dpdv) at a hit, only computed for the hits that get shaded;
+    // an arbitrary tangent frame for shapes without a parametrization
+    fn surface_frame (&self, intersection: &Intersection) -> (Vector2<f64>, Vector3<f64>, Vector3<f64>) {
+        let [dpdu, _, dpdv] = create_coordinate_system_from_up_vector(&intersection.normal);
+        (Vector2::zeros(), dpdu, dpdv)
+    }
+
+    // expresses a scene position relative to the shape, used by textures evaluated in object space
+    fn to_object_space (&self, point: &Vector3<f64>) -> Vector3<f64>;
+
+    // whether the point is inside the volume enclosed by the shape, always false for open shapes
+    fn contains (&self, point: &Vector3<f64>) -> bool;
+
+    fn get_material (&self) -> &Material;
+
+    // the same at a given time, for shapes that move while the shutter is open
+    fn to_object_space_at (&self, point: &Vector3<f64>, _time: f64) -> Vector3<f64> {
+        self.to_object_space(point)
+    }
+
+    fn contains_at (&self, point: &Vector3<f64>, _time: f64) -> bool {
+        self.contains(point)
+    }
+
+    fn get_interior_medium (&self) -> Option<&dyn Medium> {
+        self.get_material().interior_medium.as_ref().map(|medium| medium as &dyn Medium)
+    }
+
+    // material parameters may be textured, so they are evaluated at a given intersection
+    fn get_color (&self, intersection: &Intersection) -> Vector3<f64> {
+        self.get_material().color.evaluate(intersection)
+    }
 
-    fn get_emissive_color(&self) -> Vector3<f64>;
+    fn get_emissive_color(&self, intersection: &Intersection) -> Vector3<f64> {
+        self.get_material().emissive_color.evaluate(intersection)
+    }
 
-    fn get_albedo(&self) -> f64;
+    fn get_albedo(&self, intersection: &Intersection) -> f64 {
+        self.get_material().albedo.evaluate(intersection)
+    }
 
-    fn get_reflectivity(&self) -> f64;
+    fn get_reflectivity(&self, intersection: &Intersection) -> f64 {
+        self.get_material().reflectivity.evaluate(intersection)
+    }
 }
\ No newline at end of file
@@ -3,18 +3,18 @@ use serde::{Serialize, Deserialize};
 use super::shape::*; 
 use crate::raytracer::ray::*;
 use crate::raytracer::Intersection;
+use crate::raytracer::material::Material;
+use crate::raytracer::utils::create_coordinate_system_from_up_vector;
 
-use na::Vector3;
+use na::{Vector2, Vector3};
 
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Sphere {
     pub centre: Vector3<f64>,
     pub r: f64,
-    pub color: Vector3<f64>,
-    pub emissive_color: Vector3<f64>,
-    pub albedo: f64,
-    pub reflectivity: f64
+    #[serde(flatten)]
+    pub material: Material
 }
 
 impl Sphere {
@@ -26,7 +26,15 @@ impl Sphere {
         albedo: f64,
         reflectivity: f64
     ) -> Sphere {
-        Sphere {centre, r, color, emissive_color, albedo, reflectivity}
+        Sphere {centre, r, material: Material::new(color, emissive_color, albedo, reflectivity)}
+    }
+
+    fn intersection_at<'a>(&'a self, ray: &Ray, distance: f64) -> Intersection<'a> {
+        let location: Vector3<f64> = ray.origin + ray.unit_vec * distance;
+        let normal: Vector3<f64> = (location - self.centre).normalize();
+        let mut intersection = Intersection::new(location, distance, normal, self);
+        intersection.front_face = ray.unit_vec.dot(&normal) < 0.0;
+        intersection
     }
 }
 
@@ -50,32 +58,45 @@ impl Shape3D for Sphere {
             let dist2: f64 = - ray.unit_vec.dot(&or_sub_centr) - discriminant.sqrt();
 
             if dist1 >= 0.0 && (dist2 < 0.0 || dist2 > dist1) {
-                let location: Vector3<f64> = &ray.origin + &ray.unit_vec * dist1;
-                let normal: Vector3<f64> = (&location - &self.centre).normalize(); 
-                return Some(Intersection::new(location, dist1, normal, self))
+                return Some(self.intersection_at(ray, dist1))
             } else if dist2 >= 0.0 {
-                let location: Vector3<f64> = &ray.origin + &ray.unit_vec * dist2;
-                let normal: Vector3<f64> = (&location - &self.centre).normalize();
-                return Some(Intersection::new(location, dist2, normal, self))
+                return Some(self.intersection_at(ray, dist2))
             }
         }
         return None;
     }
 
-    fn get_color (&self) -> Vector3<f64> {
-        return self.color;
+    // spherical mapping: u follows the longitude around the y axis, v goes from the top pole (0) to the bottom one (1)
+    fn surface_frame (&self, intersection: &Intersection) -> (Vector2<f64>, Vector3<f64>, Vector3<f64>) {
+        let normal = (intersection.location - self.centre).normalize();
+        let mut phi = normal.z.atan2(normal.x);
+        if phi < 0.0 {
+            phi += 2.0 * std::f64::consts::PI;
+        }
+        let theta = normal.y.clamp(-1.0, 1.0).acos();
+        let uv = Vector2::new(phi / (2.0 * std::f64::consts::PI), theta / std::f64::consts::PI);
+
+        let dpdu = Vector3::new(-normal.z, 0.0, normal.x) * self.r * 2.0 * std::f64::consts::PI;
+        let dpdv = Vector3::new(normal.y * phi.cos(), -theta.sin(), normal.y * phi.sin()) * self.r * std::f64::consts::PI;
+        // at the poles dpdu vanishes, an arbitrary tangent frame is used there
+        if dpdu.norm() > 1e-9 {
+            (uv, dpdu, dpdv)
+        } else {
+            let [dpdu, _, dpdv] = create_coordinate_system_from_up_vector(&normal);
+            (uv, dpdu, dpdv)
+        }
     }
 
-    fn get_emissive_color (&self) -> Vector3<f64> {
-        return self.emissive_color;
+    fn to_object_space (&self, point: &Vector3<f64>) -> Vector3<f64> {
+        point - self.centre
     }
 
-    fn get_albedo (&self) -> f64 {
-        return self.albedo;
+    fn contains (&self, point: &Vector3<f64>) -> bool {
+        (point - self.centre).norm() < self.r
     }
 
-    fn get_reflectivity(&self) -> f64 {
-        return self.reflectivity;
+    fn get_material (&self) -> &Material {
+        &self.material
     }
 }
 
@@ -188,26 +209,96 @@ mod tests {
     #[test]
     fn test_sphere_get_color() {
         let sphere = create_test_sphere();
-        let color = sphere.get_color();
+        let hit = Intersection::new(Vector3::zeros(), 1.0, Vector3::y(), &sphere);
+        let color = sphere.get_color(&hit);
         assert_eq!(color, Vector3::new(1.0, 0.0, 0.0));
     }
 
     #[test]
     fn test_sphere_get_albedo() {
         let sphere = create_test_sphere();
-        assert!((sphere.get_albedo() - 0.5).abs() < EPSILON);
+        let hit = Intersection::new(Vector3::zeros(), 1.0, Vector3::y(), &sphere);
+        assert!((sphere.get_albedo(&hit) - 0.5).abs() < EPSILON);
     }
 
     #[test]
     fn test_sphere_get_reflectivity() {
         let sphere = create_test_sphere();
-        assert!(sphere.get_reflectivity().abs() < EPSILON);
+        let hit = Intersection::new(Vector3::zeros(), 1.0, Vector3::y(), &sphere);
+        assert!(sphere.get_reflectivity(&hit).abs() < EPSILON);
     }
 
     #[test]
     fn test_sphere_get_emissive_color() {
         let sphere = create_test_sphere();
-        let emissive = sphere.get_emissive_color();
+        let hit = Intersection::new(Vector3::zeros(), 1.0, Vector3::y(), &sphere);
+        let emissive = sphere.get_emissive_color(&hit);
         assert_eq!(emissive, Vector3::new(0.0, 0.0, 0.0));
     }
+
+    #[test]
+    fn test_sphere_uv_in_unit_square() {
+        let sphere = create_test_sphere();
+        let ray = Ray::new_from_origine_and_direction(
+            &Vector3::new(0.3, 0.4, 0.0),
+            &Vector3::new(0.0, 0.0, 1.0),
+        );
+
+        let mut hit = sphere.ray_closest_intersections(&ray).unwrap();
+        hit.complete_surface_frame();
+        assert!(hit.uv.x >= 0.0 && hit.uv.x <= 1.0);
+        assert!(hit.uv.y >= 0.0 && hit.uv.y <= 1.0);
+    }
+
+    #[test]
+    fn test_sphere_uv_front_point() {
+        let sphere = create_test_sphere();
+        let ray = Ray::new_from_origine_and_direction(
+            &Vector3::new(0.0, 0.0, 0.0),
+            &Vector3::new(0.0, 0.0, 1.0),
+        );
+
+        let mut hit = sphere.ray_closest_intersections(&ray).unwrap();
+        hit.complete_surface_frame();
+        // hit at normal (0, 0, -1): three quarters around the equator
+        assert!((hit.uv.x - 0.75).abs() < EPSILON);
+        assert!((hit.uv.y - 0.5).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_sphere_tangents_orthogonal_to_normal() {
+        let sphere = create_test_sphere();
+        let ray = Ray::new_from_origine_and_direction(
+            &Vector3::new(0.2, 0.5, 0.0),
+            &Vector3::new(0.0, 0.0, 1.0),
+        );
+
+        let mut hit = sphere.ray_closest_intersections(&ray).unwrap();
+        hit.complete_surface_frame();
+        assert!(hit.dpdu.dot(&hit.normal).abs() < 1e-9);
+        assert!(hit.dpdv.dot(&hit.normal).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_sphere_contains() {
+        let sphere = create_test_sphere();
+        assert!(sphere.contains(&Vector3::new(0.0, 0.5, 5.0)));
+        assert!(!sphere.contains(&Vector3::new(0.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn test_sphere_front_face() {
+        let sphere = create_test_sphere();
+        let outside = Ray::new_from_origine_and_direction(
+            &Vector3::new(0.0, 0.0, 0.0),
+            &Vector3::new(0.0, 0.0, 1.0),
+        );
+        let inside = Ray::new_from_origine_and_direction(
+            &Vector3::new(0.0, 0.0, 5.0),
+            &Vector3::new(0.0, 0.0, 1.0),
+        );
+
+        assert!(sphere.ray_closest_intersections(&outside).unwrap().front_face);
+        assert!(!sphere.ray_closest_intersections(&inside).unwrap().front_face);
+    }
 }
@@ -0,0 +1,182 @@
+use serde::{Serialize, Deserialize};
+use std::convert::TryFrom;
+use std::path::Path;
+use na::{Vector2, Vector3};
+
+use super::shape::*;
+use super::cuboid::Cuboid;
+use crate::raytracer::ray::*;
+use crate::raytracer::Intersection;
+use crate::raytracer::material::Material;
+use crate::raytracer::medium::{Medium, GridMedium};
+use crate::raytracer::density_grid::DensityGrid;
+
+// density grid stretched over a box, the box surface is invisible and only delimits the medium
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(try_from = "VolumeConfig")]
+pub struct Volume {
+    pub position: Vector3<f64>,
+    pub bounds: [Vector3<f64>; 2],
+    // path of a .fgrid raw grid or a Mitsuba .vol grid
+    pub grid: String,
+    pub density_scale: f64,
+    // coefficients at unit density, multiplied by the grid density and density_scale
+    pub sigma_a: Vector3<f64>,
+    pub sigma_s: Vector3<f64>,
+    pub g: f64,
+    #[serde(skip)]
+    bounding_box: Cuboid,
+    #[serde(skip)]
+    medium: GridMedium,
+}
+
+#[derive(Deserialize)]
+struct VolumeConfig {
+    position: Vector3<f64>,
+    bounds: [Vector3<f64>; 2],
+    grid: String,
+    #[serde(default = "default_density_scale")]
+    density_scale: f64,
+    sigma_a: Vector3<f64>,
+    sigma_s: Vector3<f64>,
+    #[serde(default)]
+    g: f64,
+}
+
+fn default_density_scale() -> f64 {
+    1.0
+}
+
+impl TryFrom<VolumeConfig> for Volume {
+    type Error = String;
+
+    fn try_from(config: VolumeConfig) -> Result<Self, Self::Error> {
+        Volume::new(config.position, config.bounds, config.grid, config.density_scale, config.sigma_a, config.sigma_s, config.g)
+    }
+}
+
+impl Volume {
+    pub fn new(
+        position: Vector3<f64>,
+        bounds: [Vector3<f64>; 2],
+        grid: String,
+        density_scale: f64,
+        sigma_a: Vector3<f64>,
+        sigma_s: Vector3<f64>,
+        g: f64
+    ) -> Result<Volume, String> {
+        let density_grid = DensityGrid::load_shared(Path::new(&grid))?;
+        let medium = GridMedium::new(
+            density_grid,
+            position + bounds[0],
+            bounds[1] - bounds[0],
+            sigma_a * density_scale,
+            sigma_s * density_scale,
+            g,
+        );
+        let zero = Vector3::<f64>::zeros();
+        let bounding_box = Cuboid::new(position, bounds, zero, zero, 0.0, 0.0);
+        Ok(Volume {position, bounds, grid, density_scale, sigma_a, sigma_s, g, bounding_box, medium})
+    }
+}
+
+#[typetag::serde]
+impl Shape3D for Volume {
+    fn ray_closest_intersections (&self, ray: &Ray) -> Option<Intersection<'_>> {
+        self.bounding_box.ray_closest_intersections(ray)
+            .map(|hit| Intersection { shape: self, ..hit })
+    }
+
+    fn surface_frame (&self, intersection: &Intersection) -> (Vector2<f64>, Vector3<f64>, Vector3<f64>) {
+        self.bounding_box.surface_frame(intersection)
+    }
+
+    fn to_object_space (&self, point: &Vector3<f64>) -> Vector3<f64> {
+        point - self.position
+    }
+
+    fn contains (&self, point: &Vector3<f64>) -> bool {
+        self.bounding_box.contains(point)
+    }
+
+    fn get_material (&self) -> &Material {
+        &self.bounding_box.material
+    }
+
+    fn get_interior_medium (&self) -> Option<&dyn Medium> {
+        Some(&self.medium)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raytracer::samplers::independent::IndependentSampler;
+
+    fn write_test_grid(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("rust-tracer-{}-{}.fgrid", std::process::id(), name));
+        let mut bytes = b"FGRID 1 1 1\n".to_vec();
+        bytes.extend(1.0_f32.to_le_bytes());
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    fn create_test_volume(name: &str) -> (Volume, std::path::PathBuf) {
+        let path = write_test_grid(name);
+        let volume = Volume::new(
+            Vector3::new(0.0, 0.0, 5.0),
+            [Vector3::from_element(-1.0), Vector3::from_element(1.0)],
+            path.display().to_string(),
+            2.0,
+            Vector3::from_element(0.5),
+            Vector3::zeros(),
+            0.0,
+        ).unwrap();
+        (volume, path)
+    }
+
+    #[test]
+    fn test_volume_hit_belongs_to_volume() {
+        let (volume, path) = create_test_volume("hit");
+        let ray = Ray::new_from_origine_and_direction(&Vector3::zeros(), &Vector3::new(0.0, 0.0, 1.0));
+
+        let hit = volume.ray_closest_intersections(&ray).unwrap();
+        assert!((hit.distance - 4.0).abs() < 1e-10);
+        assert!(hit.shape.get_interior_medium().is_some());
+        assert!(volume.contains(&Vector3::new(0.0, 0.0, 5.0)));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_volume_density_scale() {
+        let mut sampler = IndependentSampler::new(0, 0);
+        let (volume, path) = create_test_volume("scale");
+        let ray = Ray::new_from_origine_and_direction(&Vector3::zeros(), &Vector3::new(0.0, 0.0, 1.0));
+
+        // unit density scaled twice over the 2 units of the box: exp(-0.5 * 2 * 2)
+        let samples = 20000;
+        let transmittance = (0..samples)
+            .map(|_| volume.medium.transmittance(&ray, 10.0, &mut sampler).x)
+            .sum::<f64>() / samples as f64;
+        assert!((transmittance - (-2.0_f64).exp()).abs() < 0.01);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_volume_from_yaml() {
+        let path = write_test_grid("yaml");
+        let yaml = format!(
+            "type: Volume\nposition: [0.0, 0.0, 5.0]\nbounds: [[-1.0, -1.0, -1.0], [1.0, 1.0, 1.0]]\ngrid: {}\nsigma_a: [0.5, 0.5, 0.5]\nsigma_s: [0.0, 0.0, 0.0]",
+            path.display()
+        );
+        let shape: Box<dyn Shape3D> = serde_yaml::from_str(&yaml).unwrap();
+        assert!(shape.get_interior_medium().is_some());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_volume_missing_grid_file() {
+        let yaml = "type: Volume\nposition: [0.0, 0.0, 0.0]\nbounds: [[-1.0, -1.0, -1.0], [1.0, 1.0, 1.0]]\ngrid: does-not-exist.fgrid\nsigma_a: [1.0, 1.0, 1.0]\nsigma_s: [1.0, 1.0, 1.0]";
+        assert!(serde_yaml::from_str::<Box<dyn Shape3D>>(yaml).is_err());
+    }
+}
@@ -0,0 +1,148 @@
+use serde::{Serialize, Deserialize};
+
+use super::texture::*;
+use crate::raytracer::Intersection;
+use crate::raytracer::material::ColorParameter;
+
+use na::Vector3;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Checkerboard {
+    pub even: ColorParameter,
+    pub odd: ColorParameter,
+    // solid checkerboards alternate along x, y and z of the mapped point, flat ones lie on the surface
+    // and alternate along its uv coordinates, scaled and offset by the mapping whatever its space;
+    // shapes without a useful parametrization should use a solid checkerboard
+    #[serde(default)]
+    pub solid: bool,
+    #[serde(flatten)]
+    pub mapping: TextureMapping,
+}
+
+impl Checkerboard {
+    fn point(&self, intersection: &Intersection) -> Vector3<f64> {
+        if self.solid {
+            self.mapping.point(intersection)
+        } else {
+            Vector3::new(intersection.uv.x, intersection.uv.y, 0.0) * self.mapping.scale + self.mapping.offset
+        }
+    }
+
+    // unit sized cells, the cell containing the origin is even
+    fn is_even(&self, point: &Vector3<f64>) -> bool {
+        let mut sum = point.x.floor() as i64 + point.y.floor() as i64;
+        if self.solid {
+            sum += point.z.floor() as i64;
+        }
+        sum.rem_euclid(2) == 0
+    }
+}
+
+#[typetag::serde]
+impl Texture for Checkerboard {
+    fn evaluate (&self, intersection: &Intersection) -> Vector3<f64> {
+        if self.is_even(&self.point(intersection)) {
+            self.even.evaluate(intersection)
+        } else {
+            self.odd.evaluate(intersection)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_checkerboard(solid: bool) -> Checkerboard {
+        Checkerboard {
+            even: ColorParameter::Constant(Vector3::new(1.0, 1.0, 1.0)),
+            odd: ColorParameter::Constant(Vector3::new(0.0, 0.0, 0.0)),
+            solid,
+            mapping: TextureMapping::default(),
+        }
+    }
+
+    #[test]
+    fn test_flat_checkerboard_alternates_in_x_and_y() {
+        let checkerboard = create_checkerboard(false);
+
+        assert!(checkerboard.is_even(&Vector3::new(0.5, 0.5, 0.5)));
+        assert!(!checkerboard.is_even(&Vector3::new(1.5, 0.5, 0.5)));
+        assert!(!checkerboard.is_even(&Vector3::new(-0.5, 0.5, 0.5)));
+        assert!(checkerboard.is_even(&Vector3::new(1.5, 1.5, 0.5)));
+        // z is ignored
+        assert!(checkerboard.is_even(&Vector3::new(0.5, 0.5, 1.5)));
+    }
+
+    #[test]
+    fn test_solid_checkerboard_alternates_in_z() {
+        let checkerboard = create_checkerboard(true);
+
+        assert!(checkerboard.is_even(&Vector3::new(0.5, 0.5, 0.5)));
+        assert!(!checkerboard.is_even(&Vector3::new(0.5, 0.5, 1.5)));
+    }
+
+    #[test]
+    fn test_checkerboard_on_plane_uvs() {
+        use crate::raytracer::ray::Ray;
+        use crate::raytracer::shapes::*;
+
+        let mut plane = Plane::new(Vector3::zeros(), Vector3::y(), Vector3::zeros(), Vector3::zeros(), 1.0, 0.0);
+        plane.material.color = serde_yaml::from_str(
+            "type: Checkerboard\neven: [1.0, 1.0, 1.0]\nodd: [0.0, 0.0, 0.0]\nspace: Uv\nscale: 2.0"
+        ).unwrap();
+
+        let colors: Vec<Vector3<f64>> = [0.1, 0.6]
+            .iter()
+            .map(|x| {
+                let ray = Ray::new_from_origine_and_direction(&Vector3::new(*x, 1.0, 0.1), &Vector3::new(0.0, -1.0, 0.0));
+                let mut hit = plane.ray_closest_intersections(&ray).unwrap();
+                hit.complete_surface_frame();
+                plane.get_color(&hit)
+            })
+            .collect();
+        // half a unit apart at scale 2 lands on neighbouring cells
+        assert!((colors[0] - colors[1]).norm() > 0.5);
+    }
+
+    #[test]
+    fn test_flat_checkerboard_on_a_floor() {
+        use crate::raytracer::ray::Ray;
+        use crate::raytracer::shapes::*;
+
+        // y-up floor with the default mapping
+        let mut plane = Plane::new(Vector3::zeros(), Vector3::y(), Vector3::zeros(), Vector3::zeros(), 1.0, 0.0);
+        plane.material.color = serde_yaml::from_str("type: Checkerboard\neven: [1.0, 1.0, 1.0]\nodd: [0.0, 0.0, 0.0]").unwrap();
+
+        let color = |x: f64, z: f64| {
+            let ray = Ray::new_from_origine_and_direction(&Vector3::new(x, 1.0, z), &Vector3::new(0.0, -1.0, 0.0));
+            let mut hit = plane.ray_closest_intersections(&ray).unwrap();
+            hit.complete_surface_frame();
+            plane.get_color(&hit)
+        };
+        assert!((color(0.5, 0.5) - color(1.5, 0.5)).norm() > 0.5);
+        assert!((color(0.5, 0.5) - color(0.5, 1.5)).norm() > 0.5);
+        assert!((color(0.5, 0.5) - color(1.5, 1.5)).norm() < 1e-10);
+    }
+
+    #[test]
+    fn test_flat_checkerboard_on_a_sphere_follows_its_uvs() {
+        use crate::raytracer::ray::Ray;
+        use crate::raytracer::shapes::*;
+
+        let mut sphere = Sphere::new(Vector3::new(0.0, 0.0, 5.0), 2.0, Vector3::zeros(), Vector3::zeros(), 1.0, 0.0);
+        sphere.material.color = serde_yaml::from_str(
+            "type: Checkerboard\neven: [1.0, 1.0, 1.0]\nodd: [0.0, 0.0, 0.0]\nscale: 8.0"
+        ).unwrap();
+
+        let color = |x: f64| {
+            let ray = Ray::new_from_origine_and_direction(&Vector3::new(x, 0.3, 0.0), &Vector3::new(0.0, 0.0, 1.0));
+            let mut hit = sphere.ray_closest_intersections(&ray).unwrap();
+            hit.complete_surface_frame();
+            sphere.get_color(&hit)
+        };
+        // eight cells around the equator: u * 8 is about 5.7, 6.3 and 6.5 at these hits
+        assert!((color(-0.4) - color(0.4)).norm() > 0.5);
+        assert!((color(0.4) - color(0.8)).norm() < 1e-10);
+    }
+}
@@ -0,0 +1,99 @@
+use serde::{Serialize, Deserialize};
+use num_complex::Complex;
+
+use super::texture::*;
+use crate::raytracer::Intersection;
+use crate::raytracer::material::ColorParameter;
+
+use na::Vector3;
+
+fn default_max_iterations() -> u32 {
+    64
+}
+
+// escape time fractal over the (x, y) plane of the mapped point
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Fractal {
+    pub inside: ColorParameter,
+    pub outside: ColorParameter,
+    // constant of the Julia set to draw, the Mandelbrot set is drawn when omitted
+    #[serde(default)]
+    pub julia: Option<[f64; 2]>,
+    #[serde(default = "default_max_iterations")]
+    pub max_iterations: u32,
+    #[serde(flatten)]
+    pub mapping: TextureMapping,
+}
+
+impl Fractal {
+    // None when the point is in the set, otherwise a smoothed escape time in [0, 1]
+    fn escape_time(&self, point: &Vector3<f64>) -> Option<f64> {
+        let position = Complex::new(point.x, point.y);
+        let (mut z, c) = match self.julia {
+            Some([re, im]) => (position, Complex::new(re, im)),
+            None => (Complex::new(0.0, 0.0), position),
+        };
+
+        for iteration in 0..self.max_iterations {
+            z = z * z + c;
+            let norm_sqr = z.norm_sqr();
+            if norm_sqr > 256.0 {
+                // continuous iteration count, removes the banding between integer escape times
+                let smoothed = iteration as f64 + 1.0 - (0.5 * norm_sqr.ln()).log2();
+                return Some((smoothed / self.max_iterations as f64).clamp(0.0, 1.0));
+            }
+        }
+        None
+    }
+}
+
+#[typetag::serde]
+impl Texture for Fractal {
+    fn evaluate (&self, intersection: &Intersection) -> Vector3<f64> {
+        match self.escape_time(&self.mapping.point(intersection)) {
+            Some(t) => self.outside.evaluate(intersection) * t,
+            None => self.inside.evaluate(intersection),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_fractal(julia: Option<[f64; 2]>) -> Fractal {
+        Fractal {
+            inside: ColorParameter::Constant(Vector3::zeros()),
+            outside: ColorParameter::Constant(Vector3::new(1.0, 1.0, 1.0)),
+            julia,
+            max_iterations: default_max_iterations(),
+            mapping: TextureMapping::default(),
+        }
+    }
+
+    #[test]
+    fn test_mandelbrot_membership() {
+        let mandelbrot = create_fractal(None);
+        assert!(mandelbrot.escape_time(&Vector3::new(0.0, 0.0, 0.0)).is_none());
+        assert!(mandelbrot.escape_time(&Vector3::new(-1.0, 0.0, 0.0)).is_none());
+        assert!(mandelbrot.escape_time(&Vector3::new(2.0, 2.0, 0.0)).is_some());
+    }
+
+    #[test]
+    fn test_julia_membership() {
+        // with c = 0 the Julia set is the unit disk
+        let julia = create_fractal(Some([0.0, 0.0]));
+        assert!(julia.escape_time(&Vector3::new(0.5, 0.5, 0.0)).is_none());
+        assert!(julia.escape_time(&Vector3::new(1.5, 0.0, 0.0)).is_some());
+    }
+
+    #[test]
+    fn test_escape_time_in_unit_range() {
+        let mandelbrot = create_fractal(None);
+        for i in 0..100 {
+            if let Some(t) = mandelbrot.escape_time(&Vector3::new(-2.0 + i as f64 * 0.04, 0.7, 0.0)) {
+                assert!((0.0..=1.0).contains(&t));
+            }
+        }
+    }
+}
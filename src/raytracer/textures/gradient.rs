@@ -0,0 +1,59 @@
+use serde::{Serialize, Deserialize};
+
+use super::texture::*;
+use crate::raytracer::Intersection;
+use crate::raytracer::material::ColorParameter;
+
+use na::Vector3;
+
+fn default_direction() -> Vector3<f64> {
+    Vector3::new(1.0, 0.0, 0.0)
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Gradient {
+    pub start: ColorParameter,
+    pub end: ColorParameter,
+    // the gradient goes from start at the origin to end at the tip of this vector
+    #[serde(default = "default_direction")]
+    pub direction: Vector3<f64>,
+    #[serde(flatten)]
+    pub mapping: TextureMapping,
+}
+
+impl Gradient {
+    fn blend_factor(&self, point: &Vector3<f64>) -> f64 {
+        (point.dot(&self.direction) / self.direction.norm_squared()).clamp(0.0, 1.0)
+    }
+}
+
+#[typetag::serde]
+impl Texture for Gradient {
+    fn evaluate (&self, intersection: &Intersection) -> Vector3<f64> {
+        let t = self.blend_factor(&self.mapping.point(intersection));
+        self.start.evaluate(intersection).lerp(&self.end.evaluate(intersection), t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f64 = 1e-10;
+
+    #[test]
+    fn test_gradient_blend_factor_along_direction() {
+        let gradient = Gradient {
+            start: ColorParameter::Constant(Vector3::zeros()),
+            end: ColorParameter::Constant(Vector3::new(1.0, 1.0, 1.0)),
+            direction: Vector3::new(0.0, 4.0, 0.0),
+            mapping: TextureMapping::default(),
+        };
+
+        assert!(gradient.blend_factor(&Vector3::new(5.0, 0.0, 0.0)).abs() < EPSILON);
+        assert!((gradient.blend_factor(&Vector3::new(0.0, 1.0, 0.0)) - 0.25).abs() < EPSILON);
+        // clamped outside of the [start, end] range
+        assert!((gradient.blend_factor(&Vector3::new(0.0, 9.0, 0.0)) - 1.0).abs() < EPSILON);
+        assert!(gradient.blend_factor(&Vector3::new(0.0, -1.0, 0.0)).abs() < EPSILON);
+    }
+}
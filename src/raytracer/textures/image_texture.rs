@@ -0,0 +1,296 @@
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::time::SystemTime;
+
+use image::DynamicImage;
+
+use super::texture::*;
+use crate::raytracer::Intersection;
+use crate::raytracer::utils::srgb_to_linear;
+
+use na::{Vector2, Vector3};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Default)]
+pub enum WrapMode {
+    #[default]
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl WrapMode {
+    // maps any integer texel coordinate into [0, size)
+    fn apply(&self, coordinate: i64, size: u32) -> usize {
+        let size = size as i64;
+        let wrapped = match self {
+            WrapMode::Repeat => coordinate.rem_euclid(size),
+            WrapMode::Clamp => coordinate.clamp(0, size - 1),
+            WrapMode::Mirror => {
+                let period = coordinate.rem_euclid(2 * size);
+                if period < size { period } else { 2 * size - 1 - period }
+            }
+        };
+        wrapped as usize
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Default)]
+pub enum TextureFilter {
+    Nearest,
+    #[default]
+    Bilinear,
+}
+
+// decoded texels in linear space, shared between every texture using the same file
+#[derive(Debug)]
+pub struct TextureImage {
+    pub width: u32,
+    pub height: u32,
+    texels: Vec<[f32; 3]>,
+}
+
+impl TextureImage {
+    fn texel(&self, x: usize, y: usize) -> Vector3<f64> {
+        let [r, g, b] = self.texels[y * self.width as usize + x];
+        Vector3::new(r as f64, g as f64, b as f64)
+    }
+}
+
+struct CachedImage {
+    modified: Option<SystemTime>,
+    image: Weak<TextureImage>,
+}
+
+static IMAGE_CACHE: OnceLock<Mutex<HashMap<(PathBuf, bool), CachedImage>>> = OnceLock::new();
+
+// loads an image file, reusing the already decoded texels if another texture references the same unchanged file
+pub fn load_texture_image(path: &Path, srgb: bool) -> Result<Arc<TextureImage>, String> {
+    let key = (path.canonicalize().unwrap_or_else(|_| path.to_path_buf()), srgb);
+    let modified = std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+
+    let mut cache = IMAGE_CACHE.get_or_init(Default::default).lock().unwrap();
+    if let Some(cached) = cache.get(&key) {
+        if cached.modified == modified {
+            if let Some(image) = cached.image.upgrade() {
+                return Ok(image);
+            }
+        }
+    }
+
+    let dynamic_image = image::open(path)
+        .map_err(|err| format!("could not load texture {}: {}", path.display(), err))?;
+    let decode = |value: f64| {
+        (if srgb { srgb_to_linear(value) } else { value }) as f32
+    };
+    // 8 bit images are read as is, widening them to 16 bit would not map 255 to 65535
+    let (width, height, texels) = match dynamic_image {
+        DynamicImage::ImageLuma16(_) | DynamicImage::ImageLumaA16(_) | DynamicImage::ImageRgb16(_) | DynamicImage::ImageRgba16(_) => {
+            let decoded = dynamic_image.to_rgb16();
+            let texels = decoded.pixels()
+                .map(|pixel| [0, 1, 2].map(|c| decode(pixel[c] as f64 / 65535.0)))
+                .collect();
+            (decoded.width(), decoded.height(), texels)
+        }
+        _ => {
+            let decoded = dynamic_image.to_rgb8();
+            let texels = decoded.pixels()
+                .map(|pixel| [0, 1, 2].map(|c| decode(pixel[c] as f64 / 255.0)))
+                .collect();
+            (decoded.width(), decoded.height(), texels)
+        }
+    };
+    let image = Arc::new(TextureImage { width, height, texels });
+
+    cache.insert(key, CachedImage { modified, image: Arc::downgrade(&image) });
+    Ok(image)
+}
+
+fn default_srgb() -> bool {
+    true
+}
+
+fn default_scale() -> Vector2<f64> {
+    Vector2::new(1.0, 1.0)
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "ImageTextureConfig")]
+pub struct ImageTexture {
+    pub path: String,
+    pub wrap: WrapMode,
+    pub filter: TextureFilter,
+    // color maps are usually stored in sRGB, data maps (bump, opacity...) should set this to false
+    pub srgb: bool,
+    pub scale: Vector2<f64>,
+    pub offset: Vector2<f64>,
+    #[serde(skip)]
+    image: Arc<TextureImage>,
+}
+
+#[derive(Deserialize)]
+struct ImageTextureConfig {
+    path: String,
+    #[serde(default)]
+    wrap: WrapMode,
+    #[serde(default)]
+    filter: TextureFilter,
+    #[serde(default = "default_srgb")]
+    srgb: bool,
+    #[serde(default = "default_scale")]
+    scale: Vector2<f64>,
+    #[serde(default)]
+    offset: Vector2<f64>,
+}
+
+impl TryFrom<ImageTextureConfig> for ImageTexture {
+    type Error = String;
+
+    fn try_from(config: ImageTextureConfig) -> Result<Self, Self::Error> {
+        ImageTexture::new(config.path, config.wrap, config.filter, config.srgb, config.scale, config.offset)
+    }
+}
+
+impl ImageTexture {
+    pub fn new(
+        path: String,
+        wrap: WrapMode,
+        filter: TextureFilter,
+        srgb: bool,
+        scale: Vector2<f64>,
+        offset: Vector2<f64>
+    ) -> Result<ImageTexture, String> {
+        let image = load_texture_image(Path::new(&path), srgb)?;
+        Ok(ImageTexture {path, wrap, filter, srgb, scale, offset, image})
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Vector3<f64> {
+        self.image.texel(self.wrap.apply(x, self.image.width), self.wrap.apply(y, self.image.height))
+    }
+
+    // u goes left to right and v top to bottom of the image
+    pub fn lookup(&self, uv: &Vector2<f64>) -> Vector3<f64> {
+        let uv = uv.component_mul(&self.scale) + self.offset;
+        let x = uv.x * self.image.width as f64;
+        let y = uv.y * self.image.height as f64;
+
+        match self.filter {
+            TextureFilter::Nearest => self.texel(x.floor() as i64, y.floor() as i64),
+            TextureFilter::Bilinear => {
+                // texel centers are at half-integer coordinates
+                let x = x - 0.5;
+                let y = y - 0.5;
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+
+                let top = self.texel(x0, y0) * (1.0 - fx) + self.texel(x0 + 1, y0) * fx;
+                let bottom = self.texel(x0, y0 + 1) * (1.0 - fx) + self.texel(x0 + 1, y0 + 1) * fx;
+                top * (1.0 - fy) + bottom * fy
+            }
+        }
+    }
+}
+
+#[typetag::serde]
+impl Texture for ImageTexture {
+    fn evaluate (&self, intersection: &Intersection) -> Vector3<f64> {
+        self.lookup(&intersection.uv)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgb};
+
+    const EPSILON: f64 = 1e-6;
+
+    // 2x1 image: a black texel on the left, a white one on the right
+    fn write_test_image(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("rust-tracer-{}-{}.png", name, std::process::id()));
+        let imgbuf = ImageBuffer::<Rgb<u8>, Vec<u8>>::from_vec(2, 1, vec![0, 0, 0, 255, 255, 255]).unwrap();
+        imgbuf.save(&path).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn load(path: &str, wrap: WrapMode, filter: TextureFilter, srgb: bool) -> Result<ImageTexture, String> {
+        ImageTexture::new(path.to_string(), wrap, filter, srgb, default_scale(), Vector2::zeros())
+    }
+
+    #[test]
+    fn test_wrap_modes() {
+        assert_eq!(WrapMode::Repeat.apply(-1, 4), 3);
+        assert_eq!(WrapMode::Repeat.apply(5, 4), 1);
+        assert_eq!(WrapMode::Clamp.apply(-1, 4), 0);
+        assert_eq!(WrapMode::Clamp.apply(5, 4), 3);
+        assert_eq!(WrapMode::Mirror.apply(4, 4), 3);
+        assert_eq!(WrapMode::Mirror.apply(-1, 4), 0);
+    }
+
+    #[test]
+    fn test_srgb_decoding() {
+        let path = write_test_image("srgb");
+        let texture = load(&path, WrapMode::Clamp, TextureFilter::Nearest, true).unwrap();
+
+        assert!(texture.lookup(&Vector2::new(0.25, 0.5)).norm() < EPSILON);
+        assert!((texture.lookup(&Vector2::new(0.75, 0.5)).x - 1.0).abs() < EPSILON);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_bilinear_filtering_interpolates_between_texels() {
+        let path = write_test_image("bilinear");
+        let texture = load(&path, WrapMode::Clamp, TextureFilter::Bilinear, false).unwrap();
+
+        // halfway between the two texel centers
+        assert!((texture.lookup(&Vector2::new(0.5, 0.5)).x - 0.5).abs() < EPSILON);
+        // clamped past the last texel center
+        assert!((texture.lookup(&Vector2::new(1.0, 0.5)).x - 1.0).abs() < EPSILON);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_uv_scale_and_offset() {
+        let path = write_test_image("transform");
+        let mut texture = load(&path, WrapMode::Repeat, TextureFilter::Nearest, false).unwrap();
+        texture.offset = Vector2::new(0.5, 0.0);
+
+        assert!((texture.lookup(&Vector2::new(0.25, 0.5)).x - 1.0).abs() < EPSILON);
+
+        texture.offset = Vector2::zeros();
+        texture.scale = Vector2::new(2.0, 1.0);
+        // u = 0.375 * 2 lands in the white texel
+        assert!((texture.lookup(&Vector2::new(0.375, 0.5)).x - 1.0).abs() < EPSILON);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_textures_share_decoded_image() {
+        let path = write_test_image("cache");
+        let first = load(&path, WrapMode::Repeat, TextureFilter::Bilinear, true).unwrap();
+        let second = load(&path, WrapMode::Clamp, TextureFilter::Nearest, true).unwrap();
+
+        assert!(Arc::ptr_eq(&first.image, &second.image));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_deserialize_with_defaults() {
+        let path = write_test_image("yaml");
+        let texture: Box<dyn Texture> = serde_yaml::from_str(&format!("type: ImageTexture\npath: {}\nscale: [2.0, 1.0]", path)).unwrap();
+        let sphere = crate::raytracer::shapes::Sphere::new(Vector3::zeros(), 1.0, Vector3::zeros(), Vector3::zeros(), 0.0, 0.0);
+        let hit = Intersection::new(Vector3::zeros(), 1.0, Vector3::y(), &sphere)
+            .with_surface_frame(Vector2::new(0.375, 0.5), Vector3::x(), Vector3::z(), true);
+
+        assert!((texture.evaluate(&hit).x - 1.0).abs() < EPSILON);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_missing_file_is_an_error() {
+        assert!(load("does/not/exist.png", WrapMode::Repeat, TextureFilter::Bilinear, true).is_err());
+    }
+}
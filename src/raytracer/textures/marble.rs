@@ -0,0 +1,73 @@
+use serde::{Serialize, Deserialize};
+
+use super::texture::*;
+use super::perlin::turbulence;
+use super::noise_texture::default_octaves;
+use crate::raytracer::Intersection;
+use crate::raytracer::material::ColorParameter;
+
+use na::Vector3;
+
+fn default_distortion() -> f64 {
+    5.0
+}
+
+// veins are stripes along x bent by turbulence
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Marble {
+    pub base: ColorParameter,
+    pub vein: ColorParameter,
+    #[serde(default = "default_octaves")]
+    pub octaves: u32,
+    #[serde(default = "default_distortion")]
+    pub distortion: f64,
+    #[serde(flatten)]
+    pub mapping: TextureMapping,
+}
+
+impl Marble {
+    fn blend_factor(&self, point: &Vector3<f64>) -> f64 {
+        0.5 + 0.5 * (point.x + self.distortion * turbulence(point, self.octaves)).sin()
+    }
+}
+
+#[typetag::serde]
+impl Texture for Marble {
+    fn evaluate (&self, intersection: &Intersection) -> Vector3<f64> {
+        let t = self.blend_factor(&self.mapping.point(intersection));
+        self.base.evaluate(intersection).lerp(&self.vein.evaluate(intersection), t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f64 = 1e-10;
+
+    fn create_marble(distortion: f64) -> Marble {
+        Marble {
+            base: ColorParameter::Constant(Vector3::new(1.0, 1.0, 1.0)),
+            vein: ColorParameter::Constant(Vector3::zeros()),
+            octaves: default_octaves(),
+            distortion,
+            mapping: TextureMapping::default(),
+        }
+    }
+
+    #[test]
+    fn test_undistorted_marble_is_a_sine_stripe() {
+        let marble = create_marble(0.0);
+        let x = std::f64::consts::FRAC_PI_2;
+        assert!((marble.blend_factor(&Vector3::new(x, 0.3, 0.7)) - 1.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_marble_blend_factor_in_unit_range() {
+        let marble = create_marble(default_distortion());
+        for i in 0..200 {
+            let t = marble.blend_factor(&Vector3::new(i as f64 * 0.11, i as f64 * 0.05, 0.2));
+            assert!((0.0..=1.0).contains(&t));
+        }
+    }
+}
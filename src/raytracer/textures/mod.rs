@@ -0,0 +1,12 @@
+pub mod texture;
+pub mod image_texture;
+pub mod perlin;
+pub mod checkerboard;
+pub mod gradient;
+pub mod noise_texture;
+pub mod marble;
+pub mod wood;
+pub mod fractal;
+
+// concrete textures are only referenced by name in scene files, through typetag
+pub use texture::*;
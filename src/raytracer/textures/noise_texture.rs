@@ -0,0 +1,75 @@
+use serde::{Serialize, Deserialize};
+
+use super::texture::*;
+use super::perlin::{fbm, turbulence};
+use crate::raytracer::Intersection;
+use crate::raytracer::material::ColorParameter;
+
+use na::Vector3;
+
+pub fn default_octaves() -> u32 {
+    4
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Noise {
+    pub low: ColorParameter,
+    pub high: ColorParameter,
+    #[serde(default = "default_octaves")]
+    pub octaves: u32,
+    // sums absolute noise values instead of the signed fbm
+    #[serde(default)]
+    pub turbulence: bool,
+    #[serde(flatten)]
+    pub mapping: TextureMapping,
+}
+
+impl Noise {
+    fn blend_factor(&self, point: &Vector3<f64>) -> f64 {
+        let value = if self.turbulence {
+            turbulence(point, self.octaves)
+        } else {
+            0.5 + 0.5 * fbm(point, self.octaves)
+        };
+        value.clamp(0.0, 1.0)
+    }
+}
+
+#[typetag::serde]
+impl Texture for Noise {
+    fn evaluate (&self, intersection: &Intersection) -> Vector3<f64> {
+        let t = self.blend_factor(&self.mapping.point(intersection));
+        self.low.evaluate(intersection).lerp(&self.high.evaluate(intersection), t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_noise(turbulence: bool) -> Noise {
+        Noise {
+            low: ColorParameter::Constant(Vector3::zeros()),
+            high: ColorParameter::Constant(Vector3::new(1.0, 1.0, 1.0)),
+            octaves: default_octaves(),
+            turbulence,
+            mapping: TextureMapping::default(),
+        }
+    }
+
+    #[test]
+    fn test_noise_blend_factor_in_unit_range() {
+        for noise in [create_noise(false), create_noise(true)] {
+            for i in 0..200 {
+                let t = noise.blend_factor(&Vector3::new(i as f64 * 0.13, i as f64 * 0.07, 0.3));
+                assert!((0.0..=1.0).contains(&t));
+            }
+        }
+    }
+
+    #[test]
+    fn test_fbm_noise_is_centered_on_lattice_points() {
+        let noise = create_noise(false);
+        assert!((noise.blend_factor(&Vector3::zeros()) - 0.5).abs() < 1e-10);
+    }
+}
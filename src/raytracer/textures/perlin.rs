@@ -0,0 +1,141 @@
+use std::sync::OnceLock;
+
+use na::Vector3;
+
+// Ken Perlin's improved noise, with the permutation table shuffled from a fixed seed
+// so every render of a scene produces the same pattern
+static PERMUTATION: OnceLock<[usize; 512]> = OnceLock::new();
+
+fn permutation() -> &'static [usize; 512] {
+    PERMUTATION.get_or_init(|| {
+        let mut table: [usize; 256] = [0; 256];
+        for (i, value) in table.iter_mut().enumerate() {
+            *value = i;
+        }
+        // Fisher-Yates shuffle driven by a xorshift generator
+        let mut state: u32 = 0x9E37_79B9;
+        for i in (1..256).rev() {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            table.swap(i, state as usize % (i + 1));
+        }
+        let mut doubled = [0; 512];
+        for (i, value) in doubled.iter_mut().enumerate() {
+            *value = table[i % 256];
+        }
+        doubled
+    })
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+// dot product between the offset and one of 12 gradient directions picked by the hash
+fn grad(hash: usize, x: f64, y: f64, z: f64) -> f64 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 { y } else if h == 12 || h == 14 { x } else { z };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+// gradient noise in roughly [-1, 1], zero on every integer lattice point
+pub fn perlin_noise(point: &Vector3<f64>) -> f64 {
+    let p = permutation();
+
+    let (xf, yf, zf) = (point.x.floor(), point.y.floor(), point.z.floor());
+    let (xi, yi, zi) = ((xf as i64 & 255) as usize, (yf as i64 & 255) as usize, (zf as i64 & 255) as usize);
+    let (x, y, z) = (point.x - xf, point.y - yf, point.z - zf);
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+
+    let a = p[xi] + yi;
+    let aa = p[a] + zi;
+    let ab = p[a + 1] + zi;
+    let b = p[xi + 1] + yi;
+    let ba = p[b] + zi;
+    let bb = p[b + 1] + zi;
+
+    lerp(w,
+        lerp(v,
+            lerp(u, grad(p[aa], x, y, z), grad(p[ba], x - 1.0, y, z)),
+            lerp(u, grad(p[ab], x, y - 1.0, z), grad(p[bb], x - 1.0, y - 1.0, z))),
+        lerp(v,
+            lerp(u, grad(p[aa + 1], x, y, z - 1.0), grad(p[ba + 1], x - 1.0, y, z - 1.0)),
+            lerp(u, grad(p[ab + 1], x, y - 1.0, z - 1.0), grad(p[bb + 1], x - 1.0, y - 1.0, z - 1.0))))
+}
+
+// fractional brownian motion: octaves of noise, each with twice the frequency and half the amplitude
+pub fn fbm(point: &Vector3<f64>, octaves: u32) -> f64 {
+    let mut sum = 0.0;
+    let mut amplitude = 1.0;
+    let mut frequency = 1.0;
+    for _ in 0..octaves {
+        sum += amplitude * perlin_noise(&(point * frequency));
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    sum
+}
+
+// same as fbm but summing absolute values, which gives creases along the noise zero crossings
+pub fn turbulence(point: &Vector3<f64>, octaves: u32) -> f64 {
+    let mut sum = 0.0;
+    let mut amplitude = 1.0;
+    let mut frequency = 1.0;
+    for _ in 0..octaves {
+        sum += amplitude * perlin_noise(&(point * frequency)).abs();
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f64 = 1e-10;
+
+    #[test]
+    fn test_noise_is_zero_on_lattice_points() {
+        assert!(perlin_noise(&Vector3::new(0.0, 0.0, 0.0)).abs() < EPSILON);
+        assert!(perlin_noise(&Vector3::new(3.0, -2.0, 7.0)).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_noise_is_bounded_and_varies() {
+        let values: Vec<f64> = (0..1000)
+            .map(|i| perlin_noise(&Vector3::new(i as f64 * 0.137, i as f64 * 0.071, i as f64 * 0.029)))
+            .collect();
+
+        assert!(values.iter().all(|value| value.abs() <= 1.1));
+        let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+        let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        assert!(max - min > 0.5);
+    }
+
+    #[test]
+    fn test_noise_is_deterministic() {
+        let point = Vector3::new(1.3, 2.7, -0.4);
+        assert_eq!(perlin_noise(&point), perlin_noise(&point));
+    }
+
+    #[test]
+    fn test_turbulence_is_positive() {
+        for i in 0..100 {
+            let point = Vector3::new(i as f64 * 0.31, 0.5, i as f64 * 0.17);
+            assert!(turbulence(&point, 4) >= 0.0);
+        }
+    }
+
+    #[test]
+    fn test_single_octave_fbm_is_noise() {
+        let point = Vector3::new(0.3, 0.6, 0.9);
+        assert!((fbm(&point, 1) - perlin_noise(&point)).abs() < EPSILON);
+    }
+}
@@ -0,0 +1,95 @@
+use serde::{Serialize, Deserialize};
+
+use crate::raytracer::Intersection;
+
+use na::Vector3;
+
+#[typetag::serde(tag = "type")]
+pub trait Texture : Sync + Send + std::fmt::Debug
+{
+    // returns the linear rgb value of the texture at the intersection
+    fn evaluate (&self, intersection: &Intersection) -> Vector3<f64>;
+}
+
+// coordinates procedural textures are evaluated in
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Default)]
+pub enum TextureSpace {
+    // (u, v, 0) surface coordinates
+    Uv,
+    // hit location relative to the shape, the pattern moves with the shape
+    #[default]
+    Object,
+    // hit location in scene coordinates
+    World,
+}
+
+fn default_mapping_scale() -> f64 {
+    1.0
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TextureMapping {
+    #[serde(default)]
+    pub space: TextureSpace,
+    // frequency of the pattern, applied before the offset
+    #[serde(default = "default_mapping_scale")]
+    pub scale: f64,
+    #[serde(default)]
+    pub offset: Vector3<f64>,
+}
+
+impl Default for TextureMapping {
+    fn default() -> TextureMapping {
+        TextureMapping { space: TextureSpace::default(), scale: default_mapping_scale(), offset: Vector3::zeros() }
+    }
+}
+
+impl TextureMapping {
+    pub fn point(&self, intersection: &Intersection) -> Vector3<f64> {
+        let point = match self.space {
+            TextureSpace::Uv => Vector3::new(intersection.uv.x, intersection.uv.y, 0.0),
+            TextureSpace::Object => intersection.shape.to_object_space_at(&intersection.location, intersection.time),
+            TextureSpace::World => intersection.location,
+        };
+        point * self.scale + self.offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raytracer::shapes::Sphere;
+    use na::Vector2;
+
+    const EPSILON: f64 = 1e-10;
+
+    fn create_hit(sphere: &Sphere) -> Intersection<'_> {
+        Intersection::new(Vector3::new(1.0, 3.0, 2.0), 1.0, Vector3::x(), sphere)
+            .with_surface_frame(Vector2::new(0.25, 0.5), Vector3::z(), Vector3::y(), true)
+    }
+
+    #[test]
+    fn test_mapping_spaces() {
+        let sphere = Sphere::new(Vector3::new(0.0, 3.0, 2.0), 1.0, Vector3::zeros(), Vector3::zeros(), 0.0, 0.0);
+        let hit = create_hit(&sphere);
+
+        let mut mapping = TextureMapping::default();
+        assert!((mapping.point(&hit) - Vector3::new(1.0, 0.0, 0.0)).norm() < EPSILON);
+
+        mapping.space = TextureSpace::World;
+        assert!((mapping.point(&hit) - Vector3::new(1.0, 3.0, 2.0)).norm() < EPSILON);
+
+        mapping.space = TextureSpace::Uv;
+        assert!((mapping.point(&hit) - Vector3::new(0.25, 0.5, 0.0)).norm() < EPSILON);
+    }
+
+    #[test]
+    fn test_mapping_scale_and_offset() {
+        let sphere = Sphere::new(Vector3::new(0.0, 3.0, 2.0), 1.0, Vector3::zeros(), Vector3::zeros(), 0.0, 0.0);
+        let hit = create_hit(&sphere);
+        let mapping: TextureMapping = serde_yaml::from_str("scale: 4.0\noffset: [0.0, 1.0, 0.0]").unwrap();
+
+        assert_eq!(mapping.space, TextureSpace::Object);
+        assert!((mapping.point(&hit) - Vector3::new(4.0, 1.0, 0.0)).norm() < EPSILON);
+    }
+}
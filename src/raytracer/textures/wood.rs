@@ -0,0 +1,84 @@
+use serde::{Serialize, Deserialize};
+
+use super::texture::*;
+use super::perlin::fbm;
+use super::noise_texture::default_octaves;
+use crate::raytracer::Intersection;
+use crate::raytracer::material::ColorParameter;
+
+use na::Vector3;
+
+fn default_rings() -> f64 {
+    4.0
+}
+
+fn default_distortion() -> f64 {
+    0.1
+}
+
+// concentric rings around the y axis, made irregular with fbm
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Wood {
+    pub light: ColorParameter,
+    pub dark: ColorParameter,
+    // number of rings per unit of distance from the axis
+    #[serde(default = "default_rings")]
+    pub rings: f64,
+    #[serde(default = "default_octaves")]
+    pub octaves: u32,
+    #[serde(default = "default_distortion")]
+    pub distortion: f64,
+    #[serde(flatten)]
+    pub mapping: TextureMapping,
+}
+
+impl Wood {
+    fn blend_factor(&self, point: &Vector3<f64>) -> f64 {
+        let radius = (point.x.powi(2) + point.z.powi(2)).sqrt() + self.distortion * fbm(point, self.octaves);
+        (radius * self.rings).rem_euclid(1.0)
+    }
+}
+
+#[typetag::serde]
+impl Texture for Wood {
+    fn evaluate (&self, intersection: &Intersection) -> Vector3<f64> {
+        let t = self.blend_factor(&self.mapping.point(intersection));
+        self.light.evaluate(intersection).lerp(&self.dark.evaluate(intersection), t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f64 = 1e-10;
+
+    fn create_wood(distortion: f64) -> Wood {
+        Wood {
+            light: ColorParameter::Constant(Vector3::new(0.8, 0.6, 0.4)),
+            dark: ColorParameter::Constant(Vector3::new(0.4, 0.2, 0.1)),
+            rings: default_rings(),
+            octaves: default_octaves(),
+            distortion,
+            mapping: TextureMapping::default(),
+        }
+    }
+
+    #[test]
+    fn test_rings_are_concentric_around_y() {
+        let wood = create_wood(0.0);
+        let on_x = wood.blend_factor(&Vector3::new(0.3, 0.0, 0.0));
+        let on_z = wood.blend_factor(&Vector3::new(0.0, 2.0, 0.3));
+        assert!((on_x - on_z).abs() < EPSILON);
+        assert!((on_x - 0.2).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_wood_blend_factor_in_unit_range() {
+        let wood = create_wood(default_distortion());
+        for i in 0..200 {
+            let t = wood.blend_factor(&Vector3::new(i as f64 * 0.03, 0.4, -(i as f64) * 0.02));
+            assert!((0.0..1.0).contains(&t));
+        }
+    }
+}
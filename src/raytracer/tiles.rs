@@ -0,0 +1,153 @@
+use serde::{Serialize, Deserialize};
+use std::str::FromStr;
+
+fn default_tile_size() -> u32 {
+    16
+}
+
+// order in which the tiles of a pass are handed to the threads
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Default)]
+pub enum TileOrder {
+    // rows of tiles from the top
+    Scanline,
+    // rings of tiles going out from the center of the image, where the subject usually is
+    #[default]
+    Spiral,
+    // Hilbert curve, consecutive tiles are always neighbours
+    Hilbert,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TileSettings {
+    // side of the square tiles in pixels, tiles on the right and bottom edges may be smaller
+    #[serde(default = "default_tile_size")]
+    pub size: u32,
+    #[serde(default)]
+    pub order: TileOrder,
+}
+
+impl Default for TileSettings {
+    fn default() -> Self {
+        TileSettings { size: default_tile_size(), order: TileOrder::default() }
+    }
+}
+
+// rectangle of pixels, relative to the film it is in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl TileSettings {
+    // tiles covering a width x height image, in the configured order
+    pub fn tiles(&self, width: u32, height: u32) -> Vec<Tile> {
+        let size = self.size.max(1);
+        let (columns, rows) = (width.div_ceil(size), height.div_ceil(size));
+        let mut tiles: Vec<(u32, u32)> = (0..rows).flat_map(|row| (0..columns).map(move |column| (column, row))).collect();
+
+        match self.order {
+            TileOrder::Scanline => {}
+            TileOrder::Spiral => {
+                // ring index around the central tile, then angle inside the ring
+                let center = ((columns as f64 - 1.0) / 2.0, (rows as f64 - 1.0) / 2.0);
+                let ring = |tile: &(u32, u32)| (tile.0 as f64 - center.0).abs().max((tile.1 as f64 - center.1).abs()).ceil() as u32;
+                let angle = |tile: &(u32, u32)| (tile.1 as f64 - center.1).atan2(tile.0 as f64 - center.0);
+                tiles.sort_by(|a, b| ring(a).cmp(&ring(b)).then(angle(a).partial_cmp(&angle(b)).unwrap()));
+            }
+            TileOrder::Hilbert => {
+                let side = columns.max(rows).next_power_of_two();
+                tiles.sort_by_key(|(column, row)| hilbert_index(side, *column, *row));
+            }
+        }
+
+        tiles.into_iter()
+            .map(|(column, row)| Tile {
+                x: column * size,
+                y: row * size,
+                width: size.min(width - column * size),
+                height: size.min(height - row * size),
+            })
+            .collect()
+    }
+}
+
+// position of the cell (x, y) along the Hilbert curve filling a side x side grid, side being a power of two
+pub fn hilbert_index(side: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut index = 0;
+    let mut half = side / 2;
+    while half > 0 {
+        let (rx, ry) = ((x & half > 0) as u32, (y & half > 0) as u32);
+        index += half as u64 * half as u64 * ((3 * rx) ^ ry) as u64;
+        // rotate the quadrant so that the curve inside it starts and ends next to its neighbours
+        if ry == 0 {
+            if rx == 1 {
+                x = side - 1 - x;
+                y = side - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        half /= 2;
+    }
+    index
+}
+
+// command line names: scanline, spiral, hilbert
+impl FromStr for TileOrder {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "scanline" => Ok(TileOrder::Scanline),
+            "spiral" => Ok(TileOrder::Spiral),
+            "hilbert" => Ok(TileOrder::Hilbert),
+            _ => Err(format!("unknown tile order '{}', expected scanline, spiral or hilbert", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn covered_pixels(tiles: &[Tile]) -> Vec<(u32, u32)> {
+        let mut pixels: Vec<(u32, u32)> = tiles.iter()
+            .flat_map(|tile| (tile.y..tile.y + tile.height).flat_map(move |y| (tile.x..tile.x + tile.width).map(move |x| (x, y))))
+            .collect();
+        pixels.sort();
+        pixels
+    }
+
+    #[test]
+    fn test_tiles_cover_every_pixel_once() {
+        let all_pixels: Vec<(u32, u32)> = (0..37).flat_map(|x| (0..21).map(move |y| (x, y))).collect();
+        for order in [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+            let tiles = TileSettings { size: 8, order }.tiles(37, 21);
+            assert_eq!(tiles.len(), 5 * 3);
+            assert_eq!(covered_pixels(&tiles), all_pixels);
+        }
+    }
+
+    #[test]
+    fn test_spiral_starts_at_the_center() {
+        let tiles = TileSettings { size: 10, order: TileOrder::Spiral }.tiles(50, 30);
+        assert_eq!((tiles[0].x, tiles[0].y), (20, 10));
+    }
+
+    #[test]
+    fn test_hilbert_tiles_are_neighbours() {
+        let tiles = TileSettings { size: 4, order: TileOrder::Hilbert }.tiles(32, 32);
+        for pair in tiles.windows(2) {
+            let distance = (pair[0].x as i64 - pair[1].x as i64).abs() + (pair[0].y as i64 - pair[1].y as i64).abs();
+            assert_eq!(distance, 4);
+        }
+    }
+
+    #[test]
+    fn test_tile_order_from_str() {
+        assert_eq!("Hilbert".parse::<TileOrder>(), Ok(TileOrder::Hilbert));
+        assert!("zigzag".parse::<TileOrder>().is_err());
+    }
+}
@@ -1,25 +1,21 @@
 
-use rand::prelude::*;
-use rand_distr::StandardNormal;
 use na::Vector3;
 
-pub fn uniform_sampling_hemisphere() -> Vector3<f64> {
-    // generate random vectors uniformly on "normalized" hemisphere: 
+use super::samplers::Sampler;
+
+pub fn uniform_sampling_hemisphere(sampler: &mut dyn Sampler) -> Vector3<f64> {
+    // generate random vectors uniformly on "normalized" hemisphere:
     // - centered on origin
     // - norm(x, y, z) = 1
-    // - x, y, z >= 0
-    // multivariate standard normal distribution is spherically symmetric
-    // so we get uniform distribution if we sample x y z with standard normal distribution
-    // and normalize the generated vector
-    let x: f64 = thread_rng().sample(StandardNormal);
-    let z: f64 = thread_rng().sample(StandardNormal);
-
-    // we flip y if negative to get hemisphere
-    let rand_value: f64 = thread_rng().sample(StandardNormal);
-    let y = if rand_value < 0.0 { -rand_value } else { rand_value };
-
-    let length = (x.powi(2) + y.powi(2) + z.powi(2)).sqrt();
-    Vector3::<f64>::new(x / length, y / length, z / length)
+    // - y >= 0
+    // the area of a spherical zone only depends on its height,
+    // so we get uniform distribution if y is uniform in [0, 1] and the azimuth uniform in [0, 2 pi]
+    let (xi_1, xi_2) = sampler.next_2d();
+    let y = xi_1;
+    let radius = (1.0 - y * y).max(0.0).sqrt();
+    let phi = 2.0 * std::f64::consts::PI * xi_2;
+
+    Vector3::<f64>::new(radius * phi.cos(), y, radius * phi.sin())
 }
 
 pub fn create_coordinate_system_from_up_vector(up_vector: &Vector3<f64>) -> [Vector3<f64>; 3] {
@@ -35,28 +31,49 @@ pub fn create_coordinate_system_from_up_vector(up_vector: &Vector3<f64>) -> [Vec
     [nt, up_vector.clone(), nb]
 }
 
+// inverse of the sRGB transfer function, maps an encoded value in [0, 1] to linear intensity
+pub fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::raytracer::samplers::independent::IndependentSampler;
 
     const EPSILON: f64 = 1e-10;
 
     #[test]
     fn test_uniform_sampling_hemisphere_returns_unit_vector() {
+        let mut sampler = IndependentSampler::new(0, 0);
         for _ in 0..100 {
-            let v = uniform_sampling_hemisphere();
+            let v = uniform_sampling_hemisphere(&mut sampler);
             assert!((v.norm() - 1.0).abs() < EPSILON, "Vector should be normalized");
         }
     }
 
     #[test]
     fn test_uniform_sampling_hemisphere_y_is_non_negative() {
+        let mut sampler = IndependentSampler::new(0, 0);
         for _ in 0..100 {
-            let v = uniform_sampling_hemisphere();
+            let v = uniform_sampling_hemisphere(&mut sampler);
             assert!(v.y >= 0.0, "Y component should be non-negative for hemisphere");
         }
     }
 
+    #[test]
+    fn test_uniform_sampling_hemisphere_mean_height() {
+        // uniform on the hemisphere, the mean cosine with the pole is 1 / 2
+        let mut sampler = IndependentSampler::new(0, 0);
+        let samples = 10000;
+        let mean_y = (0..samples).map(|_| uniform_sampling_hemisphere(&mut sampler).y).sum::<f64>() / samples as f64;
+        assert!((mean_y - 0.5).abs() < 0.01);
+    }
+
     #[test]
     fn test_coordinate_system_vectors_are_orthogonal() {
         let up = Vector3::new(0.0, 1.0, 0.0);
@@ -105,4 +122,12 @@ mod tests {
         
         assert!((n - up).norm() < EPSILON, "Middle vector should equal input up vector");
     }
+
+    #[test]
+    fn test_srgb_to_linear() {
+        assert!(srgb_to_linear(0.0).abs() < EPSILON);
+        assert!((srgb_to_linear(1.0) - 1.0).abs() < EPSILON);
+        // mid grey in sRGB is about 21% linear intensity
+        assert!((srgb_to_linear(0.5) - 0.214).abs() < 1e-3);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,223 @@
+use std::env;
+use std::io::{self, Write};
+
+use crate::raytracer::film::Film;
+
+use na::Vector3;
+use terminal_size::{terminal_size, Height, Width};
+
+// terminal size when stdout is not a terminal and COLUMNS and LINES are not exported
+const DEFAULT_COLUMNS: u32 = 80;
+const DEFAULT_LINES: u32 = 24;
+// usual size of a terminal cell in pixels, for graphics protocols that draw real pixels
+const CELL_WIDTH: u32 = 8;
+const CELL_HEIGHT: u32 = 16;
+// the kitty graphics protocol takes base64 payloads in chunks of at most this size
+const KITTY_CHUNK_SIZE: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TerminalGraphics {
+    // "▀" characters with the top pixel as foreground and the bottom one as background, in 24 bit color
+    HalfBlocks,
+    // kitty graphics protocol, one image pixel per screen pixel
+    Kitty,
+}
+
+impl TerminalGraphics {
+    // kitty and terminals that announce themselves as kitty get real pixels, others get half blocks
+    pub fn detect() -> TerminalGraphics {
+        let kitty = env::var("KITTY_WINDOW_ID").is_ok() || env::var("TERM").is_ok_and(|term| term.contains("kitty"));
+        if kitty { TerminalGraphics::Kitty } else { TerminalGraphics::HalfBlocks }
+    }
+
+    // largest size with the aspect ratio of the frame that fits in the terminal, keeping a line for the prompt
+    pub fn preview_size(&self, frame_width: u32, frame_height: u32) -> (u32, u32) {
+        let (columns, lines) = terminal_cells();
+        let lines = lines.max(2);
+        let (max_width, max_height) = match self {
+            TerminalGraphics::HalfBlocks => (columns, (lines - 1) * 2),
+            TerminalGraphics::Kitty => (columns * CELL_WIDTH, (lines - 1) * CELL_HEIGHT),
+        };
+        fit(frame_width, frame_height, max_width, max_height)
+    }
+
+    // escape sequences drawing the film, scaled down to fit the terminal, from the top left corner of the screen
+    pub fn frame(&self, film: &Film) -> String {
+        let (width, height) = self.preview_size(film.width, film.height);
+        let preview = downsample(film, width, height);
+        match self {
+            TerminalGraphics::HalfBlocks => half_block_frame(&preview),
+            TerminalGraphics::Kitty => kitty_frame(&preview),
+        }
+    }
+
+    pub fn draw(&self, film: &Film) -> io::Result<()> {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(self.frame(film).as_bytes())?;
+        stdout.flush()
+    }
+}
+
+// size of the terminal on stdout in cells, from COLUMNS and LINES when stdout is redirected
+fn terminal_cells() -> (u32, u32) {
+    if let Some((Width(columns), Height(lines))) = terminal_size() {
+        return (columns as u32, lines as u32);
+    }
+    let size_variable = |name: &str, default: u32| env::var(name).ok().and_then(|value| value.parse().ok()).unwrap_or(default);
+    (size_variable("COLUMNS", DEFAULT_COLUMNS), size_variable("LINES", DEFAULT_LINES))
+}
+
+// clears the screen before the first frame, frames are then drawn over each other
+pub fn clear_screen() {
+    print!("\x1b[2J");
+}
+
+fn fit(width: u32, height: u32, max_width: u32, max_height: u32) -> (u32, u32) {
+    let scale = (max_width as f64 / width as f64).min(max_height as f64 / height as f64).min(1.0);
+    (((width as f64 * scale) as u32).max(2), ((height as f64 * scale) as u32).max(2))
+}
+
+// film of the given size whose pixels hold the mean color of the film pixels they cover
+fn downsample(film: &Film, width: u32, height: u32) -> Film {
+    // source pixels [start, end) covered by a preview pixel, at least one
+    let span = |index: u32, size: u32, source_size: u32| {
+        let start = (index as u64 * source_size as u64 / size as u64) as u32;
+        let end = ((index as u64 + 1) * source_size as u64 / size as u64) as u32;
+        start..end.max(start + 1).min(source_size)
+    };
+    let mut preview = Film::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let (xs, ys) = (span(x, width, film.width), span(y, height, film.height));
+            let count = (xs.len() * ys.len()) as f64;
+            let sum = ys.flat_map(|sy| xs.clone().map(move |sx| (sx, sy)))
+                .fold(Vector3::zeros(), |sum, (sx, sy)| sum + film.pixels[(sx + sy * film.width) as usize].mean());
+            preview.pixels[(x + y * width) as usize].add_sample(&(sum / count));
+        }
+    }
+    preview
+}
+
+// 8 bit color of a pixel, clamped to [0, 1]; coarser than the 16 bit output image
+fn pixel_rgb(film: &Film, x: u32, y: u32) -> [u8; 3] {
+    let color = film.pixels[(x + y * film.width) as usize].mean();
+    [color.x, color.y, color.z].map(|channel| (channel.clamp(0.0, 1.0) * 255.0) as u8)
+}
+
+fn half_block_frame(film: &Film) -> String {
+    let mut frame = String::from("\x1b[H");
+    for y in (0..film.height).step_by(2) {
+        for x in 0..film.width {
+            let [r, g, b] = pixel_rgb(film, x, y);
+            frame.push_str(&format!("\x1b[38;2;{};{};{}m", r, g, b));
+            if y + 1 < film.height {
+                let [r, g, b] = pixel_rgb(film, x, y + 1);
+                frame.push_str(&format!("\x1b[48;2;{};{};{}m", r, g, b));
+            } else {
+                frame.push_str("\x1b[49m");
+            }
+            frame.push('▀');
+        }
+        frame.push_str("\x1b[0m\n");
+    }
+    frame
+}
+
+fn kitty_frame(film: &Film) -> String {
+    let rgb: Vec<u8> = (0..film.height)
+        .flat_map(|y| (0..film.width).flat_map(move |x| pixel_rgb(film, x, y)))
+        .collect();
+    let payload = base64(&rgb);
+    let chunks: Vec<&[u8]> = payload.as_bytes().chunks(KITTY_CHUNK_SIZE).collect();
+
+    // the previous frame is deleted so that frames do not pile up in the terminal's memory
+    let mut frame = String::from("\x1b[H\x1b_Ga=d\x1b\\");
+    for (index, chunk) in chunks.iter().enumerate() {
+        let more = (index + 1 < chunks.len()) as u8;
+        if index == 0 {
+            frame.push_str(&format!("\x1b_Ga=T,f=24,s={},v={},m={};", film.width, film.height, more));
+        } else {
+            frame.push_str(&format!("\x1b_Gm={};", more));
+        }
+        frame.push_str(std::str::from_utf8(chunk).unwrap());
+        frame.push_str("\x1b\\");
+    }
+    frame.push('\n');
+    frame
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for group in bytes.chunks(3) {
+        let bits = group.iter().enumerate().fold(0_u32, |bits, (index, byte)| bits | (*byte as u32) << (16 - 8 * index));
+        for index in 0..4 {
+            if index <= group.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * index) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_film() -> Film {
+        // red, green on the first row, blue, white on the second, black on the third
+        let mut film = Film::new(2, 3);
+        let colors = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0], [1.0, 1.0, 1.0], [0.0, 0.0, 0.0], [0.0, 0.0, 0.0]];
+        for (pixel, color) in film.pixels.iter_mut().zip(colors.iter()) {
+            pixel.add_sample(&Vector3::from(*color));
+        }
+        film
+    }
+
+    #[test]
+    fn test_half_blocks_pair_rows() {
+        let frame = half_block_frame(&create_film());
+        let lines: Vec<&str> = frame.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("\x1b[H\x1b[38;2;255;0;0m\x1b[48;2;0;0;255m▀"));
+        assert!(lines[0].contains("\x1b[38;2;0;255;0m\x1b[48;2;255;255;255m▀"));
+        // the last row has no pixel below it
+        assert!(lines[1].starts_with("\x1b[38;2;0;0;0m\x1b[49m▀"));
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn test_kitty_frame_sends_rgb() {
+        let frame = kitty_frame(&create_film());
+        assert!(frame.contains("\x1b_Ga=T,f=24,s=2,v=3,m=0;"));
+        assert!(frame.contains(&base64(&[255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255, 0, 0, 0, 0, 0, 0])));
+    }
+
+    #[test]
+    fn test_fit_keeps_aspect_ratio() {
+        assert_eq!(fit(640, 480, 80, 46), (61, 46));
+        assert_eq!(fit(1920, 1080, 80, 46), (80, 45));
+        // small frames are not enlarged
+        assert_eq!(fit(32, 24, 80, 46), (32, 24));
+    }
+
+    #[test]
+    fn test_downsample_averages_covered_pixels() {
+        let preview = downsample(&create_film(), 1, 2);
+        assert_eq!((preview.width, preview.height), (1, 2));
+        // the 2x3 film is split in rows 0 and 1-2
+        assert!((preview.pixels[0].mean() - Vector3::new(0.5, 0.5, 0.0)).norm() < 1e-10);
+        assert!((preview.pixels[1].mean() - Vector3::new(0.25, 0.25, 0.5)).norm() < 1e-10);
+        // a film that already fits is left as it is
+        assert_eq!(downsample(&create_film(), 2, 3).pixels, create_film().pixels);
+    }
+}
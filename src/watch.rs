@@ -0,0 +1,144 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::raytracer::film::Film;
+use crate::raytracer::Scene;
+
+// how often the files are checked
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+// files the scene yaml refers to: any string value naming an existing file, such as texture and grid paths,
+// which the loaders also resolve from the working directory
+pub fn asset_paths(scene_yaml: &str) -> Vec<PathBuf> {
+    fn collect(value: &serde_yaml::Value, paths: &mut Vec<PathBuf>) {
+        match value {
+            serde_yaml::Value::String(string) if Path::new(string).is_file() => paths.push(PathBuf::from(string)),
+            serde_yaml::Value::Sequence(values) => values.iter().for_each(|value| collect(value, paths)),
+            serde_yaml::Value::Mapping(mapping) => mapping.iter().for_each(|(_, value)| collect(value, paths)),
+            _ => {}
+        }
+    }
+
+    let mut paths = Vec::new();
+    if let Ok(value) = serde_yaml::from_str::<serde_yaml::Value>(scene_yaml) {
+        collect(&value, &mut paths);
+    }
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+// modification times of a set of files, polled since there is no file notification api in std
+pub struct FileWatcher {
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+impl FileWatcher {
+    pub fn new(paths: Vec<PathBuf>) -> FileWatcher {
+        FileWatcher { files: paths.into_iter().map(|path| { let time = modified(&path); (path, time) }).collect() }
+    }
+
+    // whether a file was modified, created or deleted since the last call
+    pub fn changed(&mut self) -> bool {
+        let mut changed = false;
+        for (path, time) in self.files.iter_mut() {
+            let current = modified(path);
+            if current != *time {
+                *time = current;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    pub fn wait_for_change(&mut self) {
+        while !self.changed() {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+// renders the scene forever, starting over whenever the scene file or one of its assets changes;
+// the first pass gives a one sample preview, the later ones refine it and save is called after each of them
+pub fn watch(scene_path: &Path, load_scene: impl Fn(&str) -> Result<Scene, String>, new_film: impl Fn() -> Film, seed: u64, save: impl Fn(&Film)) -> ! {
+    loop {
+        let scene_yaml = fs::read_to_string(scene_path).unwrap_or_default();
+        let mut paths = asset_paths(&scene_yaml);
+        paths.push(scene_path.to_path_buf());
+        let mut watcher = FileWatcher::new(paths);
+
+        match load_scene(&scene_yaml) {
+            Ok(scene) => {
+                println!("rendering {}", scene_path.display());
+                let mut film = new_film();
+                let edited = AtomicBool::new(false);
+                let finished = AtomicBool::new(false);
+                thread::scope(|scope| {
+                    // the files are polled beside the render, an edit cancels it in the middle of a pass
+                    scope.spawn(|| {
+                        while !finished.load(Ordering::SeqCst) {
+                            if watcher.changed() {
+                                edited.store(true, Ordering::SeqCst);
+                                return;
+                            }
+                            thread::sleep(POLL_INTERVAL);
+                        }
+                    });
+                    scene.render_progressive(&mut film, seed, &edited, |film, _| {
+                        save(film);
+                        true
+                    });
+                    finished.store(true, Ordering::SeqCst);
+                });
+                if edited.load(Ordering::SeqCst) {
+                    continue;
+                }
+                println!("done, waiting for changes to {}", scene_path.display());
+            }
+            Err(err) => eprintln!("{}, waiting for changes", err),
+        }
+        watcher.wait_for_change();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_temp_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("rust-tracer-watch-{}-{}", name, std::process::id()));
+        fs::write(&path, "data").unwrap();
+        path
+    }
+
+    #[test]
+    fn test_asset_paths_are_existing_files() {
+        let texture = write_temp_file("texture");
+        let scene = format!("shapes:\n  - texture:\n      path: {}\n      name: not a file\n    other: [{}, missing.png]\n", texture.display(), texture.display());
+        assert_eq!(asset_paths(&scene), vec![texture.clone()]);
+        assert!(asset_paths("not: [valid").is_empty());
+        fs::remove_file(texture).unwrap();
+    }
+
+    #[test]
+    fn test_watcher_sees_modifications() {
+        let path = write_temp_file("scene");
+        let mut watcher = FileWatcher::new(vec![path.clone()]);
+        assert!(!watcher.changed());
+
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+        assert!(watcher.changed());
+        assert!(!watcher.changed());
+
+        fs::remove_file(&path).unwrap();
+        assert!(watcher.changed());
+    }
+}